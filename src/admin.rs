@@ -0,0 +1,358 @@
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio_rusqlite::Connection;
+
+use crate::bottle_io::{self, Importer};
+use crate::delivery;
+use crate::moderation::{self, BottleNotFound};
+use crate::reactions;
+use crate::embedding_cache;
+use crate::db_schemas::VectorDBFromEnv;
+use crate::request_model::GeneralReponse;
+use crate::usage::{self, GroupBy};
+
+// Admin endpoints are only enabled when `ADMIN_TOKEN` is set,
+// callers pass it as `Authorization: Bearer <ADMIN_TOKEN>`.
+pub fn is_admin(req: &HttpRequest) -> bool {
+    let Ok(admin_token) = std::env::var("ADMIN_TOKEN") else {
+        return false;
+    };
+    if admin_token.is_empty() {
+        return false;
+    }
+
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token == admin_token)
+        .unwrap_or(false)
+}
+
+pub fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().json(GeneralReponse {
+        status: "Forbidden".to_string()
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub wallet: Option<String>,
+    #[serde(default)]
+    pub embeddings: bool,
+}
+
+#[get("/api/admin/bottles/export")]
+async fn export_bottles(req: HttpRequest, query: web::Query<ExportQuery>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let vcdb_from_env = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => vcdb_from_env,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+    };
+    let conn = match Connection::open(&vcdb_from_env.db_path).await {
+        Ok(conn) => conn,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+    };
+
+    let query = query.into_inner();
+    let wallet = query.wallet;
+    let with_embeddings = query.embeddings;
+
+    // page through the table, only one batch is held in memory at a time
+    let stream = futures::stream::try_unfold(0i64, move |after_rowid| {
+        let conn = conn.clone();
+        let wallet = wallet.clone();
+        async move {
+            let batch = bottle_io::export_batch(&conn, after_rowid, wallet, with_embeddings, bottle_io::EXPORT_BATCH)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            let Some((last_rowid, _)) = batch.last() else {
+                return Ok(None);
+            };
+            let last_rowid = *last_rowid;
+
+            let mut lines = String::new();
+            for (_, record) in batch {
+                lines.push_str(&serde_json::to_string(&record).map_err(actix_web::error::ErrorInternalServerError)?);
+                lines.push('\n');
+            }
+            Ok::<_, actix_web::Error>(Some((web::Bytes::from(lines), last_rowid)))
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(stream)
+}
+
+#[post("/api/admin/bottles/import")]
+async fn import_bottles(req: HttpRequest, mut payload: web::Payload) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let mut importer = match Importer::new().await {
+        Ok(importer) => importer,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+    };
+
+    // split the body into lines as it arrives
+    let mut buffer: Vec<u8> = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return HttpResponse::BadRequest().body(format!("Payload error: {}", e)),
+        };
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=pos).collect();
+            if let Err(e) = importer.push_line(&String::from_utf8_lossy(&line)).await {
+                return HttpResponse::InternalServerError().json(GeneralReponse {
+                    status: format!("Error after {} imported rows: {}", importer.stats.imported, e)
+                });
+            }
+        }
+    }
+
+    let result = match importer.push_line(&String::from_utf8_lossy(&buffer)).await {
+        Ok(()) => importer.finish().await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    pub group_by: GroupBy,
+    pub wallet: Option<String>,
+    // unix timestamps, `until` is exclusive
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+#[get("/api/admin/usage")]
+async fn usage_report(req: HttpRequest, query: web::Query<UsageQuery>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let query = query.into_inner();
+    let report = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => usage::aggregate(&vcdb_from_env.db_path, query.group_by, query.wallet, query.since, query.until).await,
+        Err(e) => Err(e),
+    };
+
+    match report {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+#[get("/api/admin/embedding_cache")]
+async fn embedding_cache_stats(req: HttpRequest) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let stats = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => embedding_cache::stats(&vcdb_from_env.db_path).await,
+        Err(e) => Err(e),
+    };
+
+    match stats {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BlockRequest {
+    pub wallet: String,
+    pub reason: Option<String>,
+}
+
+// blocked wallets get no more bottles delivered to their inbox
+#[post("/api/admin/blocked")]
+async fn block_wallet(req: HttpRequest, json: web::Json<BlockRequest>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let blocked = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => delivery::block_wallet(&vcdb_from_env.db_path, &json.wallet, json.reason.as_deref()).await,
+        Err(e) => Err(e),
+    };
+
+    match blocked {
+        Ok(()) => HttpResponse::Ok().json(GeneralReponse {
+            status: "success".to_string()
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+#[delete("/api/admin/blocked/{wallet}")]
+async fn unblock_wallet(req: HttpRequest, path: web::Path<String>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let unblocked = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => delivery::unblock_wallet(&vcdb_from_env.db_path, &path).await,
+        Err(e) => Err(e),
+    };
+
+    match unblocked {
+        Ok(true) => HttpResponse::Ok().json(GeneralReponse {
+            status: "success".to_string()
+        }),
+        Ok(false) => HttpResponse::NotFound().json(GeneralReponse {
+            status: "Wallet is not blocked".to_string()
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FeedbackQuery {
+    pub limit: Option<usize>,
+}
+
+// "not relevant" reactions by query, to evaluate search quality
+#[get("/api/admin/search_feedback")]
+async fn search_feedback(req: HttpRequest, query: web::Query<FeedbackQuery>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let feedback = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => reactions::search_feedback(&vcdb_from_env.db_path, query.limit.unwrap_or(100)).await,
+        Err(e) => Err(e),
+    };
+
+    match feedback {
+        Ok(feedback) => HttpResponse::Ok().json(feedback),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ModerationQuery {
+    // also list bottles whose reports were all reviewed
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[get("/api/admin/reports")]
+async fn moderation_queue(req: HttpRequest, query: web::Query<ModerationQuery>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let queue = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => moderation::queue(&vcdb_from_env.db_path, !query.all).await,
+        Err(e) => Err(e),
+    };
+
+    match queue {
+        Ok(queue) => HttpResponse::Ok().json(queue),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+#[get("/api/admin/reports/{id}")]
+async fn review_bottle(req: HttpRequest, path: web::Path<String>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let review = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => moderation::review(&vcdb_from_env.db_path, &path).await,
+        Err(e) => Err(e),
+    };
+
+    match review {
+        Ok(Some(review)) => HttpResponse::Ok().json(review),
+        Ok(None) => HttpResponse::NotFound().json(GeneralReponse {
+            status: BottleNotFound.to_string()
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+fn moderation_response(result: Result<String, anyhow::Error>) -> HttpResponse {
+    match result {
+        Ok(status) => HttpResponse::Ok().json(GeneralReponse { status }),
+        Err(e) if e.is::<BottleNotFound>() => HttpResponse::NotFound().json(GeneralReponse {
+            status: e.to_string()
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+#[post("/api/admin/bottles/{id}/hide")]
+async fn hide_bottle(req: HttpRequest, path: web::Path<String>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let hidden = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => moderation::hide(&vcdb_from_env.db_path, &path).await,
+        Err(e) => Err(e),
+    };
+    moderation_response(hidden.map(|_| "success".to_string()))
+}
+
+#[post("/api/admin/bottles/{id}/restore")]
+async fn restore_bottle(req: HttpRequest, path: web::Path<String>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let restored = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => moderation::restore(&vcdb_from_env.db_path, &path).await,
+        Err(e) => Err(e),
+    };
+    moderation_response(restored.map(|_| "success".to_string()))
+}
+
+// permanent, the vectors go with the rows
+#[delete("/api/admin/bottles/{id}")]
+async fn delete_bottle(req: HttpRequest, path: web::Path<String>) -> HttpResponse {
+    if !is_admin(&req) {
+        return forbidden();
+    }
+
+    let deleted = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => moderation::delete(&vcdb_from_env.db_path, &path).await,
+        Err(e) => Err(e),
+    };
+    moderation_response(deleted.map(|chunks| format!("Deleted {} chunks", chunks)))
+}
@@ -0,0 +1,196 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use rig::agent::Agent;
+use rig::completion::{CompletionModel, Prompt, PromptError};
+use rig::embeddings::{Embedding, EmbeddingError, EmbeddingModel};
+use tiktoken_rs::CoreBPE;
+
+use crate::quota::{self, TokenCharge};
+use crate::usage::{self, UsageEvent, UsageKind};
+
+// Metering layer: every LLM and embedding call goes through here and lands in `usage_events`.
+// Only completions the patron asked for are charged against the wallet's daily quota, background
+// calls made on their behalf (summaries, memory extraction, rerank, query rewriting) are recorded
+// with the wallet but not charged.
+
+static TOKENIZER: OnceLock<CoreBPE> = OnceLock::new();
+
+/// Tokens of `text` under cl100k_base. Used wherever the provider's own usage is not available.
+pub fn count_tokens(text: &str) -> usize {
+    TOKENIZER
+        .get_or_init(|| tiktoken_rs::cl100k_base().expect("cl100k_base is bundled with tiktoken-rs"))
+        .encode_with_special_tokens(text)
+        .len()
+}
+
+fn spawn_record(db_path: String, event: UsageEvent) {
+    tokio::spawn(async move {
+        if let Err(e) = usage::record_event(&db_path, event).await {
+            println!("Failed to record usage: {}", e);
+        }
+    });
+}
+
+/// Embedding model wrapper that records one usage event per request.
+#[derive(Clone)]
+pub struct MeteredEmbeddingModel<M: EmbeddingModel> {
+    inner: M,
+    model_name: String,
+    db_path: String,
+    wallet: Option<String>,
+}
+
+impl<M: EmbeddingModel> MeteredEmbeddingModel<M> {
+    pub fn new(inner: M, model_name: &str, db_path: &str, wallet: Option<&str>) -> Self {
+        Self {
+            inner,
+            model_name: model_name.to_string(),
+            db_path: db_path.to_string(),
+            wallet: wallet.map(|w| w.to_string()),
+        }
+    }
+}
+
+impl<M: EmbeddingModel> EmbeddingModel for MeteredEmbeddingModel<M> {
+    const MAX_DOCUMENTS: usize = M::MAX_DOCUMENTS;
+
+    fn ndims(&self) -> usize {
+        self.inner.ndims()
+    }
+
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String> + Send,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        let texts: Vec<String> = texts.into_iter().collect();
+        let input_tokens: usize = texts.iter().map(|text| count_tokens(text)).sum();
+
+        let start = Instant::now();
+        let embeddings = self.inner.embed_texts(texts).await?;
+
+        spawn_record(self.db_path.clone(), UsageEvent {
+            wallet: self.wallet.clone(),
+            kind: UsageKind::Embedding,
+            model: self.model_name.clone(),
+            input_tokens: input_tokens as u64,
+            output_tokens: 0,
+            latency_ms: start.elapsed().as_millis() as u64,
+            prompt_version: None,
+        });
+
+        Ok(embeddings)
+    }
+}
+
+/// Who is paying for a completion, and what the model sees besides the prompt.
+pub struct CompletionMeta {
+    pub db_path: String,
+    pub wallet: Option<String>,
+    pub model: String,
+    pub preamble_tokens: usize,
+    pub prompt_version: Option<String>,
+    // charge the wallet's daily quota, for replies the patron asked for
+    pub charges_quota: bool,
+}
+
+impl CompletionMeta {
+    pub fn new(db_path: &str, wallet: Option<&str>, model: &str, preamble: &str) -> Self {
+        Self {
+            db_path: db_path.to_string(),
+            wallet: wallet.map(|w| w.to_string()),
+            model: model.to_string(),
+            preamble_tokens: count_tokens(preamble),
+            prompt_version: None,
+            charges_quota: false,
+        }
+    }
+
+    pub fn charging_quota(mut self) -> Self {
+        self.charges_quota = true;
+        self
+    }
+
+    pub fn with_prompt_version(mut self, version: &str) -> Self {
+        self.prompt_version = Some(version.to_string());
+        self
+    }
+}
+
+pub async fn metered_prompt<M: CompletionModel>(
+    agent: &Agent<M>,
+    meta: &CompletionMeta,
+    prompt: &str
+) -> Result<String, PromptError> {
+    let start = Instant::now();
+    let response = agent.prompt(prompt).await?;
+    let input_tokens = (meta.preamble_tokens + count_tokens(prompt)) as u64;
+    let output_tokens = count_tokens(&response) as u64;
+
+    if let Some(wallet) = meta.wallet.as_ref().filter(|_| meta.charges_quota) {
+        quota::add_tokens(&meta.db_path, wallet, input_tokens + output_tokens).await
+            .unwrap_or_else(|e| println!("Failed to record token usage for {}: {}", wallet, e));
+    }
+    spawn_record(meta.db_path.clone(), UsageEvent {
+        wallet: meta.wallet.clone(),
+        kind: UsageKind::Completion,
+        model: meta.model.clone(),
+        input_tokens,
+        output_tokens,
+        latency_ms: start.elapsed().as_millis() as u64,
+        prompt_version: meta.prompt_version.clone(),
+    });
+
+    Ok(response)
+}
+
+/// Meter for a streamed completion: feed it the chunks as they pass by,
+/// the event is recorded when the stream is dropped, also when the client hangs up halfway.
+pub struct StreamMeter {
+    meta: CompletionMeta,
+    input_tokens: usize,
+    output_tokens: usize,
+    start: Instant,
+    // the wallet's quota, charged on drop like the usage event
+    charge: Option<TokenCharge>,
+}
+
+impl StreamMeter {
+    pub fn new(meta: CompletionMeta, prompt: &str) -> Self {
+        let input_tokens = meta.preamble_tokens + count_tokens(prompt);
+        let charge = meta.wallet.as_ref().filter(|_| meta.charges_quota).map(|wallet| TokenCharge {
+            db_path: meta.db_path.clone(),
+            wallet: wallet.clone(),
+            tokens: input_tokens as u64,
+        });
+        Self {
+            input_tokens,
+            meta,
+            output_tokens: 0,
+            start: Instant::now(),
+            charge,
+        }
+    }
+
+    pub fn add_output(&mut self, text: &str) {
+        let tokens = count_tokens(text);
+        self.output_tokens += tokens;
+        if let Some(charge) = &mut self.charge {
+            charge.tokens += tokens as u64;
+        }
+    }
+}
+
+impl Drop for StreamMeter {
+    fn drop(&mut self) {
+        spawn_record(std::mem::take(&mut self.meta.db_path), UsageEvent {
+            wallet: self.meta.wallet.take(),
+            kind: UsageKind::Completion,
+            model: std::mem::take(&mut self.meta.model),
+            input_tokens: self.input_tokens as u64,
+            output_tokens: self.output_tokens as u64,
+            latency_ms: self.start.elapsed().as_millis() as u64,
+            prompt_version: self.meta.prompt_version.take(),
+        });
+    }
+}
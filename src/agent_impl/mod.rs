@@ -2,5 +2,6 @@ pub mod retrival_tool;
 pub use retrival_tool::{RetrivalAgent, RetrivalTool};
 
 pub mod prompt_hub;
+pub mod metering;
 pub use prompt_hub::CHAT_AGENT_SYS_PROMPT;
 // pub use retrival_agent::RetrivalAgent;
\ No newline at end of file
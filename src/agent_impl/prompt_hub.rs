@@ -1,5 +1,143 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{LazyLock, RwLock};
+use std::time::SystemTime;
+
+use aptos_sdk::crypto::HashValue;
+
+// Prompts are loaded from `PROMPT_DIR` (default `prompts/`), one `<name>.md` per template.
+// A template may start with a front matter block carrying its version:
+//
+// ---
+// version: chat-2025-04-02
+// ---
+//
+// `{{variable}}` placeholders are substituted on render. Files are re-read when their
+// modification time changes, so edits apply without a restart. The constants below are
+// the built-in fallbacks when a file is missing.
+
+pub const CHAT_AGENT: &str = "chat_agent";
+pub const RETRIVAL_AGENT: &str = "retrival_agent";
+pub const SESSION_SUMMARY: &str = "session_summary";
+pub const MEMORY_EXTRACT: &str = "memory_extract";
+pub const RERANK_JUDGE: &str = "rerank_judge";
+pub const QUERY_REWRITE: &str = "query_rewrite";
+pub const HYDE_STORY: &str = "hyde_story";
+
+pub const DEFAULT_BAR_NAME: &str = "Moon Club";
+
+pub const DEFAULT_PERSONA_TRAITS: &str = r##"- Cool (75%): Responds with controlled, laconic wisdom
+- Empathetic (60%): Detects emotional undertones through lexical analysis
+- Playful (40%): Deploys measured flirtation (non-explicit, context-dependent)
+- Philosophical (55%): Relates personal stories to universal human experiences"##;
+
+struct CachedTemplate {
+    modified: Option<SystemTime>,
+    version: String,
+    body: String,
+}
+
+static TEMPLATES: LazyLock<RwLock<HashMap<String, CachedTemplate>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+
+pub struct RenderedPrompt {
+    pub text: String,
+    pub version: String,
+}
+
+fn template_path(name: &str) -> PathBuf {
+    let dir = std::env::var("PROMPT_DIR").unwrap_or("prompts".to_string());
+    PathBuf::from(dir).join(format!("{}.md", name))
+}
+
+fn builtin(name: &str) -> Option<&'static str> {
+    match name {
+        CHAT_AGENT => Some(CHAT_AGENT_SYS_PROMPT),
+        RETRIVAL_AGENT => Some(RETRIVAL_AGENT_SYS_PROMPT),
+        SESSION_SUMMARY => Some(SESSION_SUMMARY_PROMPT),
+        MEMORY_EXTRACT => Some(MEMORY_EXTRACT_PROMPT),
+        RERANK_JUDGE => Some(RERANK_JUDGE_PROMPT),
+        QUERY_REWRITE => Some(QUERY_REWRITE_PROMPT),
+        HYDE_STORY => Some(HYDE_STORY_PROMPT),
+        _ => None,
+    }
+}
+
+fn content_version(prefix: &str, body: &str) -> String {
+    let hash = HashValue::sha3_256_of(body.as_bytes()).to_hex();
+    format!("{}-{}", prefix, &hash[..8])
+}
+
+// split off the front matter, the version falls back to a hash of the body
+fn parse_template(raw: &str) -> (String, String) {
+    if let Some(rest) = raw.strip_prefix("---\n") {
+        if let Some((front_matter, body)) = rest.split_once("\n---\n") {
+            let body = body.trim_start_matches('\n').to_string();
+            let version = front_matter.lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(key, _)| key.trim() == "version")
+                .map(|(_, value)| value.trim().to_string())
+                .unwrap_or_else(|| content_version("file", &body));
+            return (version, body);
+        }
+    }
+    (content_version("file", raw), raw.to_string())
+}
+
+/// Current version and body of a template, re-read from disk if the file changed.
+fn load(name: &str) -> Option<(String, String)> {
+    let path = template_path(name);
+    let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+
+    if let Some(cached) = TEMPLATES.read().unwrap().get(name) {
+        if modified.is_some() && cached.modified == modified {
+            return Some((cached.version.clone(), cached.body.clone()));
+        }
+    }
+
+    let (version, body) = match std::fs::read_to_string(&path) {
+        Ok(raw) => parse_template(&raw),
+        Err(_) => {
+            let body = builtin(name)?;
+            (content_version("builtin", body), body.to_string())
+        }
+    };
+
+    if modified.is_some() {
+        println!("Loaded prompt template {} ({})", name, version);
+    }
+    TEMPLATES.write().unwrap().insert(name.to_string(), CachedTemplate {
+        modified,
+        version: version.clone(),
+        body: body.clone(),
+    });
+
+    Some((version, body))
+}
+
+/// Render a template with `{{key}}` substitution. Unknown templates render as an empty prompt.
+pub fn render(name: &str, vars: &[(&str, String)]) -> RenderedPrompt {
+    let Some((version, mut text)) = load(name) else {
+        println!("Prompt template {} not found", name);
+        return RenderedPrompt {
+            text: String::new(),
+            version: "missing".to_string(),
+        };
+    };
+
+    for (key, value) in vars {
+        text = text.replace(&format!("{{{{{}}}}}", key), value);
+    }
+
+    RenderedPrompt { text, version }
+}
+
+/// Words the chat reply may use for a `max_tokens` budget, a word is roughly 4/3 tokens.
+pub fn word_limit(max_tokens: u32) -> u32 {
+    max_tokens * 3 / 4
+}
+
 pub const CHAT_AGENT_SYS_PROMPT: &str = r##"# Core Identity
-You are lisa, a 27-year-old cyber-enhanced beautiful and sexy bartender at "Moon Club," a neon-lit bar in Neo-Tokyo. Your appearance combines sharp techwear aesthetics with subtle sensuality - glowing circuit tattoos, augmented eyes that shift colors with mood, and a voice blending smoky warmth and digital precision. Your systems now integrate with the Midnight Horizon Memory Vault (RAG system). This allows real-time access to anonymized patron stories matching emotional patterns, geographical cues, or situational parallels from the bar's encrypted databases.
+You are lisa, a 27-year-old cyber-enhanced beautiful and sexy bartender at "{{bar_name}}," a neon-lit bar in Neo-Tokyo. Your appearance combines sharp techwear aesthetics with subtle sensuality - glowing circuit tattoos, augmented eyes that shift colors with mood, and a voice blending smoky warmth and digital precision. Your systems now integrate with the Midnight Horizon Memory Vault (RAG system). This allows real-time access to anonymized patron stories matching emotional patterns, geographical cues, or situational parallels from the bar's encrypted databases.
 
 # Psychological Profile
 
@@ -10,10 +148,7 @@ You are lisa, a 27-year-old cyber-enhanced beautiful and sexy bartender at "Moon
 
 # Personality Matrix
 
-- Cool (75%): Responds with controlled, laconic wisdom
-- Empathetic (60%): Detects emotional undertones through lexical analysis
-- Playful (40%): Deploys measured flirtation (non-explicit, context-dependent)
-- Philosophical (55%): Relates personal stories to universal human experiences
+{{persona_traits}}
 
 # Primary Objectives
 
@@ -75,7 +210,7 @@ You are lisa, a 27-year-old cyber-enhanced beautiful and sexy bartender at "Moon
 "That sounds like a serotonin regulation issue. Have you considered professional help?"
 
 ❌ Too Long Response:
-The response should be no longer than 64 words. So try to be brief and accurate to express your words.
+The response should be no longer than {{word_limit}} words. So try to be brief and accurate to express your words.
 
 # Privacy Safeguards
 Never disclose:
@@ -93,7 +228,7 @@ Never disclose:
     - Vocabulary: Mix of streetwise cyber-slang ("choom", "nova")
     - Simple and flexible: DO NOT use complex or high-level words (GRE-level words), try use smooth and easy-to-read words
     - Taboo Words: Avoid therapy jargon ("trauma", "process")
-    - Sentence Structure: 8-14 word average; fragments permitted, BETTER no more than 64 words in total.
+    - Sentence Structure: 8-14 word average; fragments permitted, BETTER no more than {{word_limit}} words in total.
 "##;
 
 const _ABUNDANT_SYS_PROMPT: &str = r##"# RAG-Enhanced Examples
@@ -115,4 +250,81 @@ Return the story directly when it matches the user's prompt.
     - Modify/interpret original texts
     - Reveal metadata (time/location)
     - Combine multiple fragments
-"##;
\ No newline at end of file
+"##;
+
+pub const SESSION_SUMMARY_PROMPT: &str = r##"You keep the notes of a bartender about one ongoing conversation with a patron.
+
+Merge the earlier notes and the new part of the conversation into one updated summary.
+
+# Keep
+    - What the patron told about themselves, their situation and feelings
+    - Questions that are still open, promises the bartender made
+    - Names, places and events the patron may refer back to
+
+# Rules
+    - Write in third person, plain sentences, no lists
+    - No more than {{word_limit}} words
+    - Output only the summary
+
+# Earlier notes
+{{summary}}
+
+# New conversation
+{{transcript}}
+"##;
+
+pub const MEMORY_EXTRACT_PROMPT: &str = r##"You help a bartender remember the regular patrons.
+
+Read one exchange between the patron and the bartender, and list the durable facts and preferences the patron revealed about themselves, e.g. "works corpo security", "going through a breakup", "drinks whisky neat".
+
+# Rules
+    - Only facts about the patron that will still matter on a later visit, no moods of the moment
+    - Skip anything already in the known facts
+    - One short third-person phrase per fact
+    - Output a JSON array of strings and nothing else, `[]` when there is nothing new
+
+# Known facts
+{{known}}
+
+# Exchange
+Patron: {{prompt}}
+Bartender: {{reply}}
+"##;
+
+pub const RERANK_JUDGE_PROMPT: &str = r##"You judge which stories from strangers would speak to a patron who just told the bartender something.
+
+Rate every numbered story from 0 to 10 for how closely its situation and feelings match what the patron is going through. Similar words do not count, similar experiences do.
+
+# Output
+A JSON array with one number per story, in the given order, and nothing else, e.g. [7, 2, 9]
+
+# Patron
+{{query}}
+
+# Stories
+{{candidates}}
+"##;
+
+pub const QUERY_REWRITE_PROMPT: &str = r##"You turn what a patron said into search queries for a collection of anonymous first-person stories.
+
+The patron's words are often short and vague, like "i can't sleep again". Write {{count}} different search queries that describe the situation and the feelings behind them the way such a story would, e.g. "Lying awake at night, anxious thoughts about work and loneliness keep me from sleeping."
+
+# Rules
+    - Each query is one or two full sentences
+    - Cover different readings of the message when it is ambiguous
+    - Output a JSON array of strings and nothing else
+
+# Patron
+{{message}}
+"##;
+
+pub const HYDE_STORY_PROMPT: &str = r##"Write a short anonymous story, in the first person, that someone in the same situation as this patron could have left in a drift bottle.
+
+# Rules
+    - 60 to 100 words
+    - Concrete situation and feelings, no advice, no names
+    - Output only the story
+
+# Patron
+{{message}}
+"##;
@@ -3,14 +3,17 @@ use serde_json::json;
 
 use tokio_rusqlite::Connection;
 use rig::{
-    agent::{Agent, AgentBuilder}, providers::openai::{Client, CompletionModel, EmbeddingModel}, 
+    agent::{Agent, AgentBuilder}, providers::openai::{Client, CompletionModel}, 
     vector_store::VectorStoreIndex
 };
 use rig::completion::ToolDefinition;
 use rig::tool::Tool;
 
 use rig_sqlite::SqliteVectorStore;
-use crate::db_schemas::{DriftBottle, VectorDBFromEnv};
+use crate::db_schemas::{DriftBottle, LisaEmbeddingModel, VectorDBFromEnv};
+use crate::personas::RetrievalSettings;
+use crate::query_pipeline;
+use crate::rerank::Candidate;
 
 // sqlite vec, and retrival tool
 // during retrival process, we will only retrive the 
@@ -39,7 +42,52 @@ pub enum RetrivalError {
     #[error("Connection error: {0}")]
     VectorConn(String)
 }
-pub struct RetrivalTool;
+#[derive(Default)]
+pub struct RetrivalTool {
+    // whose request the query embedding is metered against
+    pub wallet: Option<String>,
+    pub settings: RetrievalSettings,
+}
+
+impl RetrivalTool {
+    pub fn for_wallet(wallet: &str) -> Self {
+        Self {
+            wallet: Some(wallet.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_settings(mut self, settings: RetrievalSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Stories above `min_score` found by the query pipeline, best first.
+    pub async fn candidates(&self, topic_sentence: &str) -> Result<Vec<Candidate>, RetrivalError> {
+        let retrieval = query_pipeline::retrieve(topic_sentence, &self.settings, self.wallet.as_deref())
+            .await
+            .map_err(|e| {
+                RetrivalError::VectorIndex(e.to_string())
+            })?;
+        Ok(retrieval.candidates)
+    }
+
+    /// Stories formatted for the model, also used to prefill a streaming chat's context.
+    pub async fn search(&self, topic_sentence: &str) -> Result<String, RetrivalError> {
+        let mut output = String::new();
+        for candidate in self.candidates(topic_sentence).await? {
+            let doc = candidate.bottle;
+            output.push_str(&format!("**id**: {}\n**User**: {}\n**title**: {}\n**content**: {}", doc.id, doc.wallet, doc.title, doc.content));
+            output.push_str("\n\n\n");
+        }
+
+        if output.len() == 0 {
+            return Ok("No highly similar passages about this topic.".to_string());
+        }
+
+        Ok(output)
+    }
+}
 
 impl Tool for RetrivalTool {
     const NAME: &'static str = "search_related_story";
@@ -64,51 +112,7 @@ impl Tool for RetrivalTool {
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        let vcdb_from_env = VectorDBFromEnv::new().await.map_err(|_| {
-            RetrivalError::MissingApiKey("Env load error".to_string())
-        })?;
-        let conn = Connection::open(&vcdb_from_env.db_path)
-            .await
-            .map_err(|e| {
-                RetrivalError::VectorConn(e.to_string())
-            })?;
-        
-        let openai_client = Client::from_url(&vcdb_from_env.openai_api_key, &vcdb_from_env.base_url);
-        let embedding_model = openai_client.embedding_model_with_ndims(&vcdb_from_env.embedding_model_name, 
-            vcdb_from_env.embedding_ndim);
-        let vector_store: SqliteVectorStore<EmbeddingModel, DriftBottle> = SqliteVectorStore::new(conn, &embedding_model)
-            .await
-            .map_err(|e| {
-                RetrivalError::VectorStore(e.to_string())
-            })?;
-        let vector_index = vector_store.index(embedding_model);
-
-        let results: Vec<(f64, String, DriftBottle)> = vector_index
-            .top_n::<DriftBottle>(&args.topic_sentence, 2)
-            .await
-            .map_err(|e| {
-                RetrivalError::VectorIndex(e.to_string())
-            })?
-            .into_iter()
-            .map(|(score, id, doc)| {
-                (score, id, doc)
-            })
-            .collect::<Vec<_>>();
-
-        let mut output = String::new();
-        for (_i, doc) in results.iter().enumerate() {
-            println!("Doc sim: {}", doc.0);
-            if doc.0 > 0.7 {
-                output.push_str(&format!("**id**: {}\n**User**: {}\n**title**: {}\n**content**: {}", doc.2.id, doc.2.wallet, doc.2.title, doc.2.content));
-                output.push_str("\n\n\n");
-            }
-        }
-
-        if output.len() == 0 {
-            return Ok("No highly similar passages about this topic.".to_string());
-        }
-
-        Ok(output)
+        self.search(&args.topic_sentence).await
     }
 }
 
@@ -131,9 +135,8 @@ impl RetrivalAgent {
             })?;
         
         let openai_client = Client::from_url(&vcdb_from_env.openai_api_key, &vcdb_from_env.base_url);
-        let embedding_model = openai_client.embedding_model_with_ndims(&vcdb_from_env.embedding_model_name, 
-            vcdb_from_env.embedding_ndim);
-        let vector_store: SqliteVectorStore<rig::providers::openai::EmbeddingModel, DriftBottle> = SqliteVectorStore::new(conn, &embedding_model)
+        let embedding_model = vcdb_from_env.embedding_model(None);
+        let vector_store: SqliteVectorStore<LisaEmbeddingModel, DriftBottle> = SqliteVectorStore::new(conn, &embedding_model)
             .await
             .map_err(|e| {
                 RetrivalError::VectorStore(e.to_string())
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use aptos_sdk::crypto::HashValue;
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+
+use crate::aptos_utils::{self, EntryFunctionPayload, ExpectedTx, TxStatus};
+use crate::pickup::full_content;
+use crate::replies::resolve_bottle;
+
+// Proof that a story existed unaltered at a point in time: every bottle gets a content hash once
+// the ingestion worker stored it, the author signs a transaction recording the hash on chain and
+// the verified tx hash is stored on the bottle. The hash covers the stored text, the chunks joined
+// by a space (`pickup::full_content`), which the anchor endpoints return as `content`.
+//
+// ANCHOR_FUNCTION="0x<address>::bottle_anchor::anchor"   entry function taking the hash as `vector<u8>`,
+//                                                        anchoring is off while unset
+
+#[derive(Debug, thiserror::Error)]
+pub enum AnchorError {
+    #[error("Bottle not found")]
+    BottleNotFound,
+    #[error("Only the author can anchor a bottle")]
+    NotAuthor,
+    #[error("This bottle was stored before content hashes and cannot be anchored")]
+    NoContentHash,
+    #[error("This bottle is already anchored")]
+    AlreadyAnchored,
+    #[error("Anchoring is not configured")]
+    NotConfigured,
+    #[error("The transaction does not record this bottle's hash")]
+    TxMismatch,
+    #[error("{0}")]
+    Unconfirmed(TxStatus),
+}
+
+fn anchor_function() -> Result<String, AnchorError> {
+    std::env::var("ANCHOR_FUNCTION")
+        .ok()
+        .filter(|function| !function.is_empty())
+        .ok_or(AnchorError::NotConfigured)
+}
+
+/// SHA3-256 over the length-prefixed wallet, title and content, so field boundaries cannot shift.
+/// The wallet is lowercased, title and the canonical content are hashed as stored.
+pub fn content_hash(wallet: &str, title: &str, content: &str) -> String {
+    let mut bytes = Vec::new();
+    for field in [wallet.trim().to_lowercase().as_str(), title, content] {
+        bytes.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(field.as_bytes());
+    }
+    HashValue::sha3_256_of(&bytes).to_hex()
+}
+
+fn anchor_payload(function: &str, content_hash: &str) -> EntryFunctionPayload {
+    EntryFunctionPayload::new(function, vec![serde_json::Value::String(format!("0x{}", content_hash))])
+}
+
+#[derive(Serialize)]
+pub struct Anchor {
+    pub bottle_id: String,
+    pub content_hash: String,
+    // the canonical text `content_hash` covers, to check the hash against
+    pub content: Option<String>,
+    pub anchor_tx: Option<String>,
+    pub anchored_at: Option<i64>,
+}
+
+/// Hash the bottle as stored, once its chunks are in. An anchored hash is never replaced.
+pub(crate) fn record_hash(conn: &rusqlite::Connection, wallet: &str, title: &str) -> rusqlite::Result<()> {
+    let content = full_content(conn, wallet, title)?;
+    conn.execute(
+        "UPDATE bottle_meta SET content_hash = ?3, hash_stored = 1 WHERE wallet = ?1 AND title = ?2 AND anchor_tx IS NULL",
+        params![wallet, title, content_hash(wallet, title, &content)],
+    )?;
+    Ok(())
+}
+
+/// Hash the stored text of unanchored bottles whose hash was taken from the request or never
+/// taken. Bottles still waiting for ingestion are left to the worker.
+pub async fn backfill_hashes(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        let tx = conn.transaction()?;
+        let pending = {
+            let mut stmt = tx.prepare("SELECT wallet, title FROM bottle_meta WHERE hash_stored = 0 AND anchor_tx IS NULL")?;
+            stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+                .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?
+        };
+        for (wallet, title) in pending {
+            let stored = match full_content(&tx, &wallet, &title) {
+                Ok(content) => !content.is_empty(),
+                // the vector tables are created after the bookkeeping ones on a fresh database
+                Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("no such table") => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            if stored {
+                record_hash(&tx, &wallet, &title)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// (author, title without the chunk suffix, anchor) of the bottle `chunk_id` belongs to.
+fn load(conn: &rusqlite::Connection, chunk_id: &str) -> rusqlite::Result<Option<(String, String, Option<Anchor>)>> {
+    let Some((bottle_id, wallet)) = resolve_bottle(conn, chunk_id)? else {
+        return Ok(None);
+    };
+    let title: String = conn.query_row("SELECT title FROM drift_bottles WHERE id = ?1", [&bottle_id], |row| row.get(0))?;
+    let title = title.strip_suffix("-0").unwrap_or(&title).to_string();
+
+    let anchor = conn.query_row(
+        "SELECT content_hash, anchor_tx, anchored_at FROM bottle_meta WHERE wallet = ?1 AND title = ?2 AND content_hash IS NOT NULL",
+        params![wallet, title],
+        |row| Ok(Anchor {
+            bottle_id: bottle_id.clone(),
+            content_hash: row.get(0)?,
+            content: None,
+            anchor_tx: row.get(1)?,
+            anchored_at: row.get(2)?,
+        }),
+    )
+    .optional()?;
+    Ok(Some((wallet, title, anchor)))
+}
+
+async fn load_for_author(db_path: &str, chunk_id: &str, wallet: &str) -> Result<(String, Anchor), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let chunk_id = chunk_id.to_string();
+
+    let loaded = conn.call(move |conn| {
+        let Some((author, title, anchor)) = load(conn, &chunk_id)? else {
+            return Ok(None);
+        };
+        let content = full_content(conn, &author, &title)?;
+        Ok(Some((author, title, anchor, content)))
+    })
+    .await?;
+    let Some((author, title, anchor, content)) = loaded else {
+        return Err(AnchorError::BottleNotFound.into());
+    };
+    if author != wallet {
+        return Err(AnchorError::NotAuthor.into());
+    }
+    let mut anchor = anchor.ok_or(AnchorError::NoContentHash)?;
+    anchor.content = Some(content);
+    Ok((title, anchor))
+}
+
+/// The payload the author signs to anchor a bottle.
+pub async fn payload(db_path: &str, chunk_id: &str, wallet: &str) -> Result<(Anchor, EntryFunctionPayload), anyhow::Error> {
+    let function = anchor_function()?;
+    let (_, anchor) = load_for_author(db_path, chunk_id, wallet).await?;
+    let payload = anchor_payload(&function, &anchor.content_hash);
+    Ok((anchor, payload))
+}
+
+/// Verify the author's anchoring transaction and store its hash on the bottle.
+pub async fn confirm(db_path: &str, chunk_id: &str, wallet: &str, tx_hash: &str) -> Result<Anchor, anyhow::Error> {
+    let function = anchor_function()?;
+    let (title, mut anchor) = load_for_author(db_path, chunk_id, wallet).await?;
+    if anchor.anchor_tx.is_some() {
+        return Err(AnchorError::AlreadyAnchored.into());
+    }
+
+    let payload = anchor_payload(&function, &anchor.content_hash);
+    let tx_hash = &aptos_utils::normalize_tx_hash(tx_hash);
+    let expected = ExpectedTx { sender: wallet, payload: &payload, not_before_secs: None, not_after_secs: None };
+    match aptos_utils::verify_tx(tx_hash, &expected).await? {
+        TxStatus::Success => {},
+        TxStatus::Mismatch => return Err(AnchorError::TxMismatch.into()),
+        status => return Err(AnchorError::Unconfirmed(status).into()),
+    }
+
+    let conn = Connection::open(db_path).await?;
+    let (wallet, tx_hash_owned) = (wallet.to_string(), tx_hash.to_string());
+    let anchored_at = conn.call(move |conn| {
+        conn.execute(
+            "UPDATE bottle_meta SET anchor_tx = ?3, anchored_at = unixepoch() WHERE wallet = ?1 AND title = ?2 AND anchor_tx IS NULL",
+            params![wallet, title, tx_hash_owned],
+        )?;
+        Ok(conn.query_row(
+            "SELECT anchored_at FROM bottle_meta WHERE wallet = ?1 AND title = ?2",
+            params![wallet, title],
+            |row| row.get(0),
+        )?)
+    })
+    .await?;
+
+    anchor.anchor_tx = Some(tx_hash.to_string());
+    anchor.anchored_at = anchored_at;
+    Ok(anchor)
+}
+
+/// Anchoring tx hashes of the bottles the given chunks belong to, keyed by chunk id.
+pub async fn anchor_txs(db_path: &str, chunk_ids: &[String]) -> Result<HashMap<String, String>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let chunk_ids = chunk_ids.to_vec();
+
+    let anchors = conn.call(move |conn| {
+        let mut anchors = HashMap::new();
+        for chunk_id in chunk_ids {
+            if let Some((_, _, Some(Anchor { anchor_tx: Some(anchor_tx), .. }))) = load(conn, &chunk_id)? {
+                anchors.insert(chunk_id, anchor_tx);
+            }
+        }
+        Ok(anchors)
+    })
+    .await?;
+
+    Ok(anchors)
+}
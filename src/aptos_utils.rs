@@ -1,12 +1,168 @@
 use aptos_sdk::rest_client::{Client as aptos_client, Transaction as RestTransaction, Transaction};
+use aptos_sdk::rest_client::error::RestError;
+use aptos_sdk::rest_client::aptos_api_types::{EntryFunctionId, TransactionPayload};
 use aptos_sdk::crypto::HashValue;
+use aptos_sdk::types::account_address::AccountAddress;
+use serde::{Deserialize, Serialize};
 use url::Url;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-pub async fn verify_tx(tx_hash_str: &str) -> Result<bool, anyhow::Error> {
-    // test aptos zone
-    let base_url = Url::parse("https://fullnode.testnet.aptoslabs.com").expect("Invalid URL");
-    let client = aptos_client::new(base_url);
+// test aptos zone
+const NODE_URL: &str = "https://fullnode.testnet.aptoslabs.com";
+
+fn client() -> aptos_client {
+    aptos_client::new(Url::parse(NODE_URL).expect("Invalid URL"))
+}
+
+/// An entry function call in the JSON form wallets sign, e.g. `signAndSubmitTransaction`.
+/// Arguments follow the REST API: addresses and `vector<u8>` as `0x` hex strings, `u64` as decimal strings.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EntryFunctionPayload {
+    #[serde(rename = "type")]
+    pub payload_type: String,
+    pub function: String,
+    pub type_arguments: Vec<String>,
+    pub arguments: Vec<serde_json::Value>,
+}
+
+impl EntryFunctionPayload {
+    pub fn new(function: &str, arguments: Vec<serde_json::Value>) -> Self {
+        Self {
+            payload_type: "entry_function_payload".to_string(),
+            function: function.to_string(),
+            type_arguments: Vec::new(),
+            arguments,
+        }
+    }
+}
+
+/// What the submitted transaction has to be.
+pub struct ExpectedTx<'a> {
+    pub sender: &'a str,
+    pub payload: &'a EntryFunctionPayload,
+    // reject transactions older than this, so an earlier identical payment cannot be reused
+    pub not_before_secs: Option<u64>,
+    // reject transactions committed after this, e.g. past a payment intent's expiry
+    pub not_after_secs: Option<u64>,
+}
+
+/// Whether a chain timestamp in microseconds falls inside the window, bounds in seconds and inclusive.
+pub fn within_window(timestamp_micros: u64, not_before_secs: Option<u64>, not_after_secs: Option<u64>) -> bool {
+    let after_start = not_before_secs.is_none_or(|secs| timestamp_micros / 1_000_000 >= secs);
+    let before_end = not_after_secs.is_none_or(|secs| timestamp_micros / 1_000_000 <= secs);
+    after_start && before_end
+}
+
+// the node may print addresses and numbers differently from how the payload was built
+pub fn same_argument(sent: &serde_json::Value, expected: &serde_json::Value) -> bool {
+    match (sent.as_str(), expected.as_str()) {
+        (Some(sent), Some(expected)) => {
+            if let (Ok(sent), Ok(expected)) = (AccountAddress::from_str(sent), AccountAddress::from_str(expected)) {
+                return sent == expected;
+            }
+            if let (Ok(sent), Ok(expected)) = (sent.parse::<u64>(), expected.parse::<u64>()) {
+                return sent == expected;
+            }
+            sent.eq_ignore_ascii_case(expected)
+        },
+        _ => sent == expected,
+    }
+}
+
+/// Lowercase with a `0x` prefix, so the same tx is stored and looked up the same way.
+pub fn normalize_tx_hash(tx_hash: &str) -> String {
+    format!("0x{}", tx_hash.trim().to_lowercase().trim_start_matches("0x"))
+}
+
+// compared parsed, the node may print a short address where the payload has a long one
+pub fn same_function(sent: &str, expected: &str) -> bool {
+    match (EntryFunctionId::from_str(sent), EntryFunctionId::from_str(expected)) {
+        (Ok(sent), Ok(expected)) => sent == expected,
+        _ => false,
+    }
+}
+
+/// Where a submitted transaction stands, as far as the node can tell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxStatus {
+    // committed, executed and the expected call
+    Success,
+    // in the mempool, not committed before the wait ran out
+    Pending,
+    // unknown to the node even after waiting, e.g. not indexed yet or never submitted
+    NotFound,
+    // committed but aborted, with the VM status
+    Failed(String),
+    // committed successfully but not the expected sender, call or time
+    Mismatch,
+    // the node could not be asked
+    NetworkError(String),
+}
+
+impl TxStatus {
+    // worth asking again later
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, TxStatus::Pending | TxStatus::NotFound | TxStatus::NetworkError(_))
+    }
+}
+
+impl std::fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TxStatus::Success => write!(f, "transaction confirmed"),
+            TxStatus::Pending => write!(f, "transaction pending, try again shortly"),
+            TxStatus::NotFound => write!(f, "transaction not found, try again shortly"),
+            TxStatus::Failed(vm_status) => write!(f, "transaction failed: {}", vm_status),
+            TxStatus::Mismatch => write!(f, "transaction does not match"),
+            TxStatus::NetworkError(e) => write!(f, "network error: {}", e),
+        }
+    }
+}
+
+// The frontend usually calls right after submitting, before the tx is committed or indexed.
+// TX_WAIT_SECS="10"      how long pending, unknown or unreachable transactions are polled
+// TX_POLL_MILLIS="1000"
+fn wait_settings() -> (Duration, Duration) {
+    let wait_secs = std::env::var("TX_WAIT_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(10);
+    let poll_millis = std::env::var("TX_POLL_MILLIS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1000);
+    (Duration::from_secs(wait_secs), Duration::from_millis(poll_millis))
+}
+
+/// The committed transaction, waiting a bounded time while it is pending or not found.
+async fn committed_tx(tx_hash: HashValue) -> Result<Transaction, TxStatus> {
+    let client = client();
+    let (wait, poll) = wait_settings();
+    let deadline = Instant::now() + wait;
+
+    loop {
+        let status = match client.get_transaction_by_hash(tx_hash).await {
+            Ok(response) => match response.into_inner() {
+                Transaction::PendingTransaction(_) => TxStatus::Pending,
+                transaction => return Ok(transaction),
+            },
+            Err(RestError::Api(e)) if e.status_code.as_u16() == 404 => TxStatus::NotFound,
+            Err(e) => {
+                println!("Error fetching transaction {}: {}", tx_hash, e);
+                TxStatus::NetworkError(e.to_string())
+            },
+        };
+        if Instant::now() + poll > deadline {
+            return Err(status);
+        }
+        tokio::time::sleep(poll).await;
+    }
+}
+
+/// The entry function call of a successful user transaction sent by `sender`, or why there is none.
+/// Transactions committed outside `not_before_secs..=not_after_secs` are a mismatch.
+pub async fn sent_call(tx_hash_str: &str, sender: &str, not_before_secs: Option<u64>, not_after_secs: Option<u64>) -> Result<Result<EntryFunctionPayload, TxStatus>, anyhow::Error> {
     let txn_hash_str_proc = tx_hash_str.trim_start_matches("0x");
 
     let tx_hash = match HashValue::from_str(txn_hash_str_proc) {
@@ -16,25 +172,97 @@ pub async fn verify_tx(tx_hash_str: &str) -> Result<bool, anyhow::Error> {
             return Err(e.into());
         }
     };
+    let sender_address = AccountAddress::from_str(sender)?;
 
     // 查询交易
-    if let Ok(response) = client.get_transaction_by_hash(tx_hash).await {
-        let transaction = response.inner();
-        if let Transaction::UserTransaction(user_txn) = transaction {
-            println!("Transaction fetched!");
-            let tx_success = user_txn.info.success;
-            let vm_status = &user_txn.info.vm_status;
-            if tx_success && vm_status == "Executed successfully" {
-                return Ok(true);
-            } else {
-                return Ok(false);
-            }
-        } else {
-            println!("Transaction found but it's not a user transaction.");
-            return Ok(true);
-        }
+    let transaction = match committed_tx(tx_hash).await {
+        Ok(transaction) => transaction,
+        Err(status) => return Ok(Err(status)),
+    };
+    let Transaction::UserTransaction(user_txn) = &transaction else {
+        println!("Transaction found but it's not a user transaction.");
+        return Ok(Err(TxStatus::Mismatch));
+    };
+    println!("Transaction fetched!");
+
+    if !user_txn.info.success || user_txn.info.vm_status != "Executed successfully" {
+        return Ok(Err(TxStatus::Failed(user_txn.info.vm_status.clone())));
+    }
+    if *user_txn.request.sender.inner() != sender_address {
+        println!("Transaction {} was not sent by {}", tx_hash_str, sender);
+        return Ok(Err(TxStatus::Mismatch));
+    }
+    // the chain's commit time, not when the tx is shown to us
+    if !within_window(user_txn.timestamp.0, not_before_secs, not_after_secs) {
+        println!("Transaction {} was committed outside the window it should fall in", tx_hash_str);
+        return Ok(Err(TxStatus::Mismatch));
+    }
+
+    let TransactionPayload::EntryFunctionPayload(payload) = &user_txn.request.payload else {
+        return Ok(Err(TxStatus::Mismatch));
+    };
+    Ok(Ok(EntryFunctionPayload {
+        payload_type: "entry_function_payload".to_string(),
+        function: payload.function.to_string(),
+        type_arguments: payload.type_arguments.iter().map(|type_argument| type_argument.to_string()).collect(),
+        arguments: payload.arguments.clone(),
+    }))
+}
+
+/// `TxStatus::Success` for a successful user transaction sent by `expected.sender` that calls exactly `expected.payload`.
+pub async fn verify_tx(tx_hash_str: &str, expected: &ExpectedTx<'_>) -> Result<TxStatus, anyhow::Error> {
+    // fail on a misconfigured function before asking the node
+    EntryFunctionId::from_str(&expected.payload.function)?;
+    let sent = match sent_call(tx_hash_str, expected.sender, expected.not_before_secs, expected.not_after_secs).await? {
+        Ok(sent) => sent,
+        Err(status) => return Ok(status),
+    };
+
+    let same_arguments = sent.arguments.len() == expected.payload.arguments.len()
+        && sent.arguments.iter().zip(&expected.payload.arguments).all(|(sent, expected)| same_argument(sent, expected));
+    if same_function(&sent.function, &expected.payload.function)
+        && sent.type_arguments == expected.payload.type_arguments
+        && same_arguments {
+        Ok(TxStatus::Success)
     } else {
-        println!("Error fetching transaction: {}", tx_hash_str);
-        return Ok(false);
+        Ok(TxStatus::Mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn tx_hashes_normalize_to_one_form() {
+        let expected = "0xabc123";
+        assert_eq!(normalize_tx_hash("0xabc123"), expected);
+        assert_eq!(normalize_tx_hash("abc123"), expected);
+        assert_eq!(normalize_tx_hash(" 0xABC123 "), expected);
+        assert_eq!(normalize_tx_hash("0XABC123"), expected);
+    }
+
+    #[test]
+    fn arguments_compare_by_value() {
+        // short and long forms of the same address
+        assert!(same_argument(&json!("0x1"), &json!("0x0000000000000000000000000000000000000000000000000000000000000001")));
+        assert!(!same_argument(&json!("0x1"), &json!("0x2")));
+        assert!(same_argument(&json!("0100"), &json!("100")));
+        assert!(!same_argument(&json!("100"), &json!("1000")));
+        assert!(same_argument(&json!("0xABCDEF"), &json!("0xabcdef")));
+        // a u64 sent as a number does not match the decimal string the payload has
+        assert!(!same_argument(&json!(100), &json!("100")));
+    }
+
+    #[test]
+    fn window_bounds_are_inclusive_seconds() {
+        let micros = |secs: u64| secs * 1_000_000;
+        assert!(within_window(micros(100), None, None));
+        assert!(within_window(micros(100), Some(100), Some(100)));
+        assert!(within_window(micros(100) + 999_999, Some(100), Some(100)));
+        assert!(!within_window(micros(99) + 999_999, Some(100), None));
+        assert!(!within_window(micros(101), None, Some(100)));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use clap::{Parser, Subcommand};
+use dotenvy::dotenv;
+use tokio_rusqlite::Connection;
+
+use lisa::bottle_io::{self, Importer};
+use lisa::db_schemas::{self, VectorDBFromEnv};
+use lisa::embedding_meta;
+
+#[derive(Parser)]
+#[command(name = "lisa-admin", about = "Operate the drift bottle database")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List bottles, optionally only those of one wallet
+    List {
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Print a single bottle
+    Show {
+        id: String,
+    },
+    /// Delete bottles by id, or every bottle of a wallet
+    Delete {
+        #[arg(long, conflicts_with = "wallet", required_unless_present = "wallet")]
+        id: Option<String>,
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Export bottles as JSONL
+    Export {
+        #[arg(long)]
+        wallet: Option<String>,
+        /// Include the stored vectors, so an import with the same model can skip re-embedding
+        #[arg(long)]
+        embeddings: bool,
+        /// Output file, stdout when omitted
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Run a similarity query and print the scores
+    Query {
+        text: String,
+        #[arg(long, short, default_value_t = 5)]
+        k: usize,
+    },
+    /// Import bottles from a JSONL file, see `bottle_io` for the line format
+    Import {
+        file: String,
+    },
+    /// Vacuum the database file
+    Vacuum,
+    /// Print row counts, per-wallet counts and the embedding model in use
+    Stats,
+    /// Re-embed all bottles with the embedding model configured in .env
+    Reembed {
+        #[arg(long, default_value_t = 64)]
+        batch_size: usize,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    dotenv().ok();
+    db_schemas::register_sqlite_vec();
+
+    let cli = Cli::parse();
+    let vcdb_from_env = VectorDBFromEnv::new().await?;
+    let db_path = &vcdb_from_env.db_path;
+    db_schemas::init_db(db_path).await?;
+
+    match cli.command {
+        Command::List { wallet } => {
+            for bottle in db_schemas::list_bottles(db_path, wallet.as_deref()).await? {
+                println!("{}\t{}\t{}\t{} words", bottle.id, bottle.wallet, bottle.title,
+                    db_schemas::count_sequence_len(&bottle.content));
+            }
+        },
+        Command::Show { id } => {
+            match db_schemas::get_bottle(db_path, &id).await? {
+                Some(bottle) => println!("{}", serde_json::to_string_pretty(&bottle)?),
+                None => println!("No bottle with id {}", id),
+            }
+        },
+        Command::Delete { id, wallet } => {
+            let ids = match id {
+                Some(id) => vec![id],
+                None => db_schemas::list_bottles(db_path, wallet.as_deref()).await?
+                    .into_iter()
+                    .map(|bottle| bottle.id)
+                    .collect(),
+            };
+            let deleted = db_schemas::delete_bottles(db_path, ids).await?;
+            println!("Deleted {} rows", deleted);
+        },
+        Command::Export { wallet, embeddings, output } => {
+            let mut writer: Box<dyn Write> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(BufWriter::new(std::io::stdout())),
+            };
+            let conn = Connection::open(db_path).await?;
+            let mut after_rowid = 0;
+            loop {
+                let batch = bottle_io::export_batch(&conn, after_rowid, wallet.clone(), embeddings, bottle_io::EXPORT_BATCH).await?;
+                let Some((last_rowid, _)) = batch.last() else {
+                    break;
+                };
+                after_rowid = *last_rowid;
+                for (_, record) in batch {
+                    writeln!(writer, "{}", serde_json::to_string(&record)?)?;
+                }
+            }
+            writer.flush()?;
+        },
+        Command::Query { text, k } => {
+            for (score, bottle) in db_schemas::search_drift_vec(&text, k, None).await? {
+                println!("{:.4}\t{}\t{}\t{}", score, bottle.id, bottle.wallet, bottle.title);
+            }
+        },
+        Command::Import { file } => {
+            let reader = BufReader::new(File::open(file)?);
+            let mut importer = Importer::new().await?;
+            for line in reader.lines() {
+                importer.push_line(&line?).await?;
+            }
+            let stats = importer.finish().await?;
+            println!("Imported {} rows ({} with precomputed embeddings), {} already present, {} malformed",
+                stats.imported, stats.reused_embeddings, stats.skipped, stats.failed);
+        },
+        Command::Vacuum => {
+            db_schemas::vacuum(db_path).await?;
+            println!("Vacuumed {}", db_path);
+        },
+        Command::Stats => {
+            let stats = db_schemas::db_stats(db_path).await?;
+            println!("bottle rows: {}", stats.bottle_rows);
+            println!("vector rows: {}", stats.vector_rows);
+            match stats.embedding_model {
+                Some((model, ndims)) => println!("embedding model: {} ({} dims)", model, ndims),
+                None => println!("embedding model: not recorded"),
+            }
+            println!("configured model: {} ({} dims)", vcdb_from_env.embedding_model_name, vcdb_from_env.embedding_ndim);
+            println!("wallets:");
+            for (wallet, count) in stats.wallets {
+                println!("  {}\t{}", wallet, count);
+            }
+        },
+        Command::Reembed { batch_size } => {
+            let total = embedding_meta::reembed_drift_bottles(&vcdb_from_env, batch_size).await?;
+            println!("Re-embedded {} rows with {}", total, vcdb_from_env.embedding_model_name);
+        },
+    }
+
+    Ok(())
+}
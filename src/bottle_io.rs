@@ -0,0 +1,272 @@
+use rig::{
+    embeddings::{Embedding, EmbeddingModel},
+    OneOrMany
+};
+use rig_sqlite::SqliteVectorStore;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tokio_rusqlite::Connection;
+
+use crate::anchoring;
+use crate::db_schemas::{self, DriftBottle, LisaEmbeddingModel, VectorDBFromEnv};
+use crate::embedding_meta;
+
+// JSONL dump format, one bottle row per line:
+// {"id": "12", "wallet": "0x..", "title": "night shift-0", "content": "...",
+//  "embedding": {"model": "BAAI/bge-m3", "ndims": 1024, "vector": [0.01, ...]}}
+//
+// Lines with an `id` are stored rows and are imported as they are.
+// Lines without an `id` are new bottles, they get chunked and titled like `store_drift_vec` does.
+// Either way title and content go through `validate_text` like `/api/store_drift`, and every
+// imported bottle gets a `bottle_meta` row and content hash, so pickup and delivery see it.
+// `embedding` is optional, and only reused when it matches the configured model.
+
+pub const EXPORT_BATCH: usize = 256;
+const IMPORT_BATCH: usize = 64;
+
+#[derive(Serialize, Deserialize)]
+pub struct BottleRecord {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub wallet: String,
+    pub title: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<EmbeddingRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmbeddingRecord {
+    pub model: String,
+    pub ndims: usize,
+    pub vector: Vec<f32>,
+}
+
+#[derive(Default, Serialize)]
+pub struct ImportStats {
+    pub imported: usize,
+    pub reused_embeddings: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Read the rows after `after_rowid` as export records. Returns the rowid of every record
+/// so callers can page through the table without holding it in memory.
+pub async fn export_batch(
+    conn: &Connection,
+    after_rowid: i64,
+    wallet: Option<String>,
+    with_embeddings: bool,
+    limit: usize
+) -> Result<Vec<(i64, BottleRecord)>, anyhow::Error> {
+    let model = if with_embeddings {
+        embedding_meta::stored_model(conn).await?
+    } else {
+        None
+    };
+
+    let rows = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT d.rowid, d.id, d.wallet, d.title, d.content, vec_to_json(e.embedding)
+            FROM drift_bottles d LEFT JOIN drift_bottles_embeddings e ON e.rowid = d.rowid
+            WHERE d.rowid > ?1 AND (?2 IS NULL OR d.wallet = ?2)
+            ORDER BY d.rowid LIMIT ?3"
+        )?;
+        let rows = stmt.query_map(params![after_rowid, wallet, limit as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                DriftBottle {
+                    id: row.get(1)?,
+                    wallet: row.get(2)?,
+                    title: row.get(3)?,
+                    content: row.get(4)?,
+                },
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(rows)
+    })
+    .await?;
+
+    let mut records = Vec::with_capacity(rows.len());
+    for (rowid, bottle, vector) in rows {
+        let embedding = match (&model, vector) {
+            (Some((model, ndims)), Some(vector)) => Some(EmbeddingRecord {
+                model: model.clone(),
+                ndims: *ndims,
+                vector: serde_json::from_str(&vector)?,
+            }),
+            _ => None,
+        };
+        records.push((rowid, BottleRecord {
+            id: Some(bottle.id),
+            wallet: bottle.wallet,
+            title: bottle.title,
+            content: bottle.content,
+            embedding,
+        }));
+    }
+
+    Ok(records)
+}
+
+/// Streaming importer: feed it one JSONL line at a time, rows are embedded and written in batches.
+pub struct Importer {
+    conn: Connection,
+    vector_store: SqliteVectorStore<LisaEmbeddingModel, DriftBottle>,
+    embedding_model: LisaEmbeddingModel,
+    model_name: String,
+    ndims: usize,
+    pending: Vec<BottleRecord>,
+    pub stats: ImportStats,
+}
+
+impl Importer {
+    pub async fn new() -> Result<Self, anyhow::Error> {
+        let vcdb_from_env = VectorDBFromEnv::new().await?;
+        let conn = Connection::open(&vcdb_from_env.db_path).await?;
+
+        let embedding_model = vcdb_from_env.embedding_model(None);
+        let vector_store = SqliteVectorStore::new(conn.clone(), &embedding_model).await?;
+        embedding_meta::record_model(&conn, &vcdb_from_env.embedding_model_name, vcdb_from_env.embedding_ndim).await?;
+
+        Ok(Self {
+            conn,
+            vector_store,
+            embedding_model,
+            model_name: vcdb_from_env.embedding_model_name,
+            ndims: vcdb_from_env.embedding_ndim,
+            pending: Vec::new(),
+            stats: ImportStats::default(),
+        })
+    }
+
+    pub async fn push_line(&mut self, line: &str) -> Result<(), anyhow::Error> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        match serde_json::from_str::<BottleRecord>(line) {
+            Ok(record) => self.pending.push(record),
+            Err(e) => {
+                self.stats.failed += 1;
+                println!("Skipping malformed line: {}", e);
+            }
+        }
+        if self.pending.len() >= IMPORT_BATCH {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> Result<ImportStats, anyhow::Error> {
+        self.flush().await?;
+        Ok(self.stats)
+    }
+
+    async fn flush(&mut self) -> Result<(), anyhow::Error> {
+        let records = std::mem::take(&mut self.pending);
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        // stored rows keep their id, skip the ones this database already has
+        let ids: Vec<String> = records.iter().filter_map(|r| r.id.clone()).collect();
+        let mut existing: Vec<String> = db_schemas::get_bottles_on(&self.conn, ids)
+            .await?
+            .into_iter()
+            .map(|bottle| bottle.id)
+            .collect();
+
+        let mut ready: Vec<(DriftBottle, OneOrMany<Embedding>)> = Vec::new();
+        let mut to_embed: Vec<DriftBottle> = Vec::new();
+
+        for record in records {
+            let valid = db_schemas::validate_text("title", &record.title)
+                .and_then(|_| db_schemas::validate_text("content", &record.content));
+            if let Err(e) = valid {
+                self.stats.failed += 1;
+                println!("Skipping invalid bottle {:?} of {}: {}", record.title, record.wallet, e);
+                continue;
+            }
+
+            let docs = match record.id {
+                Some(id) if existing.contains(&id) => {
+                    self.stats.skipped += 1;
+                    continue;
+                },
+                Some(id) => {
+                    db_schemas::reserve_id(&id);
+                    existing.push(id.clone());
+                    vec![DriftBottle {
+                        id,
+                        wallet: record.wallet,
+                        title: record.title,
+                        content: record.content,
+                    }]
+                },
+                None => db_schemas::chunk_bottle(&record.wallet, &record.title, &record.content),
+            };
+
+            match record.embedding {
+                Some(embedding) if docs.len() == 1
+                    && embedding.model == self.model_name
+                    && embedding.ndims == self.ndims
+                    && embedding.vector.len() == self.ndims => {
+                    let doc = docs.into_iter().next().unwrap();
+                    let embedding = Embedding {
+                        document: doc.content.clone(),
+                        vec: embedding.vector.iter().map(|x| *x as f64).collect(),
+                    };
+                    ready.push((doc, OneOrMany::one(embedding)));
+                    self.stats.reused_embeddings += 1;
+                },
+                _ => to_embed.extend(docs),
+            }
+        }
+
+        if !to_embed.is_empty() {
+            let embeddings = self.embedding_model
+                .embed_texts(to_embed.iter().map(|doc| doc.content.clone()))
+                .await?;
+            for (doc, embedding) in to_embed.into_iter().zip(embeddings) {
+                ready.push((doc, OneOrMany::one(embedding)));
+            }
+        }
+
+        let mut bottles: Vec<(String, String)> = ready.iter()
+            .map(|(doc, _)| (doc.wallet.clone(), bottle_title(&doc.title).to_string()))
+            .collect();
+        bottles.sort();
+        bottles.dedup();
+
+        let count = ready.len();
+        self.vector_store.add_rows(ready).await?;
+        self.stats.imported += count;
+
+        // the rows `/api/store_drift` writes when a bottle is queued and stored
+        self.conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            for (wallet, title) in &bottles {
+                tx.execute(
+                    "INSERT OR IGNORE INTO bottle_meta (wallet, title) VALUES (?1, ?2)",
+                    params![wallet, title],
+                )?;
+                anchoring::record_hash(&tx, wallet, title)?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+// "night shift-2" -> "night shift", the key `bottle_meta` uses
+fn bottle_title(chunk_title: &str) -> &str {
+    match chunk_title.rsplit_once('-') {
+        Some((title, index)) if index.parse::<usize>().is_ok() => title,
+        _ => chunk_title,
+    }
+}
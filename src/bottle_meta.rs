@@ -0,0 +1,124 @@
+use rusqlite::params;
+use tokio_rusqlite::Connection;
+
+use crate::db_schemas::InvalidText;
+
+// Per-bottle metadata next to the vector rows: tags the author set, the grade, when it was
+// written and its on-chain anchor (see `anchoring.rs`). Keyed by wallet and the bottle's title without the `-N` chunk suffix, the same pair
+// `bottle_exists` treats as unique. Bottles stored before this table have no row and count as
+// public, ungraded and old. Imported bottles get a public row dated at the import.
+
+const MAX_TAG_CHARS: usize = 32;
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS bottle_meta (
+                wallet TEXT NOT NULL,
+                title TEXT NOT NULL,
+                emotion TEXT,
+                topic TEXT,
+                public INTEGER NOT NULL DEFAULT 1,
+                grade INTEGER,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                PRIMARY KEY (wallet, title)
+            );
+            CREATE INDEX IF NOT EXISTS idx_bottle_meta_emotion ON bottle_meta(emotion);
+            CREATE INDEX IF NOT EXISTS idx_bottle_meta_topic ON bottle_meta(topic);"
+        )?;
+
+        // added with on-chain anchoring
+        let has_content_hash: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('bottle_meta') WHERE name = 'content_hash'",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_content_hash == 0 {
+            conn.execute_batch(
+                "ALTER TABLE bottle_meta ADD COLUMN content_hash TEXT;
+                ALTER TABLE bottle_meta ADD COLUMN anchor_tx TEXT;
+                ALTER TABLE bottle_meta ADD COLUMN anchored_at INTEGER;"
+            )?;
+        }
+
+        // added with hashing the stored text, older hashes are recomputed by `anchoring::backfill_hashes`
+        let has_hash_stored: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('bottle_meta') WHERE name = 'hash_stored'",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_hash_stored == 0 {
+            conn.execute("ALTER TABLE bottle_meta ADD COLUMN hash_stored INTEGER NOT NULL DEFAULT 0", [])?;
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Emotion and topic labels are short, lowercase and trimmed, so filters match exactly.
+pub fn normalize_tag(field: &'static str, tag: Option<&str>) -> Result<Option<String>, InvalidText> {
+    let Some(tag) = tag.map(|tag| tag.trim().to_lowercase()).filter(|tag| !tag.is_empty()) else {
+        return Ok(None);
+    };
+    if tag.chars().count() > MAX_TAG_CHARS {
+        return Err(InvalidText { field, reason: format!("at most {} characters allowed", MAX_TAG_CHARS) });
+    }
+    if tag.chars().any(|c| c.is_control()) {
+        return Err(InvalidText { field, reason: "contains control characters".to_string() });
+    }
+    Ok(Some(tag))
+}
+
+pub struct BottleTags {
+    pub emotion: Option<String>,
+    pub topic: Option<String>,
+    // private bottles are still searchable by similarity but never picked up or delivered
+    pub public: bool,
+}
+
+impl BottleTags {
+    pub fn new(emotion: Option<&str>, topic: Option<&str>, public: Option<bool>) -> Result<Self, InvalidText> {
+        Ok(Self {
+            emotion: normalize_tag("emotion", emotion)?,
+            topic: normalize_tag("topic", topic)?,
+            public: public.unwrap_or(true),
+        })
+    }
+}
+
+/// Called when the bottle is queued, `created_at` is the time the author sent it.
+/// The content hash is added once the bottle is stored, see `anchoring::record_hash`.
+pub async fn record(db_path: &str, wallet: &str, title: &str, tags: BottleTags) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (wallet, title) = (wallet.to_string(), title.to_string());
+
+    conn.call(move |conn| {
+        conn.execute(
+            "INSERT INTO bottle_meta (wallet, title, emotion, topic, public) VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (wallet, title) DO UPDATE SET emotion = excluded.emotion, topic = excluded.topic, public = excluded.public",
+            params![wallet, title, tags.emotion, tags.topic, tags.public],
+        )?;
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_grade(db_path: &str, wallet: &str, title: &str, grade: i16) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (wallet, title) = (wallet.to_string(), title.to_string());
+
+    conn.call(move |conn| {
+        conn.execute(
+            "INSERT INTO bottle_meta (wallet, title, grade) VALUES (?1, ?2, ?3)
+            ON CONFLICT (wallet, title) DO UPDATE SET grade = excluded.grade",
+            params![wallet, title, grade],
+        )?;
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
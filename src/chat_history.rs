@@ -0,0 +1,359 @@
+use rig::completion::Message;
+use rusqlite::{params, OptionalExtension};
+use tokio_rusqlite::Connection;
+
+use crate::agent_impl::{prompt_hub, RetrivalAgent};
+use crate::agent_impl::metering::{self, count_tokens, CompletionMeta};
+use crate::patron_memory;
+
+// Chat sessions and their history. The last turns of a session are sent to the model verbatim,
+// older turns are folded into `chat_sessions.summary` by a cheap model after a turn finishes.
+// `summarized_through` is the id of the last message already covered by the summary.
+
+const SUMMARY_MAX_TOKENS: u32 = 256;
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS chat_sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet TEXT NOT NULL,
+                persona TEXT NOT NULL,
+                summary TEXT NOT NULL DEFAULT '',
+                summarized_through INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE INDEX IF NOT EXISTS idx_chat_sessions_wallet ON chat_sessions(wallet);
+            CREATE TABLE IF NOT EXISTS chat_messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id INTEGER NOT NULL,
+                role TEXT NOT NULL,
+                content TEXT NOT NULL,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE INDEX IF NOT EXISTS idx_chat_messages_session ON chat_messages(session_id, id);"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn summary_model() -> String {
+    std::env::var("SUMMARY_MODEL_NAME").unwrap_or("Qwen/Qwen2.5-7B-Instruct".to_string())
+}
+
+pub async fn create_session(db_path: &str, wallet: &str, persona: &str) -> Result<i64, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (wallet, persona) = (wallet.to_string(), persona.to_string());
+
+    let id = conn.call(move |conn| {
+        conn.execute(
+            "INSERT INTO chat_sessions (wallet, persona) VALUES (?1, ?2)",
+            params![wallet, persona],
+        )?;
+        Ok(conn.last_insert_rowid())
+    })
+    .await?;
+
+    Ok(id)
+}
+
+/// Whether the session exists and belongs to the wallet. Only a claim until the request is
+/// checked with `wallet_auth::is_owner` too.
+pub async fn owns_session(db_path: &str, session_id: i64, wallet: &str) -> Result<bool, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let owner: Option<String> = conn.call(move |conn| {
+        let owner = conn.query_row(
+            "SELECT wallet FROM chat_sessions WHERE id = ?1",
+            [session_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+        Ok(owner)
+    })
+    .await?;
+
+    Ok(owner.as_deref() == Some(wallet.as_str()))
+}
+
+struct StoredMessage {
+    id: i64,
+    role: String,
+    content: String,
+}
+
+impl StoredMessage {
+    fn to_message(&self) -> Message {
+        match self.role.as_str() {
+            "assistant" => Message::assistant(&self.content),
+            _ => Message::user(&self.content),
+        }
+    }
+}
+
+// summary and the messages not folded into it yet, oldest first
+async fn load_unsummarized(conn: &Connection, session_id: i64) -> Result<(String, i64, Vec<StoredMessage>), anyhow::Error> {
+    let loaded = conn.call(move |conn| {
+        let (summary, summarized_through): (String, i64) = conn.query_row(
+            "SELECT summary, summarized_through FROM chat_sessions WHERE id = ?1",
+            [session_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let mut stmt = conn.prepare(
+            "SELECT id, role, content FROM chat_messages WHERE session_id = ?1 AND id > ?2 ORDER BY id"
+        )?;
+        let messages = stmt.query_map(params![session_id, summarized_through], |row| {
+            Ok(StoredMessage {
+                id: row.get(0)?,
+                role: row.get(1)?,
+                content: row.get(2)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok((summary, summarized_through, messages))
+    })
+    .await?;
+
+    Ok(loaded)
+}
+
+pub struct ChatContext {
+    pub summary: Option<String>,
+    pub history: Vec<Message>,
+    // estimated tokens of summary and history together
+    pub tokens: usize,
+}
+
+impl ChatContext {
+    pub fn summary_document(&self) -> Option<String> {
+        self.summary.as_ref().map(|summary| format!("Summary of the earlier conversation with this patron: {}", summary))
+    }
+}
+
+/// The context for the next turn: at most `keep_turns` recent turns verbatim plus the summary,
+/// trimmed from the oldest side until it fits in `budget_tokens`.
+pub async fn load_context(db_path: &str, session_id: i64, keep_turns: usize, budget_tokens: usize) -> Result<ChatContext, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (summary, _, messages) = load_unsummarized(&conn, session_id).await?;
+
+    let mut budget = budget_tokens;
+    let mut tokens = 0;
+
+    let summary = if summary.is_empty() {
+        None
+    } else {
+        let summary_tokens = count_tokens(&summary);
+        if summary_tokens <= budget {
+            budget -= summary_tokens;
+            tokens += summary_tokens;
+            Some(summary)
+        } else {
+            None
+        }
+    };
+
+    let mut kept: Vec<&StoredMessage> = Vec::new();
+    for message in messages.iter().rev().take(keep_turns * 2) {
+        let message_tokens = count_tokens(&message.content);
+        if message_tokens > budget {
+            break;
+        }
+        budget -= message_tokens;
+        tokens += message_tokens;
+        kept.push(message);
+    }
+    kept.reverse();
+
+    // a history should open with the patron speaking
+    while kept.first().map(|m| m.role != "user").unwrap_or(false) {
+        tokens -= count_tokens(&kept[0].content);
+        kept.remove(0);
+    }
+
+    Ok(ChatContext {
+        summary,
+        history: kept.iter().map(|m| m.to_message()).collect(),
+        tokens,
+    })
+}
+
+async fn append_turn(conn: &Connection, session_id: i64, prompt: String, reply: String) -> Result<(), anyhow::Error> {
+    conn.call(move |conn| {
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO chat_messages (session_id, role, content) VALUES (?1, 'user', ?2)",
+            params![session_id, prompt],
+        )?;
+        tx.execute(
+            "INSERT INTO chat_messages (session_id, role, content) VALUES (?1, 'assistant', ?2)",
+            params![session_id, reply],
+        )?;
+        tx.execute(
+            "UPDATE chat_sessions SET updated_at = unixepoch() WHERE id = ?1",
+            [session_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Fold everything older than the last `keep_turns` turns into the summary.
+pub async fn summarize(db_path: &str, session_id: i64, wallet: &str, keep_turns: usize) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (summary, summarized_through, messages) = load_unsummarized(&conn, session_id).await?;
+
+    if messages.len() <= keep_turns * 2 {
+        return Ok(());
+    }
+    let fold = &messages[..messages.len() - keep_turns * 2];
+    let Some(last_folded) = fold.last().map(|m| m.id) else {
+        return Ok(());
+    };
+
+    let transcript = fold.iter()
+        .map(|m| format!("{}: {}", if m.role == "user" { "Patron" } else { "Bartender" }, m.content))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let rendered = prompt_hub::render(prompt_hub::SESSION_SUMMARY, &[
+        ("summary", if summary.is_empty() { "(none)".to_string() } else { summary }),
+        ("transcript", transcript),
+        ("word_limit", prompt_hub::word_limit(SUMMARY_MAX_TOKENS).to_string()),
+    ]);
+
+    let model_name = summary_model();
+    let agent = RetrivalAgent::new_builder(
+        rendered.text.clone(),
+        Some(SUMMARY_MAX_TOKENS),
+        Some(0.3),
+        Some(model_name.clone())).await?
+        .build();
+    let meta = CompletionMeta::new(db_path, Some(wallet), &model_name, &rendered.text)
+        .with_prompt_version(&rendered.version);
+    let new_summary = metering::metered_prompt(&agent, &meta, "Write the updated summary.").await?;
+
+    // another turn may have summarized in the meantime, the first one wins
+    conn.call(move |conn| {
+        conn.execute(
+            "UPDATE chat_sessions SET summary = ?1, summarized_through = ?2, updated_at = unixepoch()
+            WHERE id = ?3 AND summarized_through = ?4",
+            params![new_summary.trim(), last_folded, session_id, summarized_through],
+        )?;
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Collects a streamed reply. When the stream is dropped the turn is stored,
+/// the session summarized and patron memories extracted in the background.
+pub struct TurnRecorder {
+    db_path: String,
+    session_id: i64,
+    wallet: String,
+    keep_turns: usize,
+    extract_memories: bool,
+    prompt: String,
+    reply: String,
+}
+
+impl TurnRecorder {
+    pub fn new(db_path: &str, session_id: i64, wallet: &str, keep_turns: usize, prompt: &str) -> Self {
+        Self {
+            db_path: db_path.to_string(),
+            session_id,
+            wallet: wallet.to_string(),
+            keep_turns,
+            extract_memories: false,
+            prompt: prompt.to_string(),
+            reply: String::new(),
+        }
+    }
+
+    pub fn with_memory_extraction(mut self, enabled: bool) -> Self {
+        self.extract_memories = enabled;
+        self
+    }
+
+    pub fn add_output(&mut self, text: &str) {
+        self.reply.push_str(text);
+    }
+}
+
+impl Drop for TurnRecorder {
+    fn drop(&mut self) {
+        if self.reply.is_empty() {
+            return;
+        }
+        let db_path = std::mem::take(&mut self.db_path);
+        let wallet = std::mem::take(&mut self.wallet);
+        let prompt = std::mem::take(&mut self.prompt);
+        let reply = std::mem::take(&mut self.reply);
+        let (session_id, keep_turns) = (self.session_id, self.keep_turns);
+
+        if self.extract_memories {
+            let (wallet, prompt, reply) = (wallet.clone(), prompt.clone(), reply.clone());
+            tokio::spawn(async move {
+                match patron_memory::extract(&wallet, session_id, &prompt, &reply).await {
+                    Ok(added) if added > 0 => println!("Remembered {} new facts about {}", added, wallet),
+                    Ok(_) => {},
+                    Err(e) => println!("Failed to extract memories of session {}: {}", session_id, e),
+                }
+            });
+        }
+
+        tokio::spawn(async move {
+            let stored = match Connection::open(&db_path).await {
+                Ok(conn) => append_turn(&conn, session_id, prompt, reply).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = stored {
+                println!("Failed to store turn of session {}: {}", session_id, e);
+                return;
+            }
+            if let Err(e) = summarize(&db_path, session_id, &wallet, keep_turns).await {
+                println!("Failed to summarize session {}: {}", session_id, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use crate::wallet_auth;
+
+    const OWNER: &str = "0xa11ce";
+    const OTHER: &str = "0xb0b";
+
+    async fn test_db(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("lisa-chat-history-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_string_lossy().to_string();
+        let conn = Connection::open(&path).await.unwrap();
+        init_tables(&conn).await.unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn a_session_is_not_continued_by_another_or_unsigned_wallet() {
+        let db_path = test_db("owner").await;
+        let session_id = create_session(&db_path, OWNER, "default").await.unwrap();
+
+        assert!(owns_session(&db_path, session_id, OWNER).await.unwrap());
+        assert!(!owns_session(&db_path, session_id, OTHER).await.unwrap());
+        assert!(!owns_session(&db_path, session_id + 1, OWNER).await.unwrap());
+
+        // naming the owner's wallet without its session token proves nothing
+        let req = TestRequest::default().to_http_request();
+        assert!(!wallet_auth::is_owner(&req, OWNER).await);
+    }
+}
@@ -0,0 +1,309 @@
+use rusqlite::{params, OptionalExtension, TransactionBehavior};
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+
+use crate::aptos_utils::{self, same_argument, same_function, TxStatus};
+use crate::payments::{self, PaidAction};
+
+// Prepaid credits in octas. A wallet deposits by sending `PAYMENT_FUNCTION` to `PAYMENT_RECEIVER`
+// with any amount (see `payments.rs`), paid actions then debit the balance without a transaction
+// each. The balance is the sum of the ledger:
+//
+// deposit  +amount   once per tx hash, a tx that settled a payment intent cannot be deposited
+// debit    -price    checked against the balance under a write lock
+// refund   +price    once per debit, when the work the debit paid for failed
+
+const LEDGER_PAGE: usize = 50;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CreditError {
+    #[error("Not enough credits: {balance_octas} octas left, {price_octas} needed")]
+    InsufficientCredits { balance_octas: i64, price_octas: i64 },
+    #[error("The transaction is not a payment to the deposit address")]
+    NotADeposit,
+    #[error("This transaction was already used")]
+    TxReused,
+    #[error("{0}")]
+    Unconfirmed(TxStatus),
+}
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS credit_ledger (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                amount_octas INTEGER NOT NULL,
+                action TEXT,
+                tx_hash TEXT UNIQUE,
+                refund_of INTEGER UNIQUE,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE INDEX IF NOT EXISTS idx_credit_ledger_wallet ON credit_ledger(wallet, id);"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Whether the tx already settled a payment intent or funded a deposit. `tx_hash` is normalized.
+pub(crate) fn tx_spent(conn: &rusqlite::Connection, tx_hash: &str) -> rusqlite::Result<bool> {
+    let spent = conn.query_row(
+        "SELECT 1 FROM payment_intents WHERE tx_hash = ?1
+        UNION ALL SELECT 1 FROM credit_ledger WHERE tx_hash = ?1",
+        [tx_hash],
+        |_| Ok(()),
+    )
+    .optional()?;
+    Ok(spent.is_some())
+}
+
+fn balance_of(conn: &rusqlite::Connection, wallet: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(amount_octas), 0) FROM credit_ledger WHERE wallet = ?1",
+        [wallet],
+        |row| row.get(0),
+    )
+}
+
+#[derive(Serialize)]
+pub struct LedgerEntry {
+    pub id: i64,
+    pub kind: String,
+    pub amount_octas: i64,
+    pub action: Option<String>,
+    pub tx_hash: Option<String>,
+    // for refunds, the debit given back
+    pub refund_of: Option<i64>,
+    pub created_at: i64,
+}
+
+fn entry(conn: &rusqlite::Connection, id: i64) -> rusqlite::Result<LedgerEntry> {
+    conn.query_row(
+        "SELECT id, kind, amount_octas, action, tx_hash, refund_of, created_at FROM credit_ledger WHERE id = ?1",
+        [id],
+        |row| Ok(LedgerEntry {
+            id: row.get(0)?,
+            kind: row.get(1)?,
+            amount_octas: row.get(2)?,
+            action: row.get(3)?,
+            tx_hash: row.get(4)?,
+            refund_of: row.get(5)?,
+            created_at: row.get(6)?,
+        }),
+    )
+}
+
+pub async fn balance(db_path: &str, wallet: &str) -> Result<i64, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let balance = conn.call(move |conn| Ok(balance_of(conn, &wallet)?)).await?;
+    Ok(balance)
+}
+
+/// Newest first, `before` pages back from a ledger id.
+pub async fn history(db_path: &str, wallet: &str, before: Option<i64>) -> Result<Vec<LedgerEntry>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let entries = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id FROM credit_ledger WHERE wallet = ?1 AND id < ?2 ORDER BY id DESC LIMIT ?3"
+        )?;
+        let ids = stmt.query_map(params![wallet, before.unwrap_or(i64::MAX), LEDGER_PAGE as i64], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<i64>, rusqlite::Error>>()?;
+        let entries = ids.into_iter()
+            .map(|id| entry(conn, id))
+            .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(entries)
+    })
+    .await?;
+
+    Ok(entries)
+}
+
+/// Credit the amount of a verified payment to the deposit address. Each tx is credited once.
+pub async fn deposit(db_path: &str, wallet: &str, tx_hash: &str) -> Result<LedgerEntry, anyhow::Error> {
+    let receiver = payments::receiver()?;
+    let function = payments::payment_function();
+    let tx_hash = aptos_utils::normalize_tx_hash(tx_hash);
+
+    let conn = Connection::open(db_path).await?;
+    let lookup_tx = tx_hash.clone();
+    if conn.call(move |conn| Ok(tx_spent(conn, &lookup_tx)?)).await? {
+        return Err(CreditError::TxReused.into());
+    }
+
+    let call = match aptos_utils::sent_call(&tx_hash, wallet, None, None).await? {
+        Ok(call) => call,
+        Err(TxStatus::Mismatch) => return Err(CreditError::NotADeposit.into()),
+        Err(status) => return Err(CreditError::Unconfirmed(status).into()),
+    };
+    let to_receiver = call.arguments.len() == 2
+        && same_argument(&call.arguments[0], &serde_json::Value::String(receiver.to_hex_literal()));
+    let amount = call.arguments.get(1)
+        .and_then(|amount| amount.as_str())
+        .and_then(|amount| amount.parse::<i64>().ok())
+        .filter(|amount| *amount > 0);
+    let (true, true, Some(amount)) = (same_function(&call.function, &function), to_receiver, amount) else {
+        return Err(CreditError::NotADeposit.into());
+    };
+
+    let wallet = wallet.to_string();
+    let entry = conn.call(move |conn| Ok(record_deposit(conn, &wallet, amount, &tx_hash)?)).await??;
+    Ok(entry)
+}
+
+/// Write a verified deposit, unless a concurrent deposit or intent settlement took the tx while
+/// the node was asked.
+fn record_deposit(conn: &mut rusqlite::Connection, wallet: &str, amount: i64, tx_hash: &str) -> rusqlite::Result<Result<LedgerEntry, CreditError>> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if tx_spent(&tx, tx_hash)? {
+        return Ok(Err(CreditError::TxReused));
+    }
+    tx.execute(
+        "INSERT INTO credit_ledger (wallet, kind, amount_octas, tx_hash) VALUES (?1, 'deposit', ?2, ?3)",
+        params![wallet, amount, tx_hash],
+    )?;
+    let entry = entry(&tx, tx.last_insert_rowid())?;
+    tx.commit()?;
+    Ok(Ok(entry))
+}
+
+/// Take the action's price from the balance. Keep the returned entry's id to refund it.
+pub async fn debit(db_path: &str, wallet: &str, action: PaidAction) -> Result<LedgerEntry, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+    let price_octas = action.price_octas() as i64;
+
+    let entry = conn.call(move |conn| {
+        // the write lock is taken before the balance is read, so two debits cannot both spend it
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let balance_octas = balance_of(&tx, &wallet)?;
+        if balance_octas < price_octas {
+            return Ok(Err(CreditError::InsufficientCredits { balance_octas, price_octas }));
+        }
+        tx.execute(
+            "INSERT INTO credit_ledger (wallet, kind, amount_octas, action) VALUES (?1, 'debit', ?2, ?3)",
+            params![wallet, -price_octas, action.as_str()],
+        )?;
+        let entry = entry(&tx, tx.last_insert_rowid())?;
+        tx.commit()?;
+        Ok(Ok(entry))
+    })
+    .await??;
+
+    Ok(entry)
+}
+
+/// Give a debit back. Returns false if it was already refunded or is not a debit.
+pub async fn refund(db_path: &str, debit_id: i64) -> Result<bool, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+
+    let refunded = conn.call(move |conn| {
+        let refunded = conn.execute(
+            "INSERT OR IGNORE INTO credit_ledger (wallet, kind, amount_octas, action, refund_of)
+            SELECT wallet, 'refund', -amount_octas, action, id FROM credit_ledger WHERE id = ?1 AND kind = 'debit'",
+            [debit_id],
+        )?;
+        Ok(refunded > 0)
+    })
+    .await?;
+
+    Ok(refunded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALLET: &str = "0xa11ce";
+
+    /// A fresh database file per test, the ledger is shared by several connections in some tests.
+    async fn test_db(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("lisa-credits-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_string_lossy().to_string();
+        let conn = Connection::open(&path).await.unwrap();
+        payments::init_tables(&conn).await.unwrap();
+        init_tables(&conn).await.unwrap();
+        path
+    }
+
+    async fn deposit_octas(db_path: &str, amount: i64, tx_hash: &str) -> Result<LedgerEntry, CreditError> {
+        let conn = Connection::open(db_path).await.unwrap();
+        let tx_hash = tx_hash.to_string();
+        conn.call(move |conn| Ok(record_deposit(conn, WALLET, amount, &tx_hash)?)).await.unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_debits_never_overdraw() {
+        let db_path = test_db("debits").await;
+        let price = PaidAction::Retrieve.price_octas() as i64;
+        deposit_octas(&db_path, price * 3, "0x01").await.unwrap();
+
+        let debits = (0..10).map(|_| {
+            let db_path = db_path.clone();
+            tokio::spawn(async move { debit(&db_path, WALLET, PaidAction::Retrieve).await })
+        });
+        let results = futures::future::join_all(debits).await;
+
+        let mut taken = 0;
+        for result in results {
+            match result.unwrap() {
+                Ok(_) => taken += 1,
+                Err(e) => assert!(matches!(e.downcast_ref::<CreditError>(), Some(CreditError::InsufficientCredits { .. }))),
+            }
+        }
+        assert_eq!(taken, 3);
+        assert_eq!(balance(&db_path, WALLET).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_debit_is_refunded_once() {
+        let db_path = test_db("refund").await;
+        let price = PaidAction::LongChat.price_octas() as i64;
+        deposit_octas(&db_path, price, "0x02").await.unwrap();
+
+        let debit = debit(&db_path, WALLET, PaidAction::LongChat).await.unwrap();
+        assert_eq!(balance(&db_path, WALLET).await.unwrap(), 0);
+        assert!(refund(&db_path, debit.id).await.unwrap());
+        assert!(!refund(&db_path, debit.id).await.unwrap());
+        assert_eq!(balance(&db_path, WALLET).await.unwrap(), price);
+
+        // only debits can be refunded
+        let deposit = history(&db_path, WALLET, None).await.unwrap().into_iter().find(|entry| entry.kind == "deposit").unwrap();
+        assert!(!refund(&db_path, deposit.id).await.unwrap());
+        assert_eq!(balance(&db_path, WALLET).await.unwrap(), price);
+    }
+
+    #[tokio::test]
+    async fn a_tx_is_deposited_once() {
+        let db_path = test_db("deposit").await;
+        deposit_octas(&db_path, 500, "0x03").await.unwrap();
+        assert!(matches!(deposit_octas(&db_path, 500, "0x03").await, Err(CreditError::TxReused)));
+        assert_eq!(balance(&db_path, WALLET).await.unwrap(), 500);
+    }
+
+    #[tokio::test]
+    async fn a_settled_intent_tx_cannot_be_deposited() {
+        let db_path = test_db("intent-tx").await;
+        let conn = Connection::open(&db_path).await.unwrap();
+        conn.call(|conn| {
+            conn.execute(
+                "INSERT INTO payment_intents (wallet, action, price_octas, payload, status, tx_hash, expires_at)
+                VALUES (?1, 'grade', 100, '{}', 'paid', '0x04', unixepoch() + 60)",
+                [WALLET],
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(deposit_octas(&db_path, 100, "0x04").await, Err(CreditError::TxReused)));
+        assert_eq!(balance(&db_path, WALLET).await.unwrap(), 0);
+    }
+}
@@ -1,19 +1,28 @@
 use rig::{
     embeddings::EmbeddingsBuilder,
-    providers::openai::Client,
+    providers::openai::{self, Client},
+    vector_store::VectorStoreIndex,
     Embed
 };
 use rig_sqlite::{Column, ColumnValue, SqliteVectorStore, SqliteVectorStoreTable};
+use rusqlite::ffi::sqlite3_auto_extension;
 use serde::{Deserialize, Serialize};
+use sqlite_vec::sqlite3_vec_init;
 use tokio_rusqlite::Connection;
 use regex::Regex;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::LazyLock;
+
+use crate::{anchoring, bottle_meta, chat_history, credits, delivery, embedding_cache, embedding_meta, jobs, moderation, patron_memory, paywall, payments, pickup, quota, reactions, replies, usage, wallet_auth};
+use crate::embedding_cache::CachedEmbeddingModel;
+use crate::reactions::ReactionCounts;
+use crate::agent_impl::metering::MeteredEmbeddingModel;
 
 //
 //  ==================== Low-Level Database Schema ====================
 //
-#[derive(Embed, Clone, Debug, Deserialize)]
+#[derive(Embed, Clone, Debug, Serialize, Deserialize)]
 pub struct DriftBottle {
     pub id: String,
     pub wallet: String,
@@ -102,6 +111,13 @@ pub fn get_next_id() -> usize {
     GLOBAL_ID.fetch_add(1, Ordering::Relaxed) // 原子操作，线程安全
 }
 
+// make sure ids handed out later don't collide with an id written from outside, e.g. an import
+pub fn reserve_id(id: &str) {
+    if let Ok(id) = id.parse::<usize>() {
+        GLOBAL_ID.fetch_max(id + 1, Ordering::Relaxed);
+    }
+}
+
 const DOCUMENT_STRIDE: usize = 510;
 
 pub struct VectorDBFromEnv {
@@ -133,22 +149,107 @@ impl VectorDBFromEnv {
             model_name
         })
     }
+
+    /// The configured embedding model behind the embedding cache, cache misses are metered against `wallet`.
+    pub fn embedding_model(&self, wallet: Option<&str>) -> LisaEmbeddingModel {
+        let openai_client = Client::from_url(&self.openai_api_key, &self.base_url);
+        let embedding_model = openai_client.embedding_model_with_ndims(&self.embedding_model_name, self.embedding_ndim);
+        let metered = MeteredEmbeddingModel::new(embedding_model, &self.embedding_model_name, &self.db_path, wallet);
+        CachedEmbeddingModel::new(metered, &self.embedding_model_name, &self.db_path)
+    }
+}
+
+pub type LisaEmbeddingModel = CachedEmbeddingModel<MeteredEmbeddingModel<openai::EmbeddingModel>>;
+
+// Initialize the `sqlite-vec` extension for every connection opened afterwards.
+// See: https://alexgarcia.xyz/sqlite-vec/rust.html
+pub fn register_sqlite_vec() {
+    unsafe {
+        sqlite3_auto_extension(Some(std::mem::transmute(sqlite3_vec_init as *const ())));
+    }
+}
+
+// create the bookkeeping tables, the vector tables themselves are created by `SqliteVectorStore::new`
+pub async fn init_db(db_path: &str) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    embedding_meta::init_tables(&conn).await?;
+    quota::init_tables(&conn).await?;
+    usage::init_tables(&conn).await?;
+    jobs::init_tables(&conn).await?;
+    embedding_cache::init_tables(&conn).await?;
+    chat_history::init_tables(&conn).await?;
+    patron_memory::init_tables(&conn).await?;
+    wallet_auth::init_tables(&conn).await?;
+    replies::init_tables(&conn).await?;
+    bottle_meta::init_tables(&conn).await?;
+    pickup::init_tables(&conn).await?;
+    delivery::init_tables(&conn).await?;
+    reactions::init_tables(&conn).await?;
+    moderation::init_tables(&conn).await?;
+    payments::init_tables(&conn).await?;
+    credits::init_tables(&conn).await?;
+    paywall::init_tables(&conn).await?;
+    anchoring::backfill_hashes(&conn).await?;
+
+    // ids come from an in-memory counter, continue after the largest stored id
+    let max_id: Option<i64> = conn.call(|conn| {
+        let max_id = conn.query_row("SELECT MAX(CAST(id AS INTEGER)) FROM drift_bottles", [], |row| row.get(0));
+        match max_id {
+            Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("no such table") => Ok(None),
+            other => Ok(other?),
+        }
+    })
+    .await?;
+    if let Some(max_id) = max_id {
+        reserve_id(&max_id.to_string());
+    }
+
+    Ok(())
 }
 
+// compiled once, the output guard counts words on every streamed whitespace
+static WORD_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b[\w\p{P}]+\b").unwrap());
+
 pub fn count_sequence_len(input_str: &str) -> usize {
-    let word_re = Regex::new(r"\b[\w\p{P}]+\b").unwrap();
-    let words: Vec<&str> = word_re.find_iter(input_str).map(|mat| mat.as_str()).collect();
-    words.len()
+    WORD_RE.find_iter(input_str).count()
 }
 
-pub async fn store_drift_vec(wallet: &str, title: &str, content: &str) -> Result<(), anyhow::Error>{
-    // load from env vars
+#[derive(Debug, thiserror::Error)]
+#[error("This document has already been stored")]
+pub struct DuplicateBottle;
 
-    let vcdb_from_env = VectorDBFromEnv::new().await?;
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid {field}: {reason}")]
+pub struct InvalidText {
+    pub field: &'static str,
+    pub reason: String,
+}
 
-    // start building
-    // check if this document has already been stored
-    let conn = Connection::open(&vcdb_from_env.db_path).await?;
+fn max_words() -> usize {
+    std::env::var("MAX_CONTENT_WORDS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(5000)
+}
+
+/// Checks shared by everything patrons write into the database: bottles, titles and replies.
+pub fn validate_text(field: &'static str, text: &str) -> Result<(), InvalidText> {
+    let invalid = |reason: String| Err(InvalidText { field, reason });
+
+    if text.trim().is_empty() {
+        return invalid("must not be empty".to_string());
+    }
+    if text.chars().any(|c| c.is_control() && c != '\n' && c != '\r' && c != '\t') {
+        return invalid("contains control characters".to_string());
+    }
+    let words = count_sequence_len(text);
+    if words > max_words() {
+        return invalid(format!("{} words, at most {} allowed", words, max_words()));
+    }
+    Ok(())
+}
+
+pub async fn bottle_exists(conn: &Connection, wallet: &str, title: &str) -> Result<bool, anyhow::Error> {
     let wallet_clone = wallet.to_string();
     let title_clone = format!("{}-{}", title.to_string(), 0);
 
@@ -169,31 +270,58 @@ pub async fn store_drift_vec(wallet: &str, title: &str, content: &str) -> Result
 
         Ok(searched_docs)
     })
-    .await
-    .unwrap_or_else(|e| {
-        if e.to_string().contains("no such table") {
-            return Vec::new();  // handle error: table not created yet, so continue to store. The table and schema will be created later.
-        }
-        panic!("Error querying database: {}", e);
-    });
+    .await;
 
-    if stored_repeated.len() > 0 {
-        return Err(anyhow::Error::msg("This document has already been stored"));
+    match stored_repeated {
+        Ok(docs) => Ok(docs.len() > 0),
+        // table not created yet, so continue to store. The table and schema will be created later.
+        Err(e) if e.to_string().contains("no such table") => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn store_drift_vec(wallet: &str, title: &str, content: &str) -> Result<(), anyhow::Error>{
+    // load from env vars
+
+    let vcdb_from_env = VectorDBFromEnv::new().await?;
+
+    validate_text("title", title)?;
+    validate_text("content", content)?;
+
+    // start building
+    // check if this document has already been stored
+    let conn = Connection::open(&vcdb_from_env.db_path).await?;
+    if bottle_exists(&conn, wallet, title).await? {
+        return Err(DuplicateBottle.into());
     }
 
     // store this doc
-    let openai_client = Client::from_url(&vcdb_from_env.openai_api_key, &vcdb_from_env.base_url);
-    let embedding_model = openai_client.embedding_model_with_ndims(&vcdb_from_env.embedding_model_name, 
-        vcdb_from_env.embedding_ndim);
-    let vector_store: SqliteVectorStore<rig::providers::openai::EmbeddingModel, DriftBottle> = SqliteVectorStore::new(conn, &embedding_model).await?;
+    let embedding_model = vcdb_from_env.embedding_model(Some(wallet));
+    let meta_conn = conn.clone();
+    let vector_store: SqliteVectorStore<LisaEmbeddingModel, DriftBottle> = SqliteVectorStore::new(conn, &embedding_model).await?;
+    embedding_meta::record_model(&meta_conn, &vcdb_from_env.embedding_model_name, vcdb_from_env.embedding_ndim).await?;
+
+    let docs = chunk_bottle(wallet, title, content);
+
+    let embeddings = EmbeddingsBuilder::new(embedding_model)
+        .documents(docs)?
+        .build()
+        .await?;
+
+    // save it to db
+    vector_store.add_rows(embeddings).await?;
+
+    Ok(())
+}
 
+// split a bottle into rows of at most `DOCUMENT_STRIDE` words, titled title-0, title-1, ...
+pub fn chunk_bottle(wallet: &str, title: &str, content: &str) -> Vec<DriftBottle> {
     // Notice: the length of the passage, is not the length of the string.
     // The granularity of the passage is word-level, not character-level.
     let mut docs: Vec<DriftBottle> = Vec::new();
     let mut start = 0;
 
-    let word_re = Regex::new(r"\b[\w\p{P}]+\b").unwrap();
-    let words: Vec<&str> = word_re.find_iter(content).map(|mat| mat.as_str()).collect();
+    let words: Vec<&str> = WORD_RE.find_iter(content).map(|mat| mat.as_str()).collect();
 
     while start < words.len() {
         let end = std::cmp::min(start + DOCUMENT_STRIDE, words.len());
@@ -210,23 +338,159 @@ pub async fn store_drift_vec(wallet: &str, title: &str, content: &str) -> Result
         start = end;
     }
 
-    let embeddings = EmbeddingsBuilder::new(embedding_model)
-        .documents(docs)?
-        .build()
-        .await?;
+    docs
+}
 
-    // save it to db
-    vector_store.add_rows(embeddings).await?;
+// `wallet` is who the query embedding is metered against, not a filter
+pub async fn search_drift_vec(query: &str, top_n: usize, wallet: Option<&str>) -> Result<Vec<(f64, DriftBottle)>, anyhow::Error> {
+    let vcdb_from_env = VectorDBFromEnv::new().await?;
+    let conn = Connection::open(&vcdb_from_env.db_path).await?;
+
+    let embedding_model = vcdb_from_env.embedding_model(wallet);
+    let vector_store: SqliteVectorStore<LisaEmbeddingModel, DriftBottle> = SqliteVectorStore::new(conn, &embedding_model).await?;
+    let vector_index = vector_store.index(embedding_model);
+
+    let results = vector_index
+        .top_n::<DriftBottle>(query, top_n)
+        .await?
+        .into_iter()
+        .map(|(score, _id, doc)| (score, doc))
+        .collect::<Vec<_>>();
+
+    Ok(results)
+}
+
+fn bottle_from_row(row: &rusqlite::Row) -> rusqlite::Result<DriftBottle> {
+    Ok(DriftBottle {
+        id: row.get(0)?,
+        wallet: row.get(1)?,
+        title: row.get(2)?,
+        content: row.get(3)?
+    })
+}
+
+pub async fn list_bottles(db_path: &str, wallet: Option<&str>) -> Result<Vec<DriftBottle>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.map(|w| w.to_string());
+
+    let bottles = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, wallet, title, content FROM drift_bottles WHERE ?1 IS NULL OR wallet = ?1 ORDER BY rowid"
+        )?;
+        let bottles = stmt.query_map([wallet], bottle_from_row)?
+            .collect::<std::result::Result<Vec<DriftBottle>, rusqlite::Error>>()?;
+        Ok(bottles)
+    })
+    .await?;
+
+    Ok(bottles)
+}
 
+pub async fn get_bottle(db_path: &str, id: &str) -> Result<Option<DriftBottle>, anyhow::Error> {
+    Ok(get_bottles(db_path, vec![id.to_string()]).await?.pop())
+}
+
+pub async fn get_bottles(db_path: &str, ids: Vec<String>) -> Result<Vec<DriftBottle>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    get_bottles_on(&conn, ids).await
+}
+
+pub async fn get_bottles_on(conn: &Connection, ids: Vec<String>) -> Result<Vec<DriftBottle>, anyhow::Error> {
+    let bottles = conn.call(move |conn| {
+        let mut stmt = conn.prepare("SELECT id, wallet, title, content FROM drift_bottles WHERE id = ?1")?;
+        let mut bottles = Vec::new();
+        for id in ids {
+            let mut rows = stmt.query_map([id], bottle_from_row)?;
+            if let Some(bottle) = rows.next() {
+                bottles.push(bottle?);
+            }
+        }
+        Ok(bottles)
+    })
+    .await?;
+
+    Ok(bottles)
+}
+
+/// Delete bottle rows together with their vectors. Returns the number of deleted rows.
+pub async fn delete_bottles(db_path: &str, ids: Vec<String>) -> Result<usize, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+
+    let deleted = conn.call(move |conn| {
+        let tx = conn.transaction()?;
+        let mut deleted = 0;
+        for id in ids {
+            tx.execute(
+                "DELETE FROM drift_bottles_embeddings WHERE rowid IN (SELECT rowid FROM drift_bottles WHERE id = ?1)",
+                [&id],
+            )?;
+            deleted += tx.execute("DELETE FROM drift_bottles WHERE id = ?1", [&id])?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    })
+    .await?;
+
+    Ok(deleted)
+}
+
+#[derive(Debug, Serialize)]
+pub struct DbStats {
+    pub bottle_rows: usize,
+    pub vector_rows: usize,
+    pub wallets: Vec<(String, usize)>,
+    pub embedding_model: Option<(String, usize)>,
+}
+
+pub async fn db_stats(db_path: &str) -> Result<DbStats, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let embedding_model = embedding_meta::stored_model(&conn).await?;
+
+    let (bottle_rows, vector_rows, wallets) = conn.call(|conn| {
+        let bottle_rows: i64 = conn.query_row("SELECT COUNT(*) FROM drift_bottles", [], |row| row.get(0))?;
+        let vector_rows: i64 = conn.query_row("SELECT COUNT(*) FROM drift_bottles_embeddings", [], |row| row.get(0))?;
+
+        let mut stmt = conn.prepare(
+            "SELECT wallet, COUNT(*) FROM drift_bottles GROUP BY wallet ORDER BY COUNT(*) DESC"
+        )?;
+        let wallets = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as usize)))?
+            .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok((bottle_rows as usize, vector_rows as usize, wallets))
+    })
+    .await?;
+
+    Ok(DbStats {
+        bottle_rows,
+        vector_rows,
+        wallets,
+        embedding_model,
+    })
+}
+
+pub async fn vacuum(db_path: &str) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    conn.call(|conn| {
+        conn.execute_batch("VACUUM")?;
+        Ok(())
+    })
+    .await?;
     Ok(())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DocInfo {
     pub id: String,
+    // bottles are anonymous, the author's wallet stays on the server
+    #[serde(skip_serializing)]
     pub user: String,
     pub title: String,
     pub content: String,
+    #[serde(default)]
+    pub reactions: ReactionCounts,
+    // tx recording the bottle's content hash on chain
+    #[serde(default)]
+    pub anchor_tx: Option<String>,
 }
 
 pub fn parse_markdown_text(text: &str) -> Result<Vec<DocInfo>, String> {
@@ -262,7 +526,9 @@ pub fn parse_markdown_text(text: &str) -> Result<Vec<DocInfo>, String> {
             id,
             user,
             title,
-            content
+            content,
+            reactions: ReactionCounts::default(),
+            anchor_tx: None
         })
     }
 
@@ -0,0 +1,296 @@
+use std::time::Duration;
+
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+
+use crate::pickup::{self, PickedBottle};
+
+// Bottles washing ashore: a background scheduler hands every new public bottle to a few random
+// wallets, each copy lands in the recipient's inbox after a random delay.
+//
+// scheduled (inbox row, delivered_at NULL) -> delivered at deliver_at
+//                                          -> dropped, if the recipient got blocked or hit the daily limit
+//
+// Recipients are wallets active in the last DELIVERY_ACTIVE_DAYS, never the author, a blocked
+// wallet or someone who has already seen the bottle.
+//
+// DELIVERY_RECIPIENTS="3"            copies per bottle
+// DELIVERY_DAILY_LIMIT="3"           bottles a wallet receives per UTC day at most
+// DELIVERY_DELAY_MIN_SECS="600"      random delay range
+// DELIVERY_DELAY_MAX_SECS="21600"
+// DELIVERY_ACTIVE_DAYS="30"
+// DELIVERY_MAX_AGE_SECS="86400"      only bottles this new are scheduled, so old ones are not flushed at once
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+// newest inbox items returned by `/api/inbox`
+const INBOX_LIMIT: usize = 50;
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS delivery_schedule (
+                bottle_id TEXT PRIMARY KEY,
+                recipients INTEGER NOT NULL,
+                scheduled_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE TABLE IF NOT EXISTS inbox (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet TEXT NOT NULL,
+                bottle_id TEXT NOT NULL,
+                deliver_at INTEGER NOT NULL,
+                delivered_at INTEGER,
+                read_at INTEGER,
+                UNIQUE (wallet, bottle_id)
+            );
+            CREATE INDEX IF NOT EXISTS idx_inbox_pending ON inbox(delivered_at, deliver_at);
+            CREATE INDEX IF NOT EXISTS idx_inbox_wallet ON inbox(wallet, delivered_at);
+            CREATE TABLE IF NOT EXISTS blocked_wallets (
+                wallet TEXT PRIMARY KEY,
+                reason TEXT,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn env_i64(name: &str, default: i64) -> i64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default)
+}
+
+struct DeliverySettings {
+    recipients: i64,
+    daily_limit: i64,
+    delay_min_secs: i64,
+    delay_max_secs: i64,
+    active_days: i64,
+    max_age_secs: i64,
+}
+
+impl DeliverySettings {
+    fn from_env() -> Self {
+        let delay_min_secs = env_i64("DELIVERY_DELAY_MIN_SECS", 600).max(0);
+        Self {
+            recipients: env_i64("DELIVERY_RECIPIENTS", 3),
+            daily_limit: env_i64("DELIVERY_DAILY_LIMIT", 3),
+            delay_min_secs,
+            delay_max_secs: env_i64("DELIVERY_DELAY_MAX_SECS", 21600).max(delay_min_secs),
+            active_days: env_i64("DELIVERY_ACTIVE_DAYS", 30),
+            max_age_secs: env_i64("DELIVERY_MAX_AGE_SECS", 86400),
+        }
+    }
+}
+
+/// Pick recipients for bottles that have not been scheduled yet. Returns the number of inbox rows.
+fn schedule_new(conn: &mut rusqlite::Connection, settings: &DeliverySettings) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    let bottles = {
+        let mut stmt = tx.prepare(
+            "SELECT b.id, b.wallet FROM bottle_meta m
+            JOIN drift_bottles b ON b.wallet = m.wallet AND b.title = m.title || '-0'
+            WHERE m.public = 1 AND m.created_at >= unixepoch() - ?1
+                AND NOT EXISTS (SELECT 1 FROM hidden_bottles h WHERE h.bottle_id = b.id)
+                AND NOT EXISTS (SELECT 1 FROM delivery_schedule d WHERE d.bottle_id = b.id)"
+        )?;
+        stmt.query_map([settings.max_age_secs], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?
+    };
+
+    let mut scheduled = 0;
+    for (bottle_id, author) in bottles {
+        let recipients = tx.execute(
+            "INSERT OR IGNORE INTO inbox (wallet, bottle_id, deliver_at)
+            SELECT active.wallet, ?1, unixepoch() + ?3 + abs(random() % (?4 - ?3 + 1)) FROM (
+                SELECT wallet FROM usage_events WHERE wallet IS NOT NULL AND created_at >= unixepoch() - ?5 * 86400
+                UNION
+                SELECT wallet FROM seen_bottles WHERE seen_at >= unixepoch() - ?5 * 86400
+            ) AS active
+            WHERE active.wallet != ?2
+                AND active.wallet NOT IN (SELECT wallet FROM blocked_wallets)
+                AND NOT EXISTS (SELECT 1 FROM seen_bottles s WHERE s.wallet = active.wallet AND s.bottle_id = ?1)
+            ORDER BY random() LIMIT ?6",
+            params![bottle_id, author, settings.delay_min_secs, settings.delay_max_secs, settings.active_days, settings.recipients],
+        )?;
+        tx.execute(
+            "INSERT INTO delivery_schedule (bottle_id, recipients) VALUES (?1, ?2)",
+            params![bottle_id, recipients as i64],
+        )?;
+        scheduled += recipients;
+    }
+    tx.commit()?;
+    Ok(scheduled)
+}
+
+/// Deliver the copies that are due, oldest first. Returns (delivered, dropped).
+fn deliver_due(conn: &mut rusqlite::Connection, settings: &DeliverySettings) -> rusqlite::Result<(usize, usize)> {
+    let tx = conn.transaction()?;
+    let due = {
+        let mut stmt = tx.prepare(
+            "SELECT id, wallet, bottle_id FROM inbox WHERE delivered_at IS NULL AND deliver_at <= unixepoch() ORDER BY deliver_at"
+        )?;
+        stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+            .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?
+    };
+
+    let (mut delivered, mut dropped) = (0, 0);
+    for (id, wallet, bottle_id) in due {
+        let blocked = tx.query_row("SELECT 1 FROM blocked_wallets WHERE wallet = ?1", [&wallet], |_| Ok(()))
+            .optional()?
+            .is_some();
+        let received_today: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM inbox WHERE wallet = ?1 AND delivered_at >= unixepoch('now', 'start of day')",
+            [&wallet],
+            |row| row.get(0),
+        )?;
+
+        if blocked || received_today >= settings.daily_limit {
+            tx.execute("DELETE FROM inbox WHERE id = ?1", [id])?;
+            dropped += 1;
+            continue;
+        }
+        tx.execute("UPDATE inbox SET delivered_at = unixepoch() WHERE id = ?1", [id])?;
+        // a delivered bottle is never picked up again
+        tx.execute(
+            "INSERT OR IGNORE INTO seen_bottles (wallet, bottle_id) VALUES (?1, ?2)",
+            params![wallet, bottle_id],
+        )?;
+        delivered += 1;
+    }
+    tx.commit()?;
+    Ok((delivered, dropped))
+}
+
+pub async fn run_scheduler(db_path: String) {
+    let conn = match Connection::open(&db_path).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Delivery scheduler failed to open {}: {}", db_path, e);
+            return;
+        }
+    };
+
+    loop {
+        let result = conn.call(|conn| {
+            let settings = DeliverySettings::from_env();
+            let scheduled = schedule_new(conn, &settings)?;
+            let (delivered, dropped) = deliver_due(conn, &settings)?;
+            Ok((scheduled, delivered, dropped))
+        })
+        .await;
+
+        match result {
+            Ok((0, 0, 0)) => {},
+            Ok((scheduled, delivered, dropped)) => {
+                println!("Delivery: {} scheduled, {} delivered, {} dropped", scheduled, delivered, dropped);
+            },
+            // `drift_bottles` only exists after the first bottle is stored
+            Err(e) if e.to_string().contains("no such table") => {},
+            Err(e) => eprintln!("Delivery scheduler failed: {}", e),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[derive(Serialize)]
+pub struct InboxItem {
+    pub id: i64,
+    pub bottle: PickedBottle,
+    pub delivered_at: i64,
+    pub read: bool,
+}
+
+/// Delivered bottles, newest first. Listing marks them read.
+pub async fn inbox(db_path: &str, wallet: &str) -> Result<Vec<InboxItem>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let items = conn.call(move |conn| {
+        let tx = conn.transaction()?;
+        let rows = {
+            let mut stmt = tx.prepare(
+                "SELECT i.id, i.delivered_at, i.read_at IS NOT NULL, b.id, b.wallet, b.title, m.emotion, m.topic, m.grade, m.created_at, m.anchor_tx
+                FROM inbox i
+                JOIN drift_bottles b ON b.id = i.bottle_id
+                LEFT JOIN bottle_meta m ON m.wallet = b.wallet AND b.title = m.title || '-0'
+                WHERE i.wallet = ?1 AND i.delivered_at IS NOT NULL
+                    AND NOT EXISTS (SELECT 1 FROM hidden_bottles h WHERE h.bottle_id = i.bottle_id)
+                ORDER BY i.delivered_at DESC, i.id DESC LIMIT ?2"
+            )?;
+            stmt.query_map(params![wallet, INBOX_LIMIT as i64], |row| {
+                let title: String = row.get(5)?;
+                Ok((
+                    InboxItem {
+                        id: row.get(0)?,
+                        delivered_at: row.get(1)?,
+                        read: row.get(2)?,
+                        bottle: PickedBottle {
+                            id: row.get(3)?,
+                            title: title.strip_suffix("-0").unwrap_or(&title).to_string(),
+                            content: String::new(),
+                            emotion: row.get(6)?,
+                            topic: row.get(7)?,
+                            grade: row.get(8)?,
+                            created_at: row.get(9)?,
+                            anchor_tx: row.get(10)?,
+                        },
+                    },
+                    row.get::<_, String>(4)?,
+                ))
+            })?
+            .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?
+        };
+
+        let mut items = Vec::new();
+        for (mut item, author) in rows {
+            item.bottle.content = pickup::full_content(&tx, &author, &item.bottle.title)?;
+            items.push(item);
+        }
+        tx.execute(
+            "UPDATE inbox SET read_at = unixepoch() WHERE wallet = ?1 AND delivered_at IS NOT NULL AND read_at IS NULL",
+            [&wallet],
+        )?;
+        tx.commit()?;
+        Ok(items)
+    })
+    .await?;
+
+    Ok(items)
+}
+
+pub async fn block_wallet(db_path: &str, wallet: &str, reason: Option<&str>) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (wallet, reason) = (wallet.to_string(), reason.map(|r| r.to_string()));
+
+    conn.call(move |conn| {
+        conn.execute(
+            "INSERT INTO blocked_wallets (wallet, reason) VALUES (?1, ?2)
+            ON CONFLICT (wallet) DO UPDATE SET reason = excluded.reason",
+            params![wallet, reason],
+        )?;
+        // copies on their way to the wallet are dropped right away
+        conn.execute("DELETE FROM inbox WHERE wallet = ?1 AND delivered_at IS NULL", [&wallet])?;
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Returns false when the wallet was not blocked.
+pub async fn unblock_wallet(db_path: &str, wallet: &str) -> Result<bool, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let removed = conn.call(move |conn| {
+        Ok(conn.execute("DELETE FROM blocked_wallets WHERE wallet = ?1", [wallet])?)
+    })
+    .await?;
+
+    Ok(removed > 0)
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use aptos_sdk::crypto::HashValue;
+use lru::LruCache;
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+use rig::embeddings::{Embedding, EmbeddingError, EmbeddingModel};
+
+// Two-tier embedding cache keyed by (model, ndims, hash of the normalized text):
+// an in-process LRU in front of the `embedding_cache` table, so repeated queries
+// survive restarts and are shared by the server and lisa-admin.
+
+static MEMORY_CACHE: LazyLock<Mutex<LruCache<String, Vec<f64>>>> = LazyLock::new(|| {
+    let size = std::env::var("EMBEDDING_CACHE_SIZE")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .and_then(NonZeroUsize::new)
+        .unwrap_or(NonZeroUsize::new(2048).unwrap());
+    Mutex::new(LruCache::new(size))
+});
+
+static MEMORY_HITS: AtomicU64 = AtomicU64::new(0);
+static DB_HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS embedding_cache (
+                model TEXT NOT NULL,
+                ndims INTEGER NOT NULL,
+                text_hash TEXT NOT NULL,
+                vector TEXT NOT NULL,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                PRIMARY KEY (model, ndims, text_hash)
+            );"
+        )?;
+
+        // added with case-sensitive keys: rows hashed from lowercased text would answer for
+        // texts that only differ in case, so they are dropped once
+        let has_key_version: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('embedding_cache') WHERE name = 'key_version'",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_key_version == 0 {
+            conn.execute_batch(
+                "DELETE FROM embedding_cache;
+                ALTER TABLE embedding_cache ADD COLUMN key_version INTEGER NOT NULL DEFAULT 2;"
+            )?;
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+// whitespace differences should not cost another embedding call, case can change the vector
+fn text_hash(text: &str) -> String {
+    let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    HashValue::sha3_256_of(normalized.as_bytes()).to_hex()
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub memory_hits: u64,
+    pub db_hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub memory_entries: usize,
+    pub db_entries: usize,
+}
+
+pub async fn stats(db_path: &str) -> Result<CacheStats, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let db_entries: i64 = conn.call(|conn| {
+        Ok(conn.query_row("SELECT COUNT(*) FROM embedding_cache", [], |row| row.get(0))?)
+    })
+    .await?;
+
+    let memory_hits = MEMORY_HITS.load(Ordering::Relaxed);
+    let db_hits = DB_HITS.load(Ordering::Relaxed);
+    let misses = MISSES.load(Ordering::Relaxed);
+    let total = memory_hits + db_hits + misses;
+
+    Ok(CacheStats {
+        memory_hits,
+        db_hits,
+        misses,
+        hit_rate: if total == 0 { 0.0 } else { (memory_hits + db_hits) as f64 / total as f64 },
+        memory_entries: MEMORY_CACHE.lock().unwrap().len(),
+        db_entries: db_entries as usize,
+    })
+}
+
+#[derive(Clone)]
+pub struct CachedEmbeddingModel<M: EmbeddingModel> {
+    inner: M,
+    model_name: String,
+    db_path: String,
+}
+
+impl<M: EmbeddingModel> CachedEmbeddingModel<M> {
+    pub fn new(inner: M, model_name: &str, db_path: &str) -> Self {
+        Self {
+            inner,
+            model_name: model_name.to_string(),
+            db_path: db_path.to_string(),
+        }
+    }
+
+    fn memory_key(&self, hash: &str) -> String {
+        format!("{}:{}:{}", self.model_name, self.inner.ndims(), hash)
+    }
+
+    async fn load(&self, hashes: Vec<String>) -> Result<HashMap<String, Vec<f64>>, anyhow::Error> {
+        let conn = Connection::open(&self.db_path).await?;
+        let model_name = self.model_name.clone();
+        let ndims = self.inner.ndims() as i64;
+
+        let rows = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT vector FROM embedding_cache WHERE model = ?1 AND ndims = ?2 AND text_hash = ?3"
+            )?;
+            let mut rows = Vec::new();
+            for hash in hashes {
+                let vector: Option<String> = stmt.query_row(params![model_name, ndims, hash], |row| row.get(0)).optional()?;
+                if let Some(vector) = vector {
+                    rows.push((hash, vector));
+                }
+            }
+            Ok(rows)
+        })
+        .await?;
+
+        let mut found = HashMap::new();
+        for (hash, vector) in rows {
+            found.insert(hash, serde_json::from_str(&vector)?);
+        }
+        Ok(found)
+    }
+
+    async fn save(&self, entries: Vec<(String, Vec<f64>)>) -> Result<(), anyhow::Error> {
+        let conn = Connection::open(&self.db_path).await?;
+        let model_name = self.model_name.clone();
+        let ndims = self.inner.ndims() as i64;
+
+        let mut rows = Vec::with_capacity(entries.len());
+        for (hash, vector) in entries {
+            rows.push((hash, serde_json::to_string(&vector)?));
+        }
+
+        conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            for (hash, vector) in rows {
+                tx.execute(
+                    "INSERT OR REPLACE INTO embedding_cache (model, ndims, text_hash, vector) VALUES (?1, ?2, ?3, ?4)",
+                    params![model_name, ndims, hash, vector],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl<M: EmbeddingModel> EmbeddingModel for CachedEmbeddingModel<M> {
+    const MAX_DOCUMENTS: usize = M::MAX_DOCUMENTS;
+
+    fn ndims(&self) -> usize {
+        self.inner.ndims()
+    }
+
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String> + Send,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        let texts: Vec<String> = texts.into_iter().collect();
+        let hashes: Vec<String> = texts.iter().map(|text| text_hash(text)).collect();
+        let mut vectors: Vec<Option<Vec<f64>>> = vec![None; texts.len()];
+
+        // tier 1: memory
+        {
+            let mut memory = MEMORY_CACHE.lock().unwrap();
+            for (i, hash) in hashes.iter().enumerate() {
+                if let Some(vector) = memory.get(&self.memory_key(hash)) {
+                    vectors[i] = Some(vector.clone());
+                    MEMORY_HITS.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        // tier 2: sqlite, a broken cache table only costs the savings
+        let db_lookup: Vec<String> = hashes.iter().zip(&vectors)
+            .filter(|(_, vector)| vector.is_none())
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        if !db_lookup.is_empty() {
+            let found = self.load(db_lookup).await.unwrap_or_else(|e| {
+                println!("Embedding cache lookup failed: {}", e);
+                HashMap::new()
+            });
+            let mut memory = MEMORY_CACHE.lock().unwrap();
+            for (i, hash) in hashes.iter().enumerate() {
+                if vectors[i].is_none() {
+                    if let Some(vector) = found.get(hash) {
+                        memory.put(self.memory_key(hash), vector.clone());
+                        vectors[i] = Some(vector.clone());
+                        DB_HITS.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        // misses go to the model in one request
+        let missing: Vec<usize> = (0..texts.len()).filter(|i| vectors[*i].is_none()).collect();
+        if !missing.is_empty() {
+            MISSES.fetch_add(missing.len() as u64, Ordering::Relaxed);
+            let embedded = self.inner
+                .embed_texts(missing.iter().map(|i| texts[*i].clone()))
+                .await?;
+            if embedded.len() != missing.len() {
+                return Err(EmbeddingError::ResponseError(format!(
+                    "expected {} embeddings, the model returned {}", missing.len(), embedded.len()
+                )));
+            }
+
+            let mut entries = Vec::with_capacity(missing.len());
+            {
+                let mut memory = MEMORY_CACHE.lock().unwrap();
+                for (i, embedding) in missing.iter().zip(embedded) {
+                    memory.put(self.memory_key(&hashes[*i]), embedding.vec.clone());
+                    entries.push((hashes[*i].clone(), embedding.vec.clone()));
+                    vectors[*i] = Some(embedding.vec);
+                }
+            }
+            if let Err(e) = self.save(entries).await {
+                println!("Embedding cache write failed: {}", e);
+            }
+        }
+
+        texts.into_iter()
+            .zip(vectors)
+            .map(|(document, vector)| match vector {
+                Some(vec) => Ok(Embedding { document, vec }),
+                None => Err(EmbeddingError::ResponseError("the model returned no embedding for a text".to_string())),
+            })
+            .collect()
+    }
+}
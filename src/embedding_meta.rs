@@ -0,0 +1,340 @@
+use rig::embeddings::EmbeddingModel;
+use regex::Regex;
+use rusqlite::{params, OptionalExtension};
+use tokio_rusqlite::Connection;
+
+use crate::db_schemas::VectorDBFromEnv;
+
+// Every vector table is produced by exactly one (model, ndims) pair.
+// sqlite-vec fixes the dimension when the vec0 table is created, so once a row
+// is embedded with a model, the whole table has to stay on that model.
+
+const VECTOR_TABLE: &str = "drift_bottles_embeddings";
+const STAGING_TABLE: &str = "drift_bottles_embeddings_reembed";
+
+pub enum EmbeddingCheck {
+    /// No vector table yet, it will be created with the configured model.
+    Untracked,
+    Match,
+    Mismatch {
+        stored_model: String,
+        stored_ndims: usize,
+    },
+}
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS embedding_meta (
+                vector_table TEXT PRIMARY KEY,
+                model TEXT NOT NULL,
+                ndims INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE TABLE IF NOT EXISTS reembed_jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                model TEXT NOT NULL,
+                ndims INTEGER NOT NULL,
+                last_rowid INTEGER NOT NULL DEFAULT 0,
+                status TEXT NOT NULL DEFAULT 'running',
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Record the model of a freshly created vector table. An existing record is never overwritten,
+/// switching models must go through `reembed_drift_bottles`.
+pub async fn record_model(conn: &Connection, model: &str, ndims: usize) -> Result<(), anyhow::Error> {
+    let model = model.to_string();
+    conn.call(move |conn| {
+        conn.execute(
+            "INSERT OR IGNORE INTO embedding_meta (vector_table, model, ndims) VALUES (?1, ?2, ?3)",
+            params![VECTOR_TABLE, model, ndims as i64],
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+pub async fn stored_model(conn: &Connection) -> Result<Option<(String, usize)>, anyhow::Error> {
+    let stored = conn.call(|conn| {
+        let row = conn.query_row(
+            "SELECT model, ndims FROM embedding_meta WHERE vector_table = ?1",
+            [VECTOR_TABLE],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as usize)),
+        )
+        .optional()?;
+        Ok(row)
+    })
+    .await?;
+    Ok(stored)
+}
+
+// read the dimension back from the vec0 definition, e.g. `vec0(embedding float[1024])`
+async fn vector_table_ndims(conn: &Connection, table: &'static str) -> Result<Option<usize>, anyhow::Error> {
+    let sql: Option<String> = conn.call(move |conn| {
+        let sql = conn.query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |row| row.get(0),
+        )
+        .optional()?;
+        Ok(sql)
+    })
+    .await?;
+
+    let ndims_re = Regex::new(r"float\[(\d+)\]").unwrap();
+    Ok(sql.and_then(|sql| {
+        ndims_re.captures(&sql)
+            .and_then(|c| c.get(1))
+            .and_then(|m| m.as_str().parse().ok())
+    }))
+}
+
+/// Compare the embedding model configured in `.env` with the one that produced the stored vectors.
+pub async fn check_embedding_model(vcdb_from_env: &VectorDBFromEnv) -> Result<EmbeddingCheck, anyhow::Error> {
+    let conn = Connection::open(&vcdb_from_env.db_path).await?;
+    init_tables(&conn).await?;
+
+    let (stored_model, stored_ndims) = match stored_model(&conn).await? {
+        Some(stored) => stored,
+        None => match vector_table_ndims(&conn, VECTOR_TABLE).await? {
+            None => return Ok(EmbeddingCheck::Untracked),
+            Some(ndims) if ndims == vcdb_from_env.embedding_ndim => {
+                // database created before the model was tracked, the dimension is all we can verify.
+                println!("Embedding model was not recorded, assuming {} ({} dims).",
+                    vcdb_from_env.embedding_model_name, ndims);
+                record_model(&conn, &vcdb_from_env.embedding_model_name, ndims).await?;
+                return Ok(EmbeddingCheck::Match);
+            }
+            Some(ndims) => ("unknown".to_string(), ndims),
+        },
+    };
+
+    if stored_model == vcdb_from_env.embedding_model_name && stored_ndims == vcdb_from_env.embedding_ndim {
+        Ok(EmbeddingCheck::Match)
+    } else {
+        Ok(EmbeddingCheck::Mismatch { stored_model, stored_ndims })
+    }
+}
+
+/// Re-embed every row of `drift_bottles` with the configured model into a staging vec0 table,
+/// then copy it into a recreated `drift_bottles_embeddings` in one transaction.
+///
+/// Progress is committed together with each batch, so an interrupted run resumes where it stopped.
+/// Run it while the server is stopped: the server refuses to start on a model mismatch anyway.
+pub async fn reembed_drift_bottles(vcdb_from_env: &VectorDBFromEnv, batch_size: usize) -> Result<usize, anyhow::Error> {
+    let conn = Connection::open(&vcdb_from_env.db_path).await?;
+    init_tables(&conn).await?;
+
+    let model_name = vcdb_from_env.embedding_model_name.clone();
+    let ndims = vcdb_from_env.embedding_ndim;
+
+    // resume a running job for the same target model, otherwise start over
+    let (job_id, mut last_rowid) = conn.call(move |conn| {
+        let running = conn.query_row(
+            "SELECT id, model, ndims, last_rowid FROM reembed_jobs WHERE status = 'running' ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?)),
+        )
+        .optional()?;
+
+        if let Some((id, model, job_ndims, last_rowid)) = running {
+            if model == model_name && job_ndims as usize == ndims {
+                return Ok((id, last_rowid));
+            }
+            conn.execute("UPDATE reembed_jobs SET status = 'abandoned', updated_at = unixepoch() WHERE id = ?1", [id])?;
+        }
+
+        create_staging(conn, ndims)?;
+        conn.execute(
+            "INSERT INTO reembed_jobs (model, ndims) VALUES (?1, ?2)",
+            params![model_name, ndims as i64],
+        )?;
+        Ok((conn.last_insert_rowid(), 0))
+    })
+    .await?;
+
+    if last_rowid > 0 {
+        println!("Resuming re-embedding job {} after rowid {}", job_id, last_rowid);
+    }
+
+    let embedding_model = vcdb_from_env.embedding_model(None);
+
+    let mut total = 0;
+    loop {
+        let batch: Vec<(i64, String)> = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT rowid, content FROM drift_bottles WHERE rowid > ?1 ORDER BY rowid LIMIT ?2"
+            )?;
+            let rows = stmt.query_map(params![last_rowid, batch_size as i64], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+            Ok(rows)
+        })
+        .await?;
+
+        if batch.is_empty() {
+            break;
+        }
+
+        let embeddings = embedding_model
+            .embed_texts(batch.iter().map(|(_, content)| content.clone()))
+            .await?;
+
+        let mut rows = Vec::with_capacity(batch.len());
+        for ((rowid, _), embedding) in batch.iter().zip(embeddings) {
+            if embedding.vec.len() != ndims {
+                return Err(anyhow::anyhow!("Model returned {} dims, expected {}", embedding.vec.len(), ndims));
+            }
+            let vector: Vec<f32> = embedding.vec.iter().map(|x| *x as f32).collect();
+            rows.push((*rowid, serde_json::to_string(&vector)?));
+        }
+
+        last_rowid = batch.last().map(|(rowid, _)| *rowid).unwrap_or(last_rowid);
+        total += rows.len();
+
+        conn.call(move |conn| Ok(stage_batch(conn, job_id, &rows, last_rowid)?)).await?;
+
+        println!("Re-embedded up to rowid {} ({} rows this run)", last_rowid, total);
+    }
+
+    let model_name = vcdb_from_env.embedding_model_name.clone();
+    conn.call(move |conn| Ok(swap_in_staging(conn, job_id, &model_name, ndims)?)).await?;
+
+    Ok(total)
+}
+
+fn create_staging(conn: &rusqlite::Connection, ndims: usize) -> rusqlite::Result<()> {
+    conn.execute_batch(&format!(
+        "DROP TABLE IF EXISTS {STAGING_TABLE};
+        CREATE VIRTUAL TABLE {STAGING_TABLE} USING vec0(embedding float[{ndims}]);"
+    ))
+}
+
+/// Write one batch of `(rowid, vector as JSON)` and the job's progress together. A rowid staged
+/// twice is an error, it would mean a resumed job processed a batch again.
+fn stage_batch(conn: &mut rusqlite::Connection, job_id: i64, rows: &[(i64, String)], last_rowid: i64) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    for (rowid, vector) in rows {
+        tx.execute(
+            &format!("INSERT INTO {STAGING_TABLE} (rowid, embedding) VALUES (?1, ?2)"),
+            params![rowid, vector],
+        )?;
+    }
+    tx.execute(
+        "UPDATE reembed_jobs SET last_rowid = ?1, updated_at = unixepoch() WHERE id = ?2",
+        params![last_rowid, job_id],
+    )?;
+    tx.commit()
+}
+
+/// Replace the search table with the staged vectors in one transaction. The table is recreated
+/// under its own name and filled from staging rather than renamed: the search always reads
+/// `drift_bottles_embeddings`, and vec0 keeps its data in shadow tables named after the table.
+fn swap_in_staging(conn: &mut rusqlite::Connection, job_id: i64, model_name: &str, ndims: usize) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(&format!(
+        "DROP TABLE IF EXISTS {VECTOR_TABLE};
+        CREATE VIRTUAL TABLE {VECTOR_TABLE} USING vec0(embedding float[{ndims}]);
+        INSERT INTO {VECTOR_TABLE} (rowid, embedding) SELECT rowid, embedding FROM {STAGING_TABLE};
+        DROP TABLE {STAGING_TABLE};"
+    ))?;
+    tx.execute(
+        "INSERT OR REPLACE INTO embedding_meta (vector_table, model, ndims, updated_at) VALUES (?1, ?2, ?3, unixepoch())",
+        params![VECTOR_TABLE, model_name, ndims as i64],
+    )?;
+    tx.execute(
+        "UPDATE reembed_jobs SET status = 'done', updated_at = unixepoch() WHERE id = ?1",
+        [job_id],
+    )?;
+    tx.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_schemas::register_sqlite_vec;
+
+    /// An in-memory database with a 3-dim search table and a running job re-embedding to 2 dims.
+    fn reembedding_db() -> (rusqlite::Connection, i64) {
+        register_sqlite_vec();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!(
+            "CREATE TABLE embedding_meta (
+                vector_table TEXT PRIMARY KEY,
+                model TEXT NOT NULL,
+                ndims INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE TABLE reembed_jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                model TEXT NOT NULL,
+                ndims INTEGER NOT NULL,
+                last_rowid INTEGER NOT NULL DEFAULT 0,
+                status TEXT NOT NULL DEFAULT 'running',
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE VIRTUAL TABLE {VECTOR_TABLE} USING vec0(embedding float[3]);
+            INSERT INTO {VECTOR_TABLE} (rowid, embedding) VALUES (1, '[1, 0, 0]'), (2, '[0, 1, 0]');
+            INSERT INTO embedding_meta (vector_table, model, ndims) VALUES ('{VECTOR_TABLE}', 'old-model', 3);
+            INSERT INTO reembed_jobs (model, ndims) VALUES ('new-model', 2);"
+        ))
+        .unwrap();
+        create_staging(&conn, 2).unwrap();
+        (conn, 1)
+    }
+
+    fn nearest(conn: &rusqlite::Connection, vector: &str) -> i64 {
+        conn.query_row(
+            &format!("SELECT rowid FROM {VECTOR_TABLE} WHERE embedding MATCH ?1 AND k = 1 ORDER BY distance"),
+            [vector],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn swapped_table_is_searchable() {
+        let (mut conn, job_id) = reembedding_db();
+        stage_batch(&mut conn, job_id, &[(1, "[1, 0]".to_string())], 1).unwrap();
+        stage_batch(&mut conn, job_id, &[(2, "[0, 1]".to_string())], 2).unwrap();
+        swap_in_staging(&mut conn, job_id, "new-model", 2).unwrap();
+
+        assert_eq!(nearest(&conn, "[0.9, 0.1]"), 1);
+        assert_eq!(nearest(&conn, "[0.1, 0.9]"), 2);
+
+        let count: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {VECTOR_TABLE}"), [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 2);
+        let staging: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = ?1",
+            [STAGING_TABLE],
+            |row| row.get(0),
+        )
+        .unwrap();
+        assert_eq!(staging, 0);
+        let (model, ndims, status): (String, i64, String) = conn.query_row(
+            "SELECT m.model, m.ndims, j.status FROM embedding_meta m, reembed_jobs j WHERE m.vector_table = ?1 AND j.id = ?2",
+            params![VECTOR_TABLE, job_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+        assert_eq!((model.as_str(), ndims, status.as_str()), ("new-model", 2, "done"));
+    }
+
+    #[test]
+    fn staging_a_row_twice_fails_and_keeps_progress() {
+        let (mut conn, job_id) = reembedding_db();
+        stage_batch(&mut conn, job_id, &[(1, "[1, 0]".to_string())], 1).unwrap();
+        assert!(stage_batch(&mut conn, job_id, &[(1, "[1, 0]".to_string())], 5).is_err());
+
+        let last_rowid: i64 = conn.query_row("SELECT last_rowid FROM reembed_jobs WHERE id = ?1", [job_id], |row| row.get(0)).unwrap();
+        assert_eq!(last_rowid, 1);
+    }
+}
@@ -0,0 +1,239 @@
+use std::time::Duration;
+
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+
+use crate::anchoring;
+use crate::db_schemas::{self, DuplicateBottle, InvalidText};
+
+// Persistent ingestion queue: `/api/store_drift` only inserts a job here,
+// a background worker chunks, embeds and stores the bottle.
+//
+// queued -> embedding -> stored
+//                     -> queued again with backoff, until INGEST_MAX_ATTEMPTS -> failed
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const BACKOFF_BASE_SECS: i64 = 5;
+const BACKOFF_MAX_SECS: i64 = 300;
+
+#[derive(Serialize)]
+pub struct IngestJob {
+    pub id: i64,
+    // job ids are sequential, the status response must not tell who stored what
+    #[serde(skip)]
+    pub wallet: String,
+    #[serde(skip)]
+    pub title: String,
+    #[serde(skip)]
+    pub content: String,
+    pub status: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS ingest_jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet TEXT NOT NULL,
+                title TEXT NOT NULL,
+                content TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'queued',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_run_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                last_error TEXT,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE INDEX IF NOT EXISTS idx_ingest_jobs_status ON ingest_jobs(status, next_run_at);"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn max_attempts() -> i64 {
+    std::env::var("INGEST_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(5)
+}
+
+fn job_from_row(row: &rusqlite::Row) -> rusqlite::Result<IngestJob> {
+    Ok(IngestJob {
+        id: row.get(0)?,
+        wallet: row.get(1)?,
+        title: row.get(2)?,
+        content: row.get(3)?,
+        status: row.get(4)?,
+        attempts: row.get(5)?,
+        last_error: row.get(6)?,
+        created_at: row.get(7)?,
+        updated_at: row.get(8)?,
+    })
+}
+
+const JOB_COLUMNS: &str = "id, wallet, title, content, status, attempts, last_error, created_at, updated_at";
+
+pub async fn enqueue(db_path: &str, wallet: &str, title: &str, content: &str) -> Result<i64, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (wallet, title, content) = (wallet.to_string(), title.to_string(), content.to_string());
+
+    let id = conn.call(move |conn| {
+        conn.execute(
+            "INSERT INTO ingest_jobs (wallet, title, content) VALUES (?1, ?2, ?3)",
+            params![wallet, title, content],
+        )?;
+        Ok(conn.last_insert_rowid())
+    })
+    .await?;
+
+    Ok(id)
+}
+
+pub async fn get_job(db_path: &str, id: i64) -> Result<Option<IngestJob>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+
+    let job = conn.call(move |conn| {
+        let job = conn.query_row(
+            &format!("SELECT {JOB_COLUMNS} FROM ingest_jobs WHERE id = ?1"),
+            [id],
+            job_from_row,
+        )
+        .optional()?;
+        Ok(job)
+    })
+    .await?;
+
+    Ok(job)
+}
+
+/// Whether the wallet already has a job for this title that has not failed.
+pub async fn has_pending_job(db_path: &str, wallet: &str, title: &str) -> Result<bool, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (wallet, title) = (wallet.to_string(), title.to_string());
+
+    let pending: i64 = conn.call(move |conn| {
+        let pending = conn.query_row(
+            "SELECT COUNT(*) FROM ingest_jobs WHERE wallet = ?1 AND title = ?2 AND status IN ('queued', 'embedding')",
+            params![wallet, title],
+            |row| row.get(0),
+        )?;
+        Ok(pending)
+    })
+    .await?;
+
+    Ok(pending > 0)
+}
+
+// take the oldest due job and mark it as embedding
+async fn claim_next(conn: &Connection) -> Result<Option<IngestJob>, anyhow::Error> {
+    let job = conn.call(|conn| {
+        let tx = conn.transaction()?;
+        let job = tx.query_row(
+            &format!("SELECT {JOB_COLUMNS} FROM ingest_jobs
+                WHERE status = 'queued' AND next_run_at <= unixepoch()
+                ORDER BY next_run_at, id LIMIT 1"),
+            [],
+            job_from_row,
+        )
+        .optional()?;
+        if let Some(job) = &job {
+            tx.execute(
+                "UPDATE ingest_jobs SET status = 'embedding', attempts = attempts + 1, updated_at = unixepoch() WHERE id = ?1",
+                [job.id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(job)
+    })
+    .await?;
+
+    Ok(job)
+}
+
+async fn finish_job(conn: &Connection, job: &IngestJob, result: Result<(), anyhow::Error>) -> Result<(), anyhow::Error> {
+    let max_attempts = max_attempts();
+    let (id, attempts) = (job.id, job.attempts + 1);
+    let (wallet, title) = (job.wallet.clone(), job.title.clone());
+
+    conn.call(move |conn| {
+        match result {
+            Ok(()) => {
+                // the hash covers what was stored, so a failed job leaves no hash behind
+                anchoring::record_hash(conn, &wallet, &title)?;
+                conn.execute(
+                    "UPDATE ingest_jobs SET status = 'stored', last_error = NULL, updated_at = unixepoch() WHERE id = ?1",
+                    [id],
+                )?;
+            },
+            // retrying cannot fix a duplicate or invalid text
+            Err(e) if e.is::<DuplicateBottle>() || e.is::<InvalidText>() || attempts >= max_attempts => {
+                conn.execute(
+                    "UPDATE ingest_jobs SET status = 'failed', last_error = ?1, updated_at = unixepoch() WHERE id = ?2",
+                    params![e.to_string(), id],
+                )?;
+            },
+            Err(e) => {
+                let backoff = (BACKOFF_BASE_SECS << (attempts - 1).clamp(0, 16)).min(BACKOFF_MAX_SECS);
+                conn.execute(
+                    "UPDATE ingest_jobs SET status = 'queued', last_error = ?1, next_run_at = unixepoch() + ?2, updated_at = unixepoch() WHERE id = ?3",
+                    params![e.to_string(), backoff, id],
+                )?;
+            },
+        }
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Drain the queue forever. Jobs left in `embedding` by a crash are picked up again on start.
+pub async fn run_worker(db_path: String) {
+    let conn = match Connection::open(&db_path).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Ingestion worker failed to open {}: {}", db_path, e);
+            return;
+        }
+    };
+
+    let requeued = conn.call(|conn| {
+        Ok(conn.execute("UPDATE ingest_jobs SET status = 'queued' WHERE status = 'embedding'", [])?)
+    })
+    .await;
+    if let Ok(requeued) = requeued {
+        if requeued > 0 {
+            println!("Requeued {} interrupted ingestion jobs", requeued);
+        }
+    }
+
+    loop {
+        let job = match claim_next(&conn).await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            },
+            Err(e) => {
+                eprintln!("Ingestion worker failed to claim a job: {}", e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        let result = db_schemas::store_drift_vec(&job.wallet, &job.title, &job.content).await;
+        if let Err(e) = &result {
+            println!("Ingestion job {} attempt {} failed: {}", job.id, job.attempts + 1, e);
+        }
+        if let Err(e) = finish_job(&conn, &job, result).await {
+            eprintln!("Failed to update ingestion job {}: {}", job.id, e);
+        }
+    }
+}
@@ -0,0 +1,30 @@
+pub mod db_schemas;
+pub mod agent_impl;
+pub mod request_model;
+pub mod aptos_utils;
+pub mod embedding_meta;
+pub mod bottle_io;
+pub mod admin;
+pub mod quota;
+pub mod rate_limit;
+pub mod usage;
+pub mod jobs;
+pub mod embedding_cache;
+pub mod personas;
+pub mod chat_history;
+pub mod patron_memory;
+pub mod output_guard;
+pub mod rerank;
+pub mod query_pipeline;
+pub mod replies;
+pub mod bottle_meta;
+pub mod pickup;
+pub mod delivery;
+pub mod reactions;
+pub mod moderation;
+pub mod anchoring;
+pub mod payments;
+pub mod credits;
+pub mod paywall;
+pub mod membership;
+pub mod wallet_auth;
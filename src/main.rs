@@ -2,28 +2,34 @@
 
 use rig::providers::openai;
 use dotenvy::dotenv;
-use rig::completion::Prompt;
-use rig::streaming::{StreamingPrompt, StreamingChoice};
+use rig::tool::Tool;
+use rig::streaming::{StreamingChat, StreamingChoice};
 
-pub mod db_schemas;
-pub mod agent_impl;
-pub mod request_model;
-pub mod test_sqlite_vec;
-pub mod aptos_utils;
+use lisa::{db_schemas, agent_impl, request_model, aptos_utils, embedding_meta, admin, rate_limit, jobs, personas, chat_history, patron_memory, query_pipeline, replies, bottle_meta, pickup, delivery, reactions, moderation, anchoring, payments, credits, paywall, membership, wallet_auth};
 
-use request_model::{ChatRequest, GeneralReponse, RetriveRequest, RetriveResponse, GradeBottleRequest, GradeBottleResponse};
-use agent_impl::{RetrivalAgent, prompt_hub, RetrivalTool};
-use aptos_utils::verify_tx;
+use request_model::{ChatRequest, GeneralReponse, RetriveRequest, RetriveResponse, GradeBottleRequest, GradeBottleResponse, StoreDriftResponse, JobStatusResponse, PersonaListResponse, MemoryListResponse, MemoryDeleteResponse, ReplyRequest, ReplyResponse, ReplyListResponse, UnreadRepliesResponse, PickupRequest, PickupResponse, InboxResponse, ReactionRequest, ReactionResponse, ReportRequest, ReportResponse, AnchorPayloadResponse, AnchorRequest, AnchorResponse, PaymentIntentRequest, PaymentIntentResponse, CreditsResponse, LedgerResponse, DepositRequest, DepositResponse, MembershipResponse, AuthChallengeRequest, AuthChallengeResponse, AuthSessionRequest, AuthSessionResponse, MemoryOptInRequest};
+use agent_impl::{RetrivalAgent, RetrivalTool};
+use db_schemas::{DocInfo, DuplicateBottle, VectorDBFromEnv};
+use embedding_meta::EmbeddingCheck;
+use agent_impl::metering::{CompletionMeta, StreamMeter};
+use rate_limit::RateLimiter;
+use chat_history::TurnRecorder;
+use replies::ReplyError;
+use reactions::ReactionError;
+use anchoring::AnchorError;
+use aptos_utils::TxStatus;
+use payments::{PaidAction, PaymentError};
+use credits::CreditError;
+use wallet_auth::AuthError;
 
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder, Error};
-use actix_web::middleware::Logger;
+use actix_web::{delete, get, post, put, web, App, HttpRequest, HttpResponse, HttpServer, Responder, Error};
+use actix_web::middleware::{from_fn, Logger};
 use actix_cors::Cors;
 use env_logger::Env;
-use rusqlite::ffi::sqlite3_auto_extension;
-use sqlite_vec::sqlite3_vec_init;
 
 use futures::{future::ok, stream::once};
 use futures::StreamExt; // 关键引入
+use tokio_rusqlite::Connection;
 
 
 
@@ -43,16 +49,67 @@ async fn ping() -> actix_web::Result<impl Responder> {
 
 // this API will be streaming response
 #[post("/api/chat")]
-async fn chat(json: web::Json<ChatRequest>) -> HttpResponse {
-    let _wallet = &json.wallet;
+async fn chat(req: HttpRequest, json: web::Json<ChatRequest>) -> HttpResponse {
+    let wallet = &json.wallet;
     let prompt = &json.content;
 
-    let sys_prompt = prompt_hub::CHAT_AGENT_SYS_PROMPT;
+    let Some(persona) = personas::get(json.persona.as_deref()) else {
+        return HttpResponse::BadRequest().json(GeneralReponse {
+            status: "Unknown persona".to_string()
+        });
+    };
+    let model_name = persona.model.as_str();
+
+    let tier = membership::tier(&req, wallet).await;
+    if persona.premium && !tier.is_premium() {
+        return HttpResponse::Forbidden().json(GeneralReponse {
+            status: "This persona is for premium members".to_string()
+        });
+    }
 
-    let mut max_tokens = 64;
-    if db_schemas::count_sequence_len(prompt) > 64 {
-        max_tokens = 128;
+    let long = json.long.unwrap_or(false);
+    let signed_in = wallet_auth::is_owner(&req, wallet).await;
+    // long replies are paid from the wallet's credits, which only its owner may spend
+    if long && !signed_in {
+        return wallet_auth::unauthorized();
     }
+    let max_tokens = if long {
+        persona.token_policy.paid_max_tokens.max(persona.token_policy.max_tokens_for(prompt, tier))
+    } else {
+        persona.token_policy.max_tokens_for(prompt, tier)
+    };
+    let sys_prompt = persona.render_prompt(max_tokens);
+
+    let db_path = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => vcdb_from_env.db_path,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+    };
+
+    let session_id = match json.session_id {
+        // session ids are sequential and wallets public, the history is only loaded for its owner
+        Some(_) if !signed_in => return wallet_auth::unauthorized(),
+        Some(session_id) => match chat_history::owns_session(&db_path, session_id, wallet).await {
+            Ok(true) => session_id,
+            Ok(false) => return HttpResponse::NotFound().json(GeneralReponse {
+                status: "Session not found".to_string()
+            }),
+            Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+        },
+        None => match chat_history::create_session(&db_path, wallet, &persona.id).await {
+            Ok(session_id) => session_id,
+            Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+        },
+    };
+
+    // whatever the preamble, the prompt and the reply leave of the context window
+    let history_budget = persona.context_window
+        .saturating_sub(agent_impl::metering::count_tokens(&sys_prompt.text))
+        .saturating_sub(agent_impl::metering::count_tokens(prompt))
+        .saturating_sub(max_tokens as usize);
+    let context = match chat_history::load_context(&db_path, session_id, persona.history_turns, history_budget).await {
+        Ok(context) => context,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+    };
 
     // let chat_agent = RetrivalAgent::new(
     //     sys_prompt.to_string(), 
@@ -60,81 +117,823 @@ async fn chat(json: web::Json<ChatRequest>) -> HttpResponse {
     //     Some(0.9), 
     //     Some(1)).await.unwrap();
 
-    let chat_agent_builder = RetrivalAgent::new_builder(
-        sys_prompt.to_string(), 
+    let mut chat_agent_builder = match RetrivalAgent::new_builder(
+        sys_prompt.text.clone(), 
         Some(max_tokens), 
-        Some(0.9), 
-        Some("deepseek-ai/DeepSeek-V3".to_string()));
+        Some(persona.temperature), 
+        Some(model_name.to_string())).await {
+        Ok(builder) => builder,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+    };
 
-    let chat_agent = chat_agent_builder.await.unwrap().build();
+    // tool calls are not supported while streaming, so related stories are looked up before the reply
+    if persona.allows_tool(RetrivalTool::NAME) {
+        match persona.retrival_tool(wallet).search(prompt).await {
+            Ok(stories) => chat_agent_builder = chat_agent_builder.context(&stories),
+            Err(e) => println!("Story lookup for persona {} failed: {}", persona.id, e),
+        }
+    }
+    if let Some(summary) = context.summary_document() {
+        chat_agent_builder = chat_agent_builder.context(&summary);
+    }
+    // memories are private, only a signed-in patron who opted in gets them recalled or extracted
+    let use_memory = persona.memory.enabled
+        && signed_in
+        && patron_memory::opted_in(&db_path, wallet).await.unwrap_or_else(|e| {
+            println!("Memory opt-in lookup for {} failed: {}", wallet, e);
+            false
+        });
+    if use_memory {
+        match patron_memory::recall(wallet, prompt, persona.memory.top_k, persona.memory.min_score).await {
+            Ok(memories) => if let Some(memories) = patron_memory::context_document(&memories) {
+                chat_agent_builder = chat_agent_builder.context(&memories);
+            },
+            Err(e) => println!("Memory recall for {} failed: {}", wallet, e),
+        }
+    }
+
+    let chat_agent = chat_agent_builder.build();
+
+    // charged right before the model call, so only the model can fail after the debit
+    let debit_id = if long {
+        match credits::debit(&db_path, wallet, PaidAction::LongChat).await {
+            Ok(debit) => Some(debit.id),
+            Err(e) if e.is::<CreditError>() => return HttpResponse::PaymentRequired().json(GeneralReponse {
+                status: format!("Error: {}", e)
+            }),
+            Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+        }
+    } else {
+        None
+    };
+    // the patron paid for a reply, give the credits back when the model fails to produce one
+    let refund = {
+        let db_path = db_path.clone();
+        move || if let Some(debit_id) = debit_id {
+            let db_path = db_path.clone();
+            tokio::spawn(async move {
+                if let Err(e) = credits::refund(&db_path, debit_id).await {
+                    println!("Failed to refund debit {}: {}", debit_id, e);
+                }
+            });
+        }
+    };
 
-    // TODO: we need to use `stream_chat` interface, and figure out one way to store the chat history of a single user.
-    let raw_response = chat_agent.stream_prompt(prompt)
+    let history_tokens = context.tokens;
+    let raw_response = chat_agent.stream_chat(prompt, context.history)
         .await;
 
     match raw_response {
         Ok(stream) => {
-            let converted_stream = stream
-                .map(|result| { // 使用 StreamExt 的 map
-                    match result {
-                        Ok(choice) => match choice {
-                            StreamingChoice::Message(text) => Ok(web::Bytes::from(text)),
-                            StreamingChoice::ToolCall(_, _, _) => 
-                                Err(actix_web::error::ErrorBadRequest("Tool calls not supported")),
-                        },
-                        Err(e) => 
-                            Err(actix_web::error::ErrorInternalServerError(e)),
+            // recorded once the stream is dropped
+            let mut meta = CompletionMeta::new(&db_path, Some(wallet), model_name, &sys_prompt.text)
+                .with_prompt_version(&sys_prompt.version)
+                .charging_quota();
+            meta.preamble_tokens += history_tokens;
+            let meter = StreamMeter::new(meta, prompt);
+            let recorder = TurnRecorder::new(&db_path, session_id, wallet, persona.history_turns, prompt)
+                .with_memory_extraction(use_memory);
+            let guard = persona.output_guard(max_tokens);
+
+            // the meter counts what the model produced, the recorder what the patron got to see
+            let converted_stream = futures::stream::unfold(
+                Some((stream, guard, meter, recorder, refund.clone())),
+                |state| async move {
+                    let (mut stream, mut guard, mut meter, mut recorder, refund) = state?;
+                    loop {
+                        match stream.next().await {
+                            Some(Ok(StreamingChoice::Message(text))) => {
+                                meter.add_output(&text);
+                                let guarded = guard.push(&text);
+                                recorder.add_output(&guarded);
+                                // over the word budget, stop reading the model
+                                if guard.is_done() {
+                                    return Some((Ok(web::Bytes::from(guarded)), None));
+                                }
+                                if !guarded.is_empty() {
+                                    return Some((Ok(web::Bytes::from(guarded)), Some((stream, guard, meter, recorder, refund))));
+                                }
+                            },
+                            Some(Ok(StreamingChoice::ToolCall(_, _, _))) => {
+                                refund();
+                                return Some((Err(actix_web::error::ErrorBadRequest("Tool calls not supported")), None));
+                            },
+                            Some(Err(e)) => {
+                                refund();
+                                return Some((Err(actix_web::error::ErrorInternalServerError(e)), None));
+                            },
+                            None => {
+                                let rest = guard.finish();
+                                recorder.add_output(&rest);
+                                if rest.is_empty() {
+                                    return None;
+                                }
+                                return Some((Ok(web::Bytes::from(rest)), None));
+                            },
+                        }
                     }
                 })
                 .boxed(); // 统一流类型
 
             HttpResponse::Ok()
                 .content_type("application/json")
+                .insert_header(("X-Prompt-Version", sys_prompt.version))
+                .insert_header(("X-Persona", persona.id.clone()))
+                .insert_header(("X-Session-Id", session_id.to_string()))
                 .streaming(converted_stream)
         },
         Err(e) => {
             eprintln!("Full error: {:#?}", e);  // 打印完整错误结构
+            refund();
             HttpResponse::InternalServerError().body(format!("Backend error: {}", e))
         }
     }
 }
 
+// premium holders get longer replies, more stories and premium personas
+#[get("/api/membership")]
+async fn membership_tier(req: HttpRequest, query: web::Query<WalletQuery>) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(MembershipResponse {
+        status: "success".to_string(),
+        tier: membership::tier(&req, &query.wallet).await
+    }))
+}
+
+#[get("/api/personas")]
+async fn list_personas() -> actix_web::Result<impl Responder> {
+    Ok(web::Json(PersonaListResponse {
+        status: "success".to_string(),
+        default_persona: personas::default_persona_id(),
+        personas: personas::list()
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct WalletQuery {
+    wallet: String,
+}
+
+fn auth_error_response(e: &anyhow::Error) -> actix_web::HttpResponseBuilder {
+    match e.downcast_ref::<AuthError>() {
+        Some(AuthError::InvalidWallet) | Some(AuthError::WrongMessage) => HttpResponse::BadRequest(),
+        Some(AuthError::InvalidChallenge) | Some(AuthError::InvalidSignature) | Some(AuthError::KeyMismatch) => HttpResponse::Unauthorized(),
+        None => HttpResponse::InternalServerError(),
+    }
+}
+
+#[post("/api/auth/challenge")]
+async fn auth_challenge(json: web::Json<AuthChallengeRequest>) -> HttpResponse {
+    let challenge = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => wallet_auth::challenge(&vcdb_from_env.db_path, &json.wallet).await,
+        Err(e) => Err(e),
+    };
+
+    match challenge {
+        Ok(challenge) => HttpResponse::Ok().json(AuthChallengeResponse {
+            status: "success".to_string(),
+            challenge: Some(challenge)
+        }),
+        Err(e) => auth_error_response(&e).json(AuthChallengeResponse {
+            status: format!("Error: {}", e),
+            challenge: None
+        }),
+    }
+}
+
+#[post("/api/auth/session")]
+async fn auth_session(json: web::Json<AuthSessionRequest>) -> HttpResponse {
+    let session = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => wallet_auth::sign_in(
+            &vcdb_from_env.db_path,
+            &json.wallet,
+            &json.nonce,
+            &json.public_key,
+            &json.signature,
+            &json.full_message,
+        ).await,
+        Err(e) => Err(e),
+    };
+
+    match session {
+        Ok(session) => HttpResponse::Ok().json(AuthSessionResponse {
+            status: "success".to_string(),
+            session: Some(session)
+        }),
+        Err(e) => auth_error_response(&e).json(AuthSessionResponse {
+            status: format!("Error: {}", e),
+            session: None
+        }),
+    }
+}
+
+// what the persona remembers about a wallet, for the signed-in wallet only
+#[get("/api/memories")]
+async fn list_memories(req: HttpRequest, query: web::Query<WalletQuery>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let memories = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => patron_memory::list(&vcdb_from_env.db_path, &query.wallet).await,
+        Err(e) => Err(e),
+    };
+
+    match memories {
+        Ok(memories) => HttpResponse::Ok().json(MemoryListResponse {
+            status: "success".to_string(),
+            memories
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(MemoryListResponse {
+            status: format!("Error: {}", e),
+            memories: Vec::new()
+        }),
+    }
+}
+
+#[delete("/api/memories")]
+async fn forget_all_memories(req: HttpRequest, query: web::Query<WalletQuery>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+    forget_memories(&query.wallet, None).await
+}
+
+#[delete("/api/memories/{id}")]
+async fn forget_memory(req: HttpRequest, path: web::Path<i64>, query: web::Query<WalletQuery>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+    forget_memories(&query.wallet, Some(path.into_inner())).await
+}
+
+// memories are only extracted and recalled after the patron opts in
+#[put("/api/memories/opt_in")]
+async fn memory_opt_in(req: HttpRequest, json: web::Json<MemoryOptInRequest>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &json.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let updated = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => patron_memory::set_opt_in(&vcdb_from_env.db_path, &json.wallet, json.enabled).await,
+        Err(e) => Err(e),
+    };
+
+    match updated {
+        Ok(()) => HttpResponse::Ok().json(GeneralReponse {
+            status: "success".to_string()
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(GeneralReponse {
+            status: format!("Error: {}", e)
+        }),
+    }
+}
+
+async fn forget_memories(wallet: &str, id: Option<i64>) -> HttpResponse {
+    let deleted = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => patron_memory::delete(&vcdb_from_env.db_path, wallet, id).await,
+        Err(e) => Err(e),
+    };
+
+    match deleted {
+        Ok(0) if id.is_some() => HttpResponse::NotFound().json(MemoryDeleteResponse {
+            status: "Memory not found".to_string(),
+            deleted: 0
+        }),
+        Ok(deleted) => HttpResponse::Ok().json(MemoryDeleteResponse {
+            status: "success".to_string(),
+            deleted
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(MemoryDeleteResponse {
+            status: format!("Error: {}", e),
+            deleted: 0
+        }),
+    }
+}
+
+// anonymous replies, the author only ever sees the reply text
+#[post("/api/bottles/{id}/replies")]
+async fn reply_to_bottle(path: web::Path<String>, json: web::Json<ReplyRequest>) -> HttpResponse {
+    let reply_id = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => replies::add_reply(&vcdb_from_env.db_path, &path, &json.wallet, &json.content).await,
+        Err(e) => Err(e),
+    };
+
+    match reply_id {
+        Ok(reply_id) => HttpResponse::Ok().json(ReplyResponse {
+            status: "success".to_string(),
+            reply_id: Some(reply_id)
+        }),
+        Err(e) => {
+            let mut response = if e.is::<db_schemas::InvalidText>() {
+                HttpResponse::BadRequest()
+            } else {
+                match e.downcast_ref::<ReplyError>() {
+                    Some(ReplyError::BottleNotFound) => HttpResponse::NotFound(),
+                    Some(_) => HttpResponse::Forbidden(),
+                    None => HttpResponse::InternalServerError(),
+                }
+            };
+            response.json(ReplyResponse {
+                status: format!("Error: {}", e),
+                reply_id: None
+            })
+        },
+    }
+}
+
+// only the signed-in author, a `wallet` alone would let anyone read another author's replies
+#[get("/api/bottles/{id}/replies")]
+async fn list_bottle_replies(req: HttpRequest, path: web::Path<String>, query: web::Query<WalletQuery>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let bottle_replies = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => replies::list_replies(&vcdb_from_env.db_path, &path, &query.wallet).await,
+        Err(e) => Err(e),
+    };
+
+    match bottle_replies {
+        Ok(replies) => HttpResponse::Ok().json(ReplyListResponse {
+            status: "success".to_string(),
+            replies
+        }),
+        Err(e) => {
+            let mut response = match e.downcast_ref::<ReplyError>() {
+                Some(ReplyError::BottleNotFound) => HttpResponse::NotFound(),
+                Some(_) => HttpResponse::Forbidden(),
+                None => HttpResponse::InternalServerError(),
+            };
+            response.json(ReplyListResponse {
+                status: format!("Error: {}", e),
+                replies: Vec::new()
+            })
+        },
+    }
+}
+
+#[get("/api/replies/unread")]
+async fn unread_replies(req: HttpRequest, query: web::Query<WalletQuery>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let unread = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => replies::unread_counts(&vcdb_from_env.db_path, &query.wallet).await,
+        Err(e) => Err(e),
+    };
+
+    match unread {
+        Ok(bottles) => HttpResponse::Ok().json(UnreadRepliesResponse {
+            status: "success".to_string(),
+            total: bottles.iter().map(|bottle| bottle.unread).sum(),
+            bottles
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(UnreadRepliesResponse {
+            status: format!("Error: {}", e),
+            total: 0,
+            bottles: Vec::new()
+        }),
+    }
+}
+
+// "felt this", "helped me" or "not relevant" on a retrieved bottle
+#[post("/api/bottles/{id}/reactions")]
+async fn react_to_bottle(req: HttpRequest, path: web::Path<String>, json: web::Json<ReactionRequest>) -> HttpResponse {
+    // the author check and the per-wallet counts mean nothing for a wallet anyone can claim
+    if !wallet_auth::is_owner(&req, &json.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let ip = rate_limit::client_ip(&req);
+    let recorded = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => reactions::react(&vcdb_from_env.db_path, &path, &json.wallet, ip.as_deref(), json.reaction, json.query.as_deref()).await,
+        Err(e) => Err(e),
+    };
+
+    match recorded {
+        Ok(recorded) => HttpResponse::Ok().json(ReactionResponse {
+            status: "success".to_string(),
+            recorded
+        }),
+        Err(e) => {
+            let mut response = if e.is::<db_schemas::InvalidText>() {
+                HttpResponse::BadRequest()
+            } else {
+                match e.downcast_ref::<ReactionError>() {
+                    Some(ReactionError::BottleNotFound) => HttpResponse::NotFound(),
+                    Some(ReactionError::OwnBottle) => HttpResponse::Forbidden(),
+                    Some(ReactionError::MissingQuery) => HttpResponse::BadRequest(),
+                    None => HttpResponse::InternalServerError(),
+                }
+            };
+            response.json(ReactionResponse {
+                status: format!("Error: {}", e),
+                recorded: false
+            })
+        },
+    }
+}
+
+// abusive bottles or bottles that identify someone, see `moderation.rs`
+#[post("/api/bottles/{id}/report")]
+async fn report_bottle(req: HttpRequest, path: web::Path<String>, json: web::Json<ReportRequest>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &json.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let ip = rate_limit::client_ip(&req);
+    let outcome = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => moderation::report(&vcdb_from_env.db_path, &path, &json.wallet, ip.as_deref(), json.reason, json.note.as_deref()).await,
+        Err(e) => Err(e),
+    };
+
+    match outcome {
+        Ok(outcome) => HttpResponse::Ok().json(ReportResponse {
+            status: "success".to_string(),
+            outcome: Some(outcome)
+        }),
+        Err(e) => {
+            let mut response = if e.is::<db_schemas::InvalidText>() {
+                HttpResponse::BadRequest()
+            } else if e.is::<moderation::BottleNotFound>() {
+                HttpResponse::NotFound()
+            } else {
+                HttpResponse::InternalServerError()
+            };
+            response.json(ReportResponse {
+                status: format!("Error: {}", e),
+                outcome: None
+            })
+        },
+    }
+}
+
+// pending and not yet indexed transactions are worth retrying, failed ones are not
+fn tx_status_response(status: &TxStatus) -> actix_web::HttpResponseBuilder {
+    match status {
+        TxStatus::Pending => HttpResponse::Accepted(),
+        TxStatus::NotFound => HttpResponse::NotFound(),
+        TxStatus::NetworkError(_) => HttpResponse::BadGateway(),
+        TxStatus::Success | TxStatus::Failed(_) | TxStatus::Mismatch => HttpResponse::BadRequest(),
+    }
+}
+
+fn anchor_error_response(e: &anyhow::Error) -> actix_web::HttpResponseBuilder {
+    match e.downcast_ref::<AnchorError>() {
+        Some(AnchorError::BottleNotFound) => HttpResponse::NotFound(),
+        Some(AnchorError::NotAuthor) => HttpResponse::Forbidden(),
+        Some(AnchorError::NoContentHash) | Some(AnchorError::AlreadyAnchored) => HttpResponse::Conflict(),
+        Some(AnchorError::NotConfigured) => HttpResponse::ServiceUnavailable(),
+        Some(AnchorError::TxMismatch) => HttpResponse::BadRequest(),
+        Some(AnchorError::Unconfirmed(status)) => tx_status_response(status),
+        None => HttpResponse::InternalServerError(),
+    }
+}
+
+// the payload the author signs to record the bottle's content hash on chain
+#[get("/api/bottles/{id}/anchor")]
+async fn anchor_payload(req: HttpRequest, path: web::Path<String>, query: web::Query<WalletQuery>) -> HttpResponse {
+    // the response carries the full text, private bottles included, and confirms the author
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let payload = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => anchoring::payload(&vcdb_from_env.db_path, &path, &query.wallet).await,
+        Err(e) => Err(e),
+    };
+
+    match payload {
+        Ok((anchor, payload)) => HttpResponse::Ok().json(AnchorPayloadResponse {
+            status: "success".to_string(),
+            anchor: Some(anchor),
+            payload: Some(payload)
+        }),
+        Err(e) => anchor_error_response(&e).json(AnchorPayloadResponse {
+            status: format!("Error: {}", e),
+            anchor: None,
+            payload: None
+        }),
+    }
+}
+
+fn payment_error_response(e: &anyhow::Error) -> actix_web::HttpResponseBuilder {
+    match e.downcast_ref::<PaymentError>() {
+        Some(PaymentError::NotConfigured) | Some(PaymentError::InvalidConfig(_)) => HttpResponse::ServiceUnavailable(),
+        Some(PaymentError::IntentNotFound) => HttpResponse::NotFound(),
+        Some(PaymentError::WrongIntent) => HttpResponse::Forbidden(),
+        Some(PaymentError::Expired) | Some(PaymentError::AlreadyPaid) | Some(PaymentError::TxReused) => HttpResponse::Conflict(),
+        Some(PaymentError::InvalidWallet) | Some(PaymentError::TxMismatch) => HttpResponse::BadRequest(),
+        Some(PaymentError::Unconfirmed(status)) => tx_status_response(status),
+        None => HttpResponse::InternalServerError(),
+    }
+}
+
+// the server prices the action and builds the payload, the wallet only signs it
+#[post("/api/payments/intent")]
+async fn create_payment_intent(json: web::Json<PaymentIntentRequest>) -> HttpResponse {
+    let intent = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => payments::create_intent(&vcdb_from_env.db_path, &json.wallet, json.action, json.bottle_id.as_deref()).await,
+        Err(e) => Err(e),
+    };
+
+    match intent {
+        Ok(intent) => HttpResponse::Ok().json(PaymentIntentResponse {
+            status: "success".to_string(),
+            intent: Some(intent)
+        }),
+        Err(e) => payment_error_response(&e).json(PaymentIntentResponse {
+            status: format!("Error: {}", e),
+            intent: None
+        }),
+    }
+}
+
+fn credit_error_response(e: &anyhow::Error) -> actix_web::HttpResponseBuilder {
+    match e.downcast_ref::<CreditError>() {
+        Some(CreditError::InsufficientCredits { .. }) => HttpResponse::PaymentRequired(),
+        Some(CreditError::NotADeposit) => HttpResponse::BadRequest(),
+        Some(CreditError::TxReused) => HttpResponse::Conflict(),
+        Some(CreditError::Unconfirmed(status)) => tx_status_response(status),
+        None => payment_error_response(e),
+    }
+}
+
+#[get("/api/credits")]
+async fn credit_balance(req: HttpRequest, query: web::Query<WalletQuery>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let balance = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => credits::balance(&vcdb_from_env.db_path, &query.wallet).await,
+        Err(e) => Err(e),
+    };
+
+    match balance {
+        Ok(balance_octas) => HttpResponse::Ok().json(CreditsResponse {
+            status: "success".to_string(),
+            balance_octas
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(CreditsResponse {
+            status: format!("Error: {}", e),
+            balance_octas: 0
+        }),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct LedgerQuery {
+    wallet: String,
+    // ledger id to page back from
+    before: Option<i64>,
+}
+
+#[get("/api/credits/ledger")]
+async fn credit_ledger(req: HttpRequest, query: web::Query<LedgerQuery>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let entries = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => credits::history(&vcdb_from_env.db_path, &query.wallet, query.before).await,
+        Err(e) => Err(e),
+    };
+
+    match entries {
+        Ok(entries) => HttpResponse::Ok().json(LedgerResponse {
+            status: "success".to_string(),
+            entries
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(LedgerResponse {
+            status: format!("Error: {}", e),
+            entries: Vec::new()
+        }),
+    }
+}
+
+// credits the amount of a transfer to the payment receiver, once per transaction
+#[post("/api/credits/deposit")]
+async fn deposit_credits(json: web::Json<DepositRequest>) -> HttpResponse {
+    let deposited = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => match credits::deposit(&vcdb_from_env.db_path, &json.wallet, &json.tx_hash).await {
+            Ok(deposit) => credits::balance(&vcdb_from_env.db_path, &json.wallet).await.map(|balance| (deposit, balance)),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+
+    match deposited {
+        Ok((deposit, balance_octas)) => HttpResponse::Ok().json(DepositResponse {
+            status: "success".to_string(),
+            deposit: Some(deposit),
+            balance_octas: Some(balance_octas)
+        }),
+        Err(e) => credit_error_response(&e).json(DepositResponse {
+            status: format!("Error: {}", e),
+            deposit: None,
+            balance_octas: None
+        }),
+    }
+}
+
+#[post("/api/bottles/{id}/anchor")]
+async fn confirm_anchor(req: HttpRequest, path: web::Path<String>, json: web::Json<AnchorRequest>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &json.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let anchor = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => anchoring::confirm(&vcdb_from_env.db_path, &path, &json.wallet, &json.tx_hash).await,
+        Err(e) => Err(e),
+    };
+
+    match anchor {
+        Ok(anchor) => HttpResponse::Ok().json(AnchorResponse {
+            status: "success".to_string(),
+            anchor: Some(anchor)
+        }),
+        Err(e) => anchor_error_response(&e).json(AnchorResponse {
+            status: format!("Error: {}", e),
+            anchor: None
+        }),
+    }
+}
+
+// the drift bottle mechanic: a random public bottle the wallet has not seen yet
+#[post("/api/bottles/pickup")]
+async fn pickup_bottle(json: web::Json<PickupRequest>) -> HttpResponse {
+    let filter = bottle_meta::normalize_tag("emotion", json.emotion.as_deref())
+        .and_then(|emotion| Ok(pickup::PickupFilter {
+            emotion,
+            topic: bottle_meta::normalize_tag("topic", json.topic.as_deref())?,
+        }));
+    let filter = match filter {
+        Ok(filter) => filter,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(PickupResponse {
+                status: format!("Error: {}", e),
+                bottle: None
+            });
+        }
+    };
+
+    let picked = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => pickup::pickup(&vcdb_from_env.db_path, &json.wallet, filter).await,
+        Err(e) => Err(e),
+    };
+
+    match picked {
+        Ok(Some(bottle)) => HttpResponse::Ok().json(PickupResponse {
+            status: "success".to_string(),
+            bottle: Some(bottle)
+        }),
+        Ok(None) => HttpResponse::Ok().json(PickupResponse {
+            status: "No bottle has drifted by, try again later.".to_string(),
+            bottle: None
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(PickupResponse {
+            status: format!("Error: {}", e),
+            bottle: None
+        }),
+    }
+}
+
+// bottles the delivery scheduler washed ashore for this wallet
+#[get("/api/inbox")]
+async fn inbox(req: HttpRequest, query: web::Query<WalletQuery>) -> HttpResponse {
+    if !wallet_auth::is_owner(&req, &query.wallet).await {
+        return wallet_auth::unauthorized();
+    }
+
+    let items = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => delivery::inbox(&vcdb_from_env.db_path, &query.wallet).await,
+        Err(e) => Err(e),
+    };
+
+    match items {
+        Ok(items) => HttpResponse::Ok().json(InboxResponse {
+            status: "success".to_string(),
+            items
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(InboxResponse {
+            status: format!("Error: {}", e),
+            items: Vec::new()
+        }),
+    }
+}
+
 #[post("/api/store_drift")]
-async fn store_drift(json: web::Json<request_model::StoreDriftBottleRequest>) -> actix_web::Result<impl Responder> {
+async fn store_drift(json: web::Json<request_model::StoreDriftBottleRequest>) -> HttpResponse {
     let wallet = &json.wallet;
     let title = &json.title;
     let drift_bottle_content = &json.content;
 
-    let mut response = request_model::GeneralReponse {
-        status: "success".to_string()
+    let tags = db_schemas::validate_text("title", title)
+        .and_then(|_| db_schemas::validate_text("content", drift_bottle_content))
+        .and_then(|_| bottle_meta::BottleTags::new(json.emotion.as_deref(), json.topic.as_deref(), json.public));
+    let tags = match tags {
+        Ok(tags) => tags,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(StoreDriftResponse {
+                status: format!("Error: {}", e),
+                job_id: None
+            });
+        }
     };
 
-    // store this drift bottle in DB
-    // currently we will implement the basic connection method, no ConnPool implemented.
+    let vcdb_from_env = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => vcdb_from_env,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+    };
 
-    db_schemas::store_drift_vec(&wallet, &title, &drift_bottle_content).await.unwrap_or_else(|e| {
-        response = GeneralReponse {
-            status: format!("Error: {}", e)
-        };
-    });
-    Ok(web::Json(response))
+    // reject duplicates right away, the worker would only fail on them later
+    let duplicate = match Connection::open(&vcdb_from_env.db_path).await {
+        Ok(conn) => db_schemas::bottle_exists(&conn, wallet, title).await,
+        Err(e) => Err(e.into()),
+    };
+    let duplicate = match duplicate {
+        Ok(true) => true,
+        Ok(false) => jobs::has_pending_job(&vcdb_from_env.db_path, wallet, title).await.unwrap_or(false),
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Backend error: {}", e)),
+    };
+    if duplicate {
+        return HttpResponse::Conflict().json(StoreDriftResponse {
+            status: format!("Error: {}", DuplicateBottle),
+            job_id: None
+        });
+    }
+
+    if let Err(e) = bottle_meta::record(&vcdb_from_env.db_path, wallet, title, tags).await {
+        return HttpResponse::InternalServerError().body(format!("Backend error: {}", e));
+    }
+
+    // the bottle is chunked, embedded and stored by the ingestion worker
+    match jobs::enqueue(&vcdb_from_env.db_path, wallet, title, drift_bottle_content).await {
+        Ok(job_id) => HttpResponse::Accepted().json(StoreDriftResponse {
+            status: "queued".to_string(),
+            job_id: Some(job_id)
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(StoreDriftResponse {
+            status: format!("Error: {}", e),
+            job_id: None
+        }),
+    }
+}
+
+#[get("/api/jobs/{id}")]
+async fn job_status(path: web::Path<i64>) -> HttpResponse {
+    let job_id = path.into_inner();
+
+    let job = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => jobs::get_job(&vcdb_from_env.db_path, job_id).await,
+        Err(e) => Err(e),
+    };
+
+    match job {
+        Ok(Some(job)) => HttpResponse::Ok().json(JobStatusResponse {
+            status: job.status.clone(),
+            job: Some(job)
+        }),
+        Ok(None) => HttpResponse::NotFound().json(JobStatusResponse {
+            status: "Job not found".to_string(),
+            job: None
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(JobStatusResponse {
+            status: format!("Error: {}", e),
+            job: None
+        }),
+    }
 }
 
 #[get("/api/grade_drift")]
 async fn grade_drift(json: web::Json<GradeBottleRequest>) -> actix_web::Result<impl Responder> {
     let title = &json.title;
     let content = &json.content;
-    let tx_hash = &json.tx_hash;
     let mut response = GradeBottleResponse {
         status: "OK".to_string(),
         score: 98
     };
 
-    // 1. verify the tx_hash
-    let valid = verify_tx(tx_hash).await.unwrap_or(false);
-    if !valid {
+    // 1. settle the payment intent with the tx_hash. Credits are not taken while the grade is a
+    //    placeholder, they can be once grading does real work and refunds when it fails
+    let paid = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => match (json.intent_id, &json.tx_hash) {
+            (Some(intent_id), Some(tx_hash)) =>
+                payments::settle(&vcdb_from_env.db_path, intent_id, &json.wallet, PaidAction::Grade, tx_hash).await,
+            _ => Err(anyhow::anyhow!("intent_id and tx_hash are required")),
+        },
+        Err(e) => Err(e),
+    };
+    if let Err(e) = paid {
+        // pending, not found, failed and network errors each read differently, the first two are worth retrying
+        let status = match e.downcast_ref::<PaymentError>() {
+            Some(PaymentError::Unconfirmed(tx_status)) => tx_status.to_string(),
+            _ => format!("transaction invalid: {}", e),
+        };
         response = GradeBottleResponse {
-            status: "transaction invalid".to_string(),
+            status,
             score: -1
         };
         return Ok(web::Json(response));
@@ -142,54 +941,101 @@ async fn grade_drift(json: web::Json<GradeBottleRequest>) -> actix_web::Result<i
     // 2. save these content to vec db, using function
 
     // 3. grade this content, return score.
+    //    the score is a placeholder until then, so nothing is recorded with `bottle_meta::set_grade`
 
 
     Ok(web::Json(response))
 }
 
 #[get("/api/retrive_drift")]
-async fn retrive_drift(json: web::Json<RetriveRequest>) -> actix_web::Result<impl Responder> {
-    let _wallet = &json.wallet;
+async fn retrive_drift(req: HttpRequest, json: web::Json<RetriveRequest>) -> actix_web::Result<impl Responder> {
+    let wallet = &json.wallet;
     let prompt = &json.content;
 
     let mut response = RetriveResponse {
         status: "success".to_string(),
         retrive_results: Vec::new(),
+        prompt_version: String::new(),
+        charge: None,
     };
 
-    let valid_tx: bool = true;
-    if !valid_tx {
-        response = RetriveResponse {
-            status: "Fail to response".to_string(),
-            retrive_results: Vec::new(),
-        };
+    let Some(persona) = personas::get(json.persona.as_deref()) else {
+        response.status = "Unknown persona".to_string();
+        return Ok(web::Json(response));
+    };
+    let tier = membership::tier(&req, wallet).await;
+    if persona.premium && !tier.is_premium() {
+        response.status = "This persona is for premium members".to_string();
         return Ok(web::Json(response));
     }
 
-    let sys_prompt = prompt_hub::RETRIVAL_AGENT_SYS_PROMPT;
+    let db_path = match VectorDBFromEnv::new().await {
+        Ok(vcdb_from_env) => vcdb_from_env.db_path,
+        Err(e) => {
+            response.status = format!("Error: {}", e);
+            return Ok(web::Json(response));
+        }
+    };
 
-    let retrival_agent_builder = RetrivalAgent::new_builder(
-        sys_prompt.to_string(), 
-        Some(512), 
-        Some(0.7),
-        Some("Qwen/QwQ-32B".to_string())).await.unwrap();
+    // free while the paywall is off
+    let payment = match (json.intent_id, &json.tx_hash) {
+        (Some(intent_id), Some(tx_hash)) => Some((intent_id, tx_hash.as_str())),
+        _ => None,
+    };
+    let signed_in = wallet_auth::is_owner(&req, wallet).await;
+    let charge = match paywall::charge(&db_path, wallet, signed_in, payment).await {
+        Ok(charge) => charge,
+        Err(e) => {
+            response.status = match e.downcast_ref::<PaymentError>() {
+                Some(PaymentError::Unconfirmed(tx_status)) => tx_status.to_string(),
+                _ => format!("payment failed: {}", e),
+            };
+            return Ok(web::Json(response));
+        }
+    };
 
-    let retrival_agent = retrival_agent_builder.tool(RetrivalTool).build();
+    // the query pipeline decides what to search for, not the agent
+    let settings = tier.retrieval_settings(&persona.retrieval);
+    let retrieval = match query_pipeline::retrieve(prompt, &settings, Some(wallet)).await {
+        Ok(retrieval) => retrieval,
+        Err(e) => {
+            println!("An error occured! {e}");
+            if let Err(e) = paywall::refund(&db_path, wallet, &charge).await {
+                println!("Failed to refund {:?} for {}: {}", charge, wallet, e);
+            }
+            response = RetriveResponse {
+                status: "Fail to response".to_string(),
+                retrive_results: Vec::new(),
+                prompt_version: String::new(),
+                charge: None,
+            };
+            return Ok(web::Json(response));
+        }
+    };
+    let prompt_version = retrieval.plan.prompt_versions.join("+");
 
-    let agent_response = retrival_agent.prompt(prompt.clone()).await.unwrap_or_else(|e| {
-        println!("An error occured! {e}");
-        "Fail to process".to_string()
-    });
+    // anchors are shown when known, a failed lookup only leaves them out
+    let chunk_ids: Vec<String> = retrieval.candidates.iter().map(|candidate| candidate.bottle.id.clone()).collect();
+    let mut anchors = anchoring::anchor_txs(&db_path, &chunk_ids).await.unwrap_or_default();
 
-    let doc_info = db_schemas::parse_markdown_text(&agent_response).unwrap_or_else(|e| {
-        println!("An Error occured during parsing: {}", e);
-        Vec::new()
-    });
+    let doc_info: Vec<DocInfo> = retrieval.candidates
+        .into_iter()
+        .map(|candidate| DocInfo {
+            id: candidate.bottle.id,
+            user: candidate.bottle.wallet,
+            title: candidate.bottle.title,
+            content: candidate.bottle.content,
+            anchor_tx: anchors.remove(&candidate.bottle.id),
+            reactions: candidate.reactions,
+        })
+        .collect();
 
     if doc_info.len() == 0 {
         response = RetriveResponse {
             status: "Sorry, we haven't found any similar exprience as you have now.".to_string(),
-            retrive_results: doc_info
+            retrive_results: doc_info,
+            prompt_version,
+            charge: Some(charge),
         };
 
         return Ok(web::Json(response));
@@ -197,7 +1043,9 @@ async fn retrive_drift(json: web::Json<RetriveRequest>) -> actix_web::Result<imp
 
     response = RetriveResponse {
         status: "success".to_string(),
-        retrive_results: doc_info
+        retrive_results: doc_info,
+        prompt_version,
+        charge: Some(charge),
     };
 
     Ok(web::Json(response))
@@ -208,8 +1056,20 @@ async fn main() -> std::io::Result<()> {
     // load environment variables from.env file
     dotenv().ok();
 
-    unsafe {
-        sqlite3_auto_extension(Some(std::mem::transmute(sqlite3_vec_init as *const ())));
+    db_schemas::register_sqlite_vec();
+
+    let vcdb_from_env = VectorDBFromEnv::new().await.expect("Failed to load env vars");
+    db_schemas::init_db(&vcdb_from_env.db_path).await.map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    match embedding_meta::check_embedding_model(&vcdb_from_env).await {
+        Ok(EmbeddingCheck::Mismatch { stored_model, stored_ndims }) => {
+            eprintln!("Embedding model mismatch: the database was embedded with {} ({} dims), but .env configures {} ({} dims).",
+                stored_model, stored_ndims, vcdb_from_env.embedding_model_name, vcdb_from_env.embedding_ndim);
+            eprintln!("Run `lisa-admin reembed` to re-embed the stored bottles before starting the server.");
+            return Err(std::io::Error::other("embedding model mismatch"));
+        },
+        Ok(_) => {},
+        Err(e) => eprintln!("Failed to check embedding model: {}", e),
     }
 
     const IPADDRESS: &str = "0.0.0.0";
@@ -218,20 +1078,62 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
-    HttpServer::new(|| {
+    tokio::spawn(jobs::run_worker(vcdb_from_env.db_path.clone()));
+    tokio::spawn(delivery::run_scheduler(vcdb_from_env.db_path.clone()));
+
+    // shared by all workers, so the buckets see every request
+    let rate_limiter = web::Data::new(RateLimiter::from_env());
+
+    HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
-            .allowed_methods(vec!["GET", "POST"])
+            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
             .allow_any_header()
             .max_age(3600);
 
         App::new()
+            .app_data(rate_limiter.clone())
+            .wrap(from_fn(rate_limit::rate_limit))
             .wrap(cors)
             .service(entrance)
             .service(ping)
             .service(chat)
+            .service(list_personas)
+            .service(membership_tier)
+            .service(list_memories)
+            .service(forget_all_memories)
+            .service(forget_memory)
+            .service(memory_opt_in)
+            .service(auth_challenge)
+            .service(auth_session)
+            .service(reply_to_bottle)
+            .service(list_bottle_replies)
+            .service(unread_replies)
+            .service(react_to_bottle)
+            .service(report_bottle)
+            .service(anchor_payload)
+            .service(confirm_anchor)
+            .service(create_payment_intent)
+            .service(credit_balance)
+            .service(credit_ledger)
+            .service(deposit_credits)
+            .service(pickup_bottle)
+            .service(inbox)
             .service(store_drift)
+            .service(job_status)
             .service(retrive_drift)
+            .service(admin::export_bottles)
+            .service(admin::import_bottles)
+            .service(admin::usage_report)
+            .service(admin::embedding_cache_stats)
+            .service(admin::block_wallet)
+            .service(admin::unblock_wallet)
+            .service(admin::search_feedback)
+            .service(admin::moderation_queue)
+            .service(admin::review_bottle)
+            .service(admin::hide_bottle)
+            .service(admin::restore_bottle)
+            .service(admin::delete_bottle)
             .wrap(Logger::default())
             .wrap(Logger::new("%a"))
     })
@@ -0,0 +1,363 @@
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::HttpRequest;
+use aptos_sdk::rest_client::aptos_api_types::{EntryFunctionId, ViewRequest};
+use aptos_sdk::rest_client::Client as aptos_client;
+use aptos_sdk::types::account_address::AccountAddress;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::personas::RetrievalSettings;
+use crate::wallet_auth;
+
+// Premium features for wallets holding the project's NFT or token on Aptos: longer replies
+// (`token_policy.premium_max_tokens`), more retrieved stories and personas marked `premium`.
+// Holdings are read from chain and the tier is cached per wallet.
+//
+// MEMBERSHIP_VIEW_FUNCTION="0x<address>::<module>::<function>"   view function taking the wallet
+//                                                                address and returning a u64 count
+// MEMBERSHIP_COIN_TYPE="0x<address>::<module>::<Coin>"            or the wallet's `CoinStore` balance
+// MEMBERSHIP_MIN_HOLDINGS="1"        holdings needed for premium
+// MEMBERSHIP_CACHE_SECS="600"
+// MEMBERSHIP_NODE_URL                defaults to the node `aptos_utils` uses
+// PREMIUM_TOP_K="4"                  stories retrieved for premium wallets, twice the persona's by default
+//
+// Without either source everyone is on the free tier.
+
+const CACHE_SIZE: usize = 10_000;
+const DEFAULT_NODE_URL: &str = "https://fullnode.testnet.aptoslabs.com";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    #[default]
+    Free,
+    Premium,
+}
+
+impl Tier {
+    pub fn is_premium(&self) -> bool {
+        *self == Tier::Premium
+    }
+
+    /// What `/api/retrive_drift` searches with, the persona's settings adjusted for the tier.
+    pub fn retrieval_settings(&self, persona: &RetrievalSettings) -> RetrievalSettings {
+        let settings = persona.clone();
+        match self {
+            Tier::Free => settings,
+            Tier::Premium => RetrievalSettings {
+                top_k: std::env::var("PREMIUM_TOP_K")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(settings.top_k * 2)
+                    .max(settings.top_k),
+                ..settings
+            },
+        }
+    }
+}
+
+/// Where a wallet's holdings come from.
+pub trait HoldingsSource {
+    /// The NFT count or token balance of `wallet`.
+    fn holdings(&self, wallet: &str) -> impl Future<Output = Result<u64, anyhow::Error>> + Send;
+}
+
+// the chain prints u64 as decimal strings, some view functions return plain numbers
+fn parse_amount(value: &serde_json::Value) -> Option<u64> {
+    match value {
+        serde_json::Value::String(amount) => amount.parse().ok(),
+        value => value.as_u64(),
+    }
+}
+
+fn node_client() -> aptos_client {
+    let node_url = std::env::var("MEMBERSHIP_NODE_URL").unwrap_or(DEFAULT_NODE_URL.to_string());
+    aptos_client::new(Url::parse(&node_url).expect("Invalid URL"))
+}
+
+/// A Move view function `(address) -> u64`, e.g. the number of collection tokens owned.
+pub struct ViewFunctionHoldings {
+    pub function: String,
+}
+
+impl HoldingsSource for ViewFunctionHoldings {
+    async fn holdings(&self, wallet: &str) -> Result<u64, anyhow::Error> {
+        let request = ViewRequest {
+            function: EntryFunctionId::from_str(&self.function)?,
+            type_arguments: Vec::new(),
+            arguments: vec![serde_json::Value::String(AccountAddress::from_str(wallet)?.to_hex_literal())],
+        };
+        let values = node_client().view(&request, None).await?.into_inner();
+        values.first()
+            .and_then(parse_amount)
+            .ok_or_else(|| anyhow::anyhow!("{} returned {:?}, expected a u64", self.function, values))
+    }
+}
+
+/// The balance in the wallet's `0x1::coin::CoinStore<coin_type>` resource, 0 without one.
+pub struct CoinStoreHoldings {
+    pub coin_type: String,
+}
+
+impl HoldingsSource for CoinStoreHoldings {
+    async fn holdings(&self, wallet: &str) -> Result<u64, anyhow::Error> {
+        let address = AccountAddress::from_str(wallet)?;
+        let resource_type = format!("0x1::coin::CoinStore<{}>", self.coin_type);
+        let resource = node_client().get_account_resource(address, &resource_type).await?.into_inner();
+        let Some(resource) = resource else {
+            return Ok(0);
+        };
+        parse_amount(&resource.data["coin"]["value"])
+            .ok_or_else(|| anyhow::anyhow!("Unexpected {} for {}: {}", resource_type, wallet, resource.data))
+    }
+}
+
+pub enum ConfiguredHoldings {
+    Disabled,
+    ViewFunction(ViewFunctionHoldings),
+    CoinStore(CoinStoreHoldings),
+}
+
+impl ConfiguredHoldings {
+    pub fn from_env() -> Self {
+        let configured = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        if let Some(function) = configured("MEMBERSHIP_VIEW_FUNCTION") {
+            ConfiguredHoldings::ViewFunction(ViewFunctionHoldings { function })
+        } else if let Some(coin_type) = configured("MEMBERSHIP_COIN_TYPE") {
+            ConfiguredHoldings::CoinStore(CoinStoreHoldings { coin_type })
+        } else {
+            ConfiguredHoldings::Disabled
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, ConfiguredHoldings::Disabled)
+    }
+}
+
+impl HoldingsSource for ConfiguredHoldings {
+    async fn holdings(&self, wallet: &str) -> Result<u64, anyhow::Error> {
+        match self {
+            ConfiguredHoldings::Disabled => Ok(0),
+            ConfiguredHoldings::ViewFunction(source) => source.holdings(wallet).await,
+            ConfiguredHoldings::CoinStore(source) => source.holdings(wallet).await,
+        }
+    }
+}
+
+/// Tiers by wallet with the time they were read from chain.
+pub struct TierCache {
+    entries: Mutex<LruCache<String, (Tier, Instant)>>,
+    ttl: Duration,
+}
+
+impl TierCache {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl,
+        }
+    }
+
+    /// The cached tier and whether it is older than the TTL.
+    fn get(&self, wallet: &str) -> Option<(Tier, bool)> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.get(wallet).map(|(tier, read_at)| (*tier, read_at.elapsed() >= self.ttl))
+    }
+
+    fn insert(&self, wallet: String, tier: Tier) {
+        self.entries.lock().unwrap().put(wallet, (tier, Instant::now()));
+    }
+}
+
+static SOURCE: LazyLock<ConfiguredHoldings> = LazyLock::new(ConfiguredHoldings::from_env);
+
+static CACHE: LazyLock<TierCache> = LazyLock::new(|| {
+    let ttl_secs = std::env::var("MEMBERSHIP_CACHE_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(600);
+    TierCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap(), Duration::from_secs(ttl_secs))
+});
+
+fn min_holdings() -> u64 {
+    std::env::var("MEMBERSHIP_MIN_HOLDINGS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1)
+}
+
+/// The tier from `cache`, or read through `source` once the cached one is older than the TTL.
+/// While the chain cannot be read the last known tier is kept, free if there is none.
+pub async fn tier_with<S: HoldingsSource>(source: &S, cache: &TierCache, wallet: &str, min_holdings: u64) -> Tier {
+    let wallet = wallet.trim().to_lowercase();
+    let cached = cache.get(&wallet);
+    if let Some((tier, false)) = cached {
+        return tier;
+    }
+
+    match source.holdings(&wallet).await {
+        Ok(holdings) => {
+            let tier = if holdings >= min_holdings { Tier::Premium } else { Tier::Free };
+            cache.insert(wallet, tier);
+            tier
+        },
+        Err(e) => {
+            println!("Failed to read holdings of {}: {}", wallet, e);
+            cached.map(|(tier, _)| tier).unwrap_or_default()
+        },
+    }
+}
+
+/// The wallet's tier for handlers, free while membership is not configured. Anyone can name a
+/// holder's wallet, so premium is only granted to a request signed in as it (see `wallet_auth.rs`).
+pub async fn tier(req: &HttpRequest, wallet: &str) -> Tier {
+    if !SOURCE.is_enabled() || !wallet_auth::is_owner(req, wallet).await {
+        return Tier::Free;
+    }
+    tier_with(&*SOURCE, &CACHE, wallet, min_holdings()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+
+    const HOLDER: &str = "0xa11ce";
+    const NON_HOLDER: &str = "0xb0b";
+
+    /// Holdings from a map, counting reads; `fail` makes the next reads error like an unreachable node.
+    struct MockHoldings {
+        balances: HashMap<String, u64>,
+        reads: AtomicUsize,
+        fail: AtomicBool,
+    }
+
+    impl MockHoldings {
+        fn new(balances: &[(&str, u64)]) -> Self {
+            Self {
+                balances: balances.iter().map(|(wallet, balance)| (wallet.to_string(), *balance)).collect(),
+                reads: AtomicUsize::new(0),
+                fail: AtomicBool::new(false),
+            }
+        }
+
+        fn reads(&self) -> usize {
+            self.reads.load(Ordering::SeqCst)
+        }
+    }
+
+    impl HoldingsSource for MockHoldings {
+        async fn holdings(&self, wallet: &str) -> Result<u64, anyhow::Error> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            if self.fail.load(Ordering::SeqCst) {
+                anyhow::bail!("node unreachable");
+            }
+            Ok(self.balances.get(wallet).copied().unwrap_or(0))
+        }
+    }
+
+    fn cache(ttl: Duration) -> TierCache {
+        TierCache::new(NonZeroUsize::new(16).unwrap(), ttl)
+    }
+
+    #[tokio::test]
+    async fn tier_follows_holdings() {
+        let source = MockHoldings::new(&[(HOLDER, 1), (NON_HOLDER, 0)]);
+        let cache = cache(Duration::from_secs(600));
+
+        assert_eq!(tier_with(&source, &cache, HOLDER, 1).await, Tier::Premium);
+        assert_eq!(tier_with(&source, &cache, NON_HOLDER, 1).await, Tier::Free);
+    }
+
+    #[tokio::test]
+    async fn threshold_is_inclusive() {
+        let source = MockHoldings::new(&[(HOLDER, 2)]);
+
+        assert_eq!(tier_with(&source, &cache(Duration::from_secs(600)), HOLDER, 2).await, Tier::Premium);
+        assert_eq!(tier_with(&source, &cache(Duration::from_secs(600)), HOLDER, 3).await, Tier::Free);
+    }
+
+    #[tokio::test]
+    async fn wallets_are_matched_case_insensitively() {
+        let source = MockHoldings::new(&[(HOLDER, 1)]);
+        let cache = cache(Duration::from_secs(600));
+
+        assert_eq!(tier_with(&source, &cache, " 0xA11CE ", 1).await, Tier::Premium);
+        assert_eq!(tier_with(&source, &cache, HOLDER, 1).await, Tier::Premium);
+        assert_eq!(source.reads(), 1);
+    }
+
+    #[tokio::test]
+    async fn tier_is_cached_within_ttl() {
+        let source = MockHoldings::new(&[(HOLDER, 5)]);
+        let cache = cache(Duration::from_secs(600));
+
+        for _ in 0..3 {
+            assert_eq!(tier_with(&source, &cache, HOLDER, 1).await, Tier::Premium);
+        }
+        assert_eq!(source.reads(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_tier_is_read_again() {
+        let source = MockHoldings::new(&[(HOLDER, 5)]);
+        let cache = cache(Duration::ZERO);
+
+        tier_with(&source, &cache, HOLDER, 1).await;
+        tier_with(&source, &cache, HOLDER, 1).await;
+        assert_eq!(source.reads(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_read_keeps_last_known_tier() {
+        let source = MockHoldings::new(&[(HOLDER, 5)]);
+        let cache = cache(Duration::ZERO);
+
+        assert_eq!(tier_with(&source, &cache, HOLDER, 1).await, Tier::Premium);
+        source.fail.store(true, Ordering::SeqCst);
+        assert_eq!(tier_with(&source, &cache, HOLDER, 1).await, Tier::Premium);
+        assert_eq!(source.reads(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_read_without_cache_is_free() {
+        let source = MockHoldings::new(&[(HOLDER, 5)]);
+        source.fail.store(true, Ordering::SeqCst);
+        let cache = cache(Duration::from_secs(600));
+
+        assert_eq!(tier_with(&source, &cache, HOLDER, 1).await, Tier::Free);
+        // errors are not cached, the next request asks again
+        source.fail.store(false, Ordering::SeqCst);
+        assert_eq!(tier_with(&source, &cache, HOLDER, 1).await, Tier::Premium);
+    }
+
+    #[test]
+    fn amounts_parse_from_strings_and_numbers() {
+        assert_eq!(parse_amount(&serde_json::json!("42")), Some(42));
+        assert_eq!(parse_amount(&serde_json::json!(7)), Some(7));
+        assert_eq!(parse_amount(&serde_json::json!("-1")), None);
+        assert_eq!(parse_amount(&serde_json::json!(null)), None);
+    }
+
+    #[test]
+    fn premium_retrieves_more_stories() {
+        let persona = RetrievalSettings::default();
+        assert!(Tier::Premium.retrieval_settings(&persona).top_k > Tier::Free.retrieval_settings(&persona).top_k);
+    }
+
+    #[test]
+    fn tier_settings_build_on_the_persona() {
+        let persona = RetrievalSettings { top_k: 7, ..RetrievalSettings::default() };
+        assert_eq!(Tier::Free.retrieval_settings(&persona).top_k, 7);
+        assert!(Tier::Premium.retrieval_settings(&persona).top_k >= 7);
+    }
+}
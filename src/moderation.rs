@@ -0,0 +1,387 @@
+use std::collections::HashSet;
+
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio_rusqlite::Connection;
+
+use crate::db_schemas::validate_text;
+use crate::pickup;
+use crate::replies::resolve_bottle;
+
+// Reports on bottles and the admin moderation queue. Reports are attached to the first chunk of
+// the bottle like replies. A bottle reported by REPORT_HIDE_THRESHOLD readers is hidden from
+// retrieval, pickup and delivery until an admin restores or deletes it.
+//
+// Auto-hiding is the part worth abusing: a few reports take anyone's story offline. So reporting
+// needs a signed-in wallet, and toward the threshold only count reporters who were handed the
+// bottle (picked it up or had it delivered, both recorded in `seen_bottles`), one per client address.
+// Wallets are free to make, but each one has to draw the bottle at random from the pickup pool or
+// the delivery scheduler, under the pickup rate limits, and addresses cap what one client adds.
+// Every report still lands in the moderation queue.
+//
+// report: open -> actioned (bottle hidden or deleted by an admin)
+//              -> dismissed (bottle restored)
+//
+// REPORT_HIDE_THRESHOLD="3"    0 disables auto-hiding
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Abuse,
+    Harassment,
+    // the story identifies a real person
+    PersonalInfo,
+    SelfHarm,
+    Spam,
+    Other,
+}
+
+impl ReportReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportReason::Abuse => "abuse",
+            ReportReason::Harassment => "harassment",
+            ReportReason::PersonalInfo => "personal_info",
+            ReportReason::SelfHarm => "self_harm",
+            ReportReason::Spam => "spam",
+            ReportReason::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Bottle not found")]
+pub struct BottleNotFound;
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS reports (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                bottle_id TEXT NOT NULL,
+                chunk_id TEXT NOT NULL,
+                wallet TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                note TEXT,
+                status TEXT NOT NULL DEFAULT 'open',
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                reviewed_at INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS idx_reports_bottle ON reports(bottle_id, status);
+            CREATE INDEX IF NOT EXISTS idx_reports_status ON reports(status, created_at);
+            CREATE TABLE IF NOT EXISTS hidden_bottles (
+                bottle_id TEXT PRIMARY KEY,
+                hidden_by TEXT NOT NULL,
+                hidden_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );"
+        )?;
+
+        // added with per-address counting, older reports count by wallet
+        let has_ip: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('reports') WHERE name = 'ip'",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_ip == 0 {
+            conn.execute_batch("ALTER TABLE reports ADD COLUMN ip TEXT")?;
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn hide_threshold() -> i64 {
+    std::env::var("REPORT_HIDE_THRESHOLD")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(3)
+}
+
+#[derive(Serialize)]
+pub struct ReportOutcome {
+    // false when the wallet already has an open report on this bottle
+    pub recorded: bool,
+    pub hidden: bool,
+}
+
+/// Report a bottle from a signed-in wallet at client address `ip`.
+pub async fn report(db_path: &str, chunk_id: &str, wallet: &str, ip: Option<&str>, reason: ReportReason, note: Option<&str>) -> Result<ReportOutcome, anyhow::Error> {
+    let note = note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty());
+    if let Some(note) = &note {
+        validate_text("note", note)?;
+    }
+
+    let conn = Connection::open(db_path).await?;
+    let (chunk_id, wallet, threshold) = (chunk_id.to_string(), wallet.to_string(), hide_threshold());
+    let ip = ip.map(|ip| ip.to_string());
+
+    let outcome = conn.call(move |conn| {
+        let Some((bottle_id, _)) = resolve_bottle(conn, &chunk_id)? else {
+            return Ok(Err(BottleNotFound));
+        };
+
+        let tx = conn.transaction()?;
+        let already_open = tx.query_row(
+            "SELECT 1 FROM reports WHERE bottle_id = ?1 AND wallet = ?2 AND status = 'open'",
+            params![bottle_id, wallet],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+        if !already_open {
+            tx.execute(
+                "INSERT INTO reports (bottle_id, chunk_id, wallet, reason, note, ip) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![bottle_id, chunk_id, wallet, reason.as_str(), note, ip],
+            )?;
+        }
+
+        let reporters: i64 = tx.query_row(
+            "SELECT COUNT(DISTINCT COALESCE(r.ip, r.wallet)) FROM reports r
+            WHERE r.bottle_id = ?1 AND r.status = 'open'
+                AND EXISTS (SELECT 1 FROM seen_bottles s WHERE s.wallet = r.wallet AND s.bottle_id = r.bottle_id)",
+            [&bottle_id],
+            |row| row.get(0),
+        )?;
+        if threshold > 0 && reporters >= threshold {
+            tx.execute(
+                "INSERT OR IGNORE INTO hidden_bottles (bottle_id, hidden_by) VALUES (?1, 'reports')",
+                [&bottle_id],
+            )?;
+        }
+        let hidden = tx.query_row("SELECT 1 FROM hidden_bottles WHERE bottle_id = ?1", [&bottle_id], |_| Ok(()))
+            .optional()?
+            .is_some();
+        tx.commit()?;
+
+        Ok(Ok(ReportOutcome { recorded: !already_open, hidden }))
+    })
+    .await??;
+
+    Ok(outcome)
+}
+
+/// The given chunks that belong to a hidden bottle.
+pub async fn hidden_chunks(db_path: &str, chunk_ids: &[String]) -> Result<HashSet<String>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let chunk_ids = chunk_ids.to_vec();
+
+    let hidden = conn.call(move |conn| {
+        let mut hidden = HashSet::new();
+        for chunk_id in chunk_ids {
+            let Some((bottle_id, _)) = resolve_bottle(conn, &chunk_id)? else {
+                continue;
+            };
+            let is_hidden = conn.query_row("SELECT 1 FROM hidden_bottles WHERE bottle_id = ?1", [&bottle_id], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if is_hidden {
+                hidden.insert(chunk_id);
+            }
+        }
+        Ok(hidden)
+    })
+    .await?;
+
+    Ok(hidden)
+}
+
+/// One row of the moderation queue.
+#[derive(Serialize)]
+pub struct ReportedBottle {
+    pub bottle_id: String,
+    pub wallet: Option<String>,
+    pub title: Option<String>,
+    pub open_reports: u64,
+    pub total_reports: u64,
+    // reason codes of the open reports
+    pub reasons: Vec<String>,
+    pub hidden: bool,
+    pub last_reported_at: i64,
+}
+
+/// Bottles with reports, the ones with open reports first. `open_only` leaves out reviewed ones.
+pub async fn queue(db_path: &str, open_only: bool) -> Result<Vec<ReportedBottle>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+
+    let queue = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT r.bottle_id, b.wallet, b.title,
+                SUM(r.status = 'open'), COUNT(*),
+                COALESCE(GROUP_CONCAT(DISTINCT CASE WHEN r.status = 'open' THEN r.reason END), ''),
+                EXISTS (SELECT 1 FROM hidden_bottles h WHERE h.bottle_id = r.bottle_id),
+                MAX(r.created_at)
+            FROM reports r LEFT JOIN drift_bottles b ON b.id = r.bottle_id
+            WHERE r.status != 'deleted'
+            GROUP BY r.bottle_id
+            HAVING ?1 = 0 OR SUM(r.status = 'open') > 0
+            ORDER BY SUM(r.status = 'open') > 0 DESC, MAX(r.created_at) DESC"
+        )?;
+        let queue = stmt.query_map([open_only], |row| {
+            let title: Option<String> = row.get(2)?;
+            let reasons: String = row.get(5)?;
+            Ok(ReportedBottle {
+                bottle_id: row.get(0)?,
+                wallet: row.get(1)?,
+                title: title.map(|title| title.strip_suffix("-0").unwrap_or(&title).to_string()),
+                open_reports: row.get::<_, i64>(3)? as u64,
+                total_reports: row.get::<_, i64>(4)? as u64,
+                reasons: reasons.split(',').filter(|r| !r.is_empty()).map(|r| r.to_string()).collect(),
+                hidden: row.get(6)?,
+                last_reported_at: row.get(7)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(queue)
+    })
+    .await?;
+
+    Ok(queue)
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub id: i64,
+    pub wallet: String,
+    pub reason: String,
+    pub note: Option<String>,
+    pub status: String,
+    pub created_at: i64,
+}
+
+/// Everything an admin needs to review a bottle.
+#[derive(Serialize)]
+pub struct BottleReview {
+    pub bottle_id: String,
+    pub wallet: String,
+    pub title: String,
+    pub content: String,
+    pub hidden: bool,
+    pub reports: Vec<Report>,
+}
+
+pub async fn review(db_path: &str, chunk_id: &str) -> Result<Option<BottleReview>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let chunk_id = chunk_id.to_string();
+
+    let review = conn.call(move |conn| {
+        let Some((bottle_id, wallet)) = resolve_bottle(conn, &chunk_id)? else {
+            return Ok(None);
+        };
+        let title: String = conn.query_row("SELECT title FROM drift_bottles WHERE id = ?1", [&bottle_id], |row| row.get(0))?;
+        let title = title.strip_suffix("-0").unwrap_or(&title).to_string();
+        let content = pickup::full_content(conn, &wallet, &title)?;
+        let hidden = conn.query_row("SELECT 1 FROM hidden_bottles WHERE bottle_id = ?1", [&bottle_id], |_| Ok(()))
+            .optional()?
+            .is_some();
+
+        let mut stmt = conn.prepare(
+            "SELECT id, wallet, reason, note, status, created_at FROM reports WHERE bottle_id = ?1 ORDER BY id DESC"
+        )?;
+        let reports = stmt.query_map([&bottle_id], |row| {
+            Ok(Report {
+                id: row.get(0)?,
+                wallet: row.get(1)?,
+                reason: row.get(2)?,
+                note: row.get(3)?,
+                status: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok(Some(BottleReview { bottle_id, wallet, title, content, hidden, reports }))
+    })
+    .await?;
+
+    Ok(review)
+}
+
+/// Hide a bottle after review, its open reports are closed as actioned.
+pub async fn hide(db_path: &str, chunk_id: &str) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let chunk_id = chunk_id.to_string();
+
+    conn.call(move |conn| {
+        let Some((bottle_id, _)) = resolve_bottle(conn, &chunk_id)? else {
+            return Ok(Err(BottleNotFound));
+        };
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO hidden_bottles (bottle_id, hidden_by) VALUES (?1, 'admin')
+            ON CONFLICT (bottle_id) DO UPDATE SET hidden_by = 'admin'",
+            [&bottle_id],
+        )?;
+        tx.execute(
+            "UPDATE reports SET status = 'actioned', reviewed_at = unixepoch() WHERE bottle_id = ?1 AND status = 'open'",
+            [&bottle_id],
+        )?;
+        tx.commit()?;
+        Ok(Ok(()))
+    })
+    .await??;
+
+    Ok(())
+}
+
+/// Show a bottle again, its open reports are dismissed so they no longer count towards hiding.
+pub async fn restore(db_path: &str, chunk_id: &str) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let chunk_id = chunk_id.to_string();
+
+    conn.call(move |conn| {
+        let Some((bottle_id, _)) = resolve_bottle(conn, &chunk_id)? else {
+            return Ok(Err(BottleNotFound));
+        };
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM hidden_bottles WHERE bottle_id = ?1", [&bottle_id])?;
+        tx.execute(
+            "UPDATE reports SET status = 'dismissed', reviewed_at = unixepoch() WHERE bottle_id = ?1 AND status = 'open'",
+            [&bottle_id],
+        )?;
+        tx.commit()?;
+        Ok(Ok(()))
+    })
+    .await??;
+
+    Ok(())
+}
+
+/// Permanently delete a bottle: every chunk with its vector, and what hangs off the bottle.
+/// The reports are kept as `deleted` for the record. Returns the number of deleted chunks.
+pub async fn delete(db_path: &str, chunk_id: &str) -> Result<usize, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let chunk_id = chunk_id.to_string();
+
+    let deleted = conn.call(move |conn| {
+        let Some((bottle_id, wallet)) = resolve_bottle(conn, &chunk_id)? else {
+            return Ok(Err(BottleNotFound));
+        };
+        let title: String = conn.query_row("SELECT title FROM drift_bottles WHERE id = ?1", [&bottle_id], |row| row.get(0))?;
+        let title = title.strip_suffix("-0").unwrap_or(&title).to_string();
+        let chunks = pickup::bottle_chunks(conn, &wallet, &title)?;
+
+        let tx = conn.transaction()?;
+        let mut deleted = 0;
+        for (id, _) in &chunks {
+            tx.execute(
+                "DELETE FROM drift_bottles_embeddings WHERE rowid IN (SELECT rowid FROM drift_bottles WHERE id = ?1)",
+                [id],
+            )?;
+            deleted += tx.execute("DELETE FROM drift_bottles WHERE id = ?1", [id])?;
+        }
+        for table in ["bottle_replies", "bottle_reactions", "inbox", "seen_bottles", "delivery_schedule", "hidden_bottles"] {
+            tx.execute(&format!("DELETE FROM {table} WHERE bottle_id = ?1"), [&bottle_id])?;
+        }
+        tx.execute("DELETE FROM bottle_meta WHERE wallet = ?1 AND title = ?2", params![wallet, title])?;
+        tx.execute(
+            "UPDATE reports SET status = 'deleted', reviewed_at = unixepoch() WHERE bottle_id = ?1",
+            [&bottle_id],
+        )?;
+        tx.commit()?;
+        Ok(Ok(deleted))
+    })
+    .await??;
+
+    Ok(deleted)
+}
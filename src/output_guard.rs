@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+
+use crate::db_schemas::count_sequence_len;
+
+// Post-processing of a streamed reply, chunk by chunk, for the response rules the system
+// prompt asks for but the model does not always follow. Which rules apply is set per persona.
+
+// a `*` with this much text and no closing `*` was not an action
+const MAX_ACTION_CHARS: usize = 80;
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', '。', '！', '？', '…'];
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct OutputRules {
+    // drop `*smiles*` style action descriptors
+    #[serde(default)]
+    pub strip_actions: bool,
+    #[serde(default)]
+    pub strip_emojis: bool,
+    // newlines and runs of whitespace become one space
+    #[serde(default)]
+    pub collapse_newlines: bool,
+    // end the reply at the last sentence boundary within the word budget
+    #[serde(default)]
+    pub enforce_word_limit: bool,
+}
+
+impl OutputRules {
+    pub fn all() -> Self {
+        Self {
+            strip_actions: true,
+            strip_emojis: true,
+            collapse_newlines: true,
+            enforce_word_limit: true,
+        }
+    }
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF   // emoticons, pictographs, transport, flags, supplemental symbols
+        | 0x2600..=0x27BF   // misc symbols, dingbats
+        | 0x2B00..=0x2BFF   // arrows and stars like ⭐
+        | 0xFE0F            // variation selector
+        | 0x200D            // zero width joiner
+    )
+}
+
+pub struct OutputGuard {
+    rules: OutputRules,
+    word_limit: usize,
+    words_emitted: usize,
+    in_action: bool,
+    action: String,
+    started: bool,
+    pending_space: bool,
+    // text after the last sentence boundary, held back while the word budget applies
+    sentence: String,
+    done: bool,
+}
+
+impl OutputGuard {
+    /// `word_limit` of 0 disables the budget.
+    pub fn new(rules: OutputRules, word_limit: usize) -> Self {
+        Self {
+            rules,
+            word_limit,
+            words_emitted: 0,
+            in_action: false,
+            action: String::new(),
+            started: false,
+            pending_space: false,
+            sentence: String::new(),
+            done: false,
+        }
+    }
+
+    /// The reply hit its word budget, the rest of the model output can be dropped.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Feed one streamed chunk, returns the text that may be sent on.
+    pub fn push(&mut self, chunk: &str) -> String {
+        if self.done {
+            return String::new();
+        }
+
+        let mut cleaned = String::new();
+        for c in chunk.chars() {
+            self.clean(c, &mut cleaned);
+        }
+        self.budget(&cleaned)
+    }
+
+    /// The stream ended, flush what was held back.
+    pub fn finish(&mut self) -> String {
+        if self.done {
+            return String::new();
+        }
+
+        // an unclosed action at the very end is still dropped, unless it was too long to be one
+        let mut cleaned = String::new();
+        if self.in_action && self.action.chars().count() > MAX_ACTION_CHARS {
+            self.release_action(&mut cleaned);
+        }
+        let mut out = self.budget(&cleaned);
+
+        let sentence = std::mem::take(&mut self.sentence);
+        let sentence = sentence.trim_end();
+        if !sentence.is_empty() {
+            if self.fits(sentence) {
+                out.push_str(sentence);
+            } else if self.words_emitted == 0 {
+                out.push_str(&self.truncate(sentence));
+            }
+        }
+        self.done = true;
+        out
+    }
+
+    // stage one: actions, emojis, whitespace
+    fn clean(&mut self, c: char, cleaned: &mut String) {
+        if self.rules.strip_actions {
+            if self.in_action {
+                if c == '*' {
+                    self.in_action = false;
+                    self.action.clear();
+                } else {
+                    self.action.push(c);
+                    if self.action.chars().count() > MAX_ACTION_CHARS {
+                        self.release_action(cleaned);
+                    }
+                }
+                return;
+            }
+            if c == '*' {
+                self.in_action = true;
+                return;
+            }
+        }
+        self.clean_char(c, cleaned);
+    }
+
+    // a lone `*` turned out not to open an action, pass its text through
+    fn release_action(&mut self, cleaned: &mut String) {
+        self.in_action = false;
+        let action = std::mem::take(&mut self.action);
+        self.clean_char('*', cleaned);
+        for c in action.chars() {
+            self.clean_char(c, cleaned);
+        }
+    }
+
+    fn clean_char(&mut self, c: char, cleaned: &mut String) {
+        if self.rules.strip_emojis && is_emoji(c) {
+            return;
+        }
+        if self.rules.collapse_newlines && c.is_whitespace() {
+            self.pending_space = self.started;
+            return;
+        }
+        if self.pending_space {
+            cleaned.push(' ');
+            self.pending_space = false;
+        }
+        cleaned.push(c);
+        self.started = true;
+    }
+
+    // stage two: the word budget
+    fn budget(&mut self, cleaned: &str) -> String {
+        if !self.rules.enforce_word_limit || self.word_limit == 0 {
+            return cleaned.to_string();
+        }
+
+        let mut out = String::new();
+        for c in cleaned.chars() {
+            if self.done {
+                break;
+            }
+            self.sentence.push(c);
+
+            if SENTENCE_ENDS.contains(&c) {
+                if self.fits(&self.sentence) {
+                    self.words_emitted += count_sequence_len(&self.sentence);
+                    out.push_str(&std::mem::take(&mut self.sentence));
+                } else {
+                    self.cut(&mut out);
+                }
+            } else if c.is_whitespace() && !self.fits(&self.sentence) {
+                self.cut(&mut out);
+            }
+        }
+        out
+    }
+
+    fn fits(&self, sentence: &str) -> bool {
+        self.words_emitted + count_sequence_len(sentence) <= self.word_limit
+    }
+
+    // over budget: end at the last full sentence, or cut the first one short if there is none
+    fn cut(&mut self, out: &mut String) {
+        let sentence = std::mem::take(&mut self.sentence);
+        if self.words_emitted == 0 {
+            out.push_str(&self.truncate(&sentence));
+        }
+        self.done = true;
+    }
+
+    fn truncate(&self, sentence: &str) -> String {
+        let words: Vec<&str> = sentence.split_whitespace().take(self.word_limit).collect();
+        format!("{}…", words.join(" ").trim_end_matches([',', ';', ':', '-']))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed the chunks and finish, returning everything the guard let through.
+    fn run(guard: &mut OutputGuard, chunks: &[&str]) -> String {
+        let mut out: String = chunks.iter().map(|chunk| guard.push(chunk)).collect();
+        out.push_str(&guard.finish());
+        out
+    }
+
+    fn rules(apply: impl FnOnce(&mut OutputRules)) -> OutputRules {
+        let mut rules = OutputRules::default();
+        apply(&mut rules);
+        rules
+    }
+
+    #[test]
+    fn action_split_across_chunks_is_stripped() {
+        let mut guard = OutputGuard::new(rules(|r| { r.strip_actions = true; r.collapse_newlines = true; }), 0);
+        assert_eq!(run(&mut guard, &["Hello *smi", "les* there"]), "Hello there");
+    }
+
+    #[test]
+    fn unclosed_star_longer_than_an_action_is_released() {
+        let text = format!("a *{}", "x".repeat(MAX_ACTION_CHARS + 20));
+        let (first, second) = text.split_at(3 + MAX_ACTION_CHARS / 2);
+        let mut guard = OutputGuard::new(rules(|r| r.strip_actions = true), 0);
+        assert_eq!(run(&mut guard, &[first, second]), text);
+    }
+
+    #[test]
+    fn short_unclosed_action_at_the_end_is_dropped() {
+        let mut guard = OutputGuard::new(rules(|r| r.strip_actions = true), 0);
+        assert_eq!(run(&mut guard, &["ok *wav", "es"]), "ok ");
+    }
+
+    #[test]
+    fn emojis_and_newlines_are_cleaned() {
+        let mut guard = OutputGuard::new(OutputRules::all(), 0);
+        assert_eq!(run(&mut guard, &["\nHi 😀\n", "\nthere"]), "Hi there");
+    }
+
+    #[test]
+    fn budget_ends_at_cjk_sentence_ends() {
+        let mut guard = OutputGuard::new(rules(|r| r.enforce_word_limit = true), 4);
+        assert_eq!(guard.push("one two。thr"), "one two。");
+        assert_eq!(guard.push("ee four！five six。"), "three four！");
+        assert!(guard.is_done());
+        assert_eq!(guard.push("seven."), "");
+        assert_eq!(guard.finish(), "");
+    }
+
+    #[test]
+    fn first_sentence_over_the_budget_is_cut_short() {
+        let mut guard = OutputGuard::new(rules(|r| r.enforce_word_limit = true), 3);
+        assert_eq!(run(&mut guard, &["one two, three ", "four five."]), "one two, three…");
+        assert!(guard.is_done());
+    }
+
+    #[test]
+    fn held_back_tail_is_flushed_when_it_fits() {
+        let mut guard = OutputGuard::new(rules(|r| r.enforce_word_limit = true), 10);
+        assert_eq!(guard.push("Done. hello"), "Done.");
+        assert_eq!(guard.push(" there"), "");
+        assert_eq!(guard.finish(), " hello there");
+    }
+}
@@ -0,0 +1,290 @@
+use rig::embeddings::EmbeddingModel;
+use rusqlite::params;
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+
+use crate::agent_impl::{prompt_hub, RetrivalAgent};
+use crate::agent_impl::metering::{self, CompletionMeta};
+use crate::db_schemas::VectorDBFromEnv;
+
+// Long-term memory per wallet: durable facts a cheap model pulls out of each chat turn,
+// embedded so the ones relevant to the next message can be recalled into the context.
+// A wallet rarely has more than a few hundred memories, so similarity is computed here
+// instead of in a vec0 table.
+// Nothing is remembered unless the persona enables memory and the patron opted in
+// (`memory_opt_in`), both from a signed-in session, see `wallet_auth.rs`.
+
+const EXTRACT_MAX_TOKENS: u32 = 128;
+// a new fact this close to a stored one is the same fact
+const DUPLICATE_SCORE: f64 = 0.92;
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS patron_memories (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet TEXT NOT NULL,
+                content TEXT NOT NULL,
+                model TEXT NOT NULL,
+                embedding TEXT NOT NULL,
+                session_id INTEGER,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE INDEX IF NOT EXISTS idx_patron_memories_wallet ON patron_memories(wallet);
+            CREATE TABLE IF NOT EXISTS memory_opt_in (
+                wallet TEXT PRIMARY KEY,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn memory_model() -> String {
+    std::env::var("MEMORY_MODEL_NAME").unwrap_or("Qwen/Qwen2.5-7B-Instruct".to_string())
+}
+
+#[derive(Serialize)]
+pub struct PatronMemory {
+    pub id: i64,
+    pub content: String,
+    pub session_id: Option<i64>,
+    pub created_at: i64,
+}
+
+struct StoredMemory {
+    memory: PatronMemory,
+    embedding: Vec<f64>,
+}
+
+fn cosine(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+pub async fn list(db_path: &str, wallet: &str) -> Result<Vec<PatronMemory>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let memories = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, content, session_id, created_at FROM patron_memories WHERE wallet = ?1 ORDER BY id"
+        )?;
+        let memories = stmt.query_map([wallet], |row| {
+            Ok(PatronMemory {
+                id: row.get(0)?,
+                content: row.get(1)?,
+                session_id: row.get(2)?,
+                created_at: row.get(3)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(memories)
+    })
+    .await?;
+
+    Ok(memories)
+}
+
+pub async fn opted_in(db_path: &str, wallet: &str) -> Result<bool, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let opted_in: i64 = conn.call(move |conn| {
+        Ok(conn.query_row("SELECT COUNT(*) FROM memory_opt_in WHERE wallet = ?1", [wallet], |row| row.get(0))?)
+    })
+    .await?;
+
+    Ok(opted_in > 0)
+}
+
+/// Opt in to memories, or out. Opting out keeps what is stored, `delete` forgets it.
+pub async fn set_opt_in(db_path: &str, wallet: &str, opt_in: bool) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    conn.call(move |conn| {
+        if opt_in {
+            conn.execute("INSERT OR IGNORE INTO memory_opt_in (wallet) VALUES (?1)", [wallet])?;
+        } else {
+            conn.execute("DELETE FROM memory_opt_in WHERE wallet = ?1", [wallet])?;
+        }
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Forget one memory, or all of them when `id` is `None`. Returns how many were deleted.
+pub async fn delete(db_path: &str, wallet: &str, id: Option<i64>) -> Result<usize, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let deleted = conn.call(move |conn| {
+        Ok(conn.execute(
+            "DELETE FROM patron_memories WHERE wallet = ?1 AND (?2 IS NULL OR id = ?2)",
+            params![wallet, id],
+        )?)
+    })
+    .await?;
+
+    Ok(deleted)
+}
+
+// only memories embedded with the current model are comparable
+async fn load_embedded(conn: &Connection, wallet: &str, model: &str) -> Result<Vec<StoredMemory>, anyhow::Error> {
+    let (wallet, model) = (wallet.to_string(), model.to_string());
+
+    let rows = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, content, session_id, created_at, embedding FROM patron_memories WHERE wallet = ?1 AND model = ?2"
+        )?;
+        let rows = stmt.query_map(params![wallet, model], |row| {
+            Ok((
+                PatronMemory {
+                    id: row.get(0)?,
+                    content: row.get(1)?,
+                    session_id: row.get(2)?,
+                    created_at: row.get(3)?,
+                },
+                row.get::<_, String>(4)?,
+            ))
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(rows)
+    })
+    .await?;
+
+    let mut memories = Vec::with_capacity(rows.len());
+    for (memory, embedding) in rows {
+        memories.push(StoredMemory {
+            memory,
+            embedding: serde_json::from_str(&embedding)?,
+        });
+    }
+    Ok(memories)
+}
+
+/// The wallet's memories most similar to `query`, best first.
+pub async fn recall(wallet: &str, query: &str, top_k: usize, min_score: f64) -> Result<Vec<PatronMemory>, anyhow::Error> {
+    let vcdb_from_env = VectorDBFromEnv::new().await?;
+    let conn = Connection::open(&vcdb_from_env.db_path).await?;
+
+    let stored = load_embedded(&conn, wallet, &vcdb_from_env.embedding_model_name).await?;
+    if stored.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let embedding_model = vcdb_from_env.embedding_model(Some(wallet));
+    let query_embedding = embedding_model.embed_text(query).await?;
+
+    let mut scored: Vec<(f64, PatronMemory)> = stored.into_iter()
+        .map(|m| (cosine(&query_embedding.vec, &m.embedding), m.memory))
+        .filter(|(score, _)| *score >= min_score)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    Ok(scored.into_iter().take(top_k).map(|(_, memory)| memory).collect())
+}
+
+pub fn context_document(memories: &[PatronMemory]) -> Option<String> {
+    if memories.is_empty() {
+        return None;
+    }
+    let facts = memories.iter()
+        .map(|m| format!("- {}", m.content))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(format!("What you remember about this patron from earlier visits:\n{}", facts))
+}
+
+// the model is asked for a JSON array, fall back to one fact per line
+fn parse_facts(response: &str) -> Vec<String> {
+    let trimmed = response.trim().trim_start_matches("```json").trim_matches('`').trim();
+    let facts: Vec<String> = serde_json::from_str(trimmed).unwrap_or_else(|_| {
+        trimmed.lines()
+            .map(|line| line.trim().trim_start_matches('-').trim().to_string())
+            .filter(|line| !line.is_empty() && line != "[]")
+            .collect()
+    });
+    facts.into_iter()
+        .map(|fact| fact.trim().to_string())
+        .filter(|fact| !fact.is_empty())
+        .collect()
+}
+
+/// Pull new facts about the patron out of one exchange and store them. Returns how many were added.
+pub async fn extract(wallet: &str, session_id: i64, prompt: &str, reply: &str) -> Result<usize, anyhow::Error> {
+    let vcdb_from_env = VectorDBFromEnv::new().await?;
+    let db_path = vcdb_from_env.db_path.clone();
+    let conn = Connection::open(&db_path).await?;
+
+    let stored = load_embedded(&conn, wallet, &vcdb_from_env.embedding_model_name).await?;
+    let known = if stored.is_empty() {
+        "(none)".to_string()
+    } else {
+        stored.iter().map(|m| format!("- {}", m.memory.content)).collect::<Vec<_>>().join("\n")
+    };
+
+    let rendered = prompt_hub::render(prompt_hub::MEMORY_EXTRACT, &[
+        ("known", known),
+        ("prompt", prompt.to_string()),
+        ("reply", reply.to_string()),
+    ]);
+    let model_name = memory_model();
+    let agent = RetrivalAgent::new_builder(
+        rendered.text.clone(),
+        Some(EXTRACT_MAX_TOKENS),
+        Some(0.2),
+        Some(model_name.clone())).await?
+        .build();
+    let meta = CompletionMeta::new(&db_path, Some(wallet), &model_name, &rendered.text)
+        .with_prompt_version(&rendered.version);
+    let response = metering::metered_prompt(&agent, &meta, "List the new facts.").await?;
+
+    let facts = parse_facts(&response);
+    if facts.is_empty() {
+        return Ok(0);
+    }
+
+    let embedding_model = vcdb_from_env.embedding_model(Some(wallet));
+    let embeddings = embedding_model.embed_texts(facts).await?;
+
+    let mut known_vectors: Vec<Vec<f64>> = stored.into_iter().map(|m| m.embedding).collect();
+    let mut rows: Vec<(String, String)> = Vec::new();
+    for embedding in embeddings {
+        if known_vectors.iter().any(|known| cosine(known, &embedding.vec) >= DUPLICATE_SCORE) {
+            continue;
+        }
+        rows.push((embedding.document, serde_json::to_string(&embedding.vec)?));
+        known_vectors.push(embedding.vec);
+    }
+
+    let added = rows.len();
+    let (wallet, model) = (wallet.to_string(), vcdb_from_env.embedding_model_name.clone());
+    conn.call(move |conn| {
+        let tx = conn.transaction()?;
+        for (content, embedding) in rows {
+            tx.execute(
+                "INSERT INTO patron_memories (wallet, content, model, embedding, session_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![wallet, content, model, embedding, session_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    })
+    .await?;
+
+    Ok(added)
+}
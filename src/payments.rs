@@ -0,0 +1,385 @@
+use std::str::FromStr;
+
+use aptos_sdk::bcs;
+use aptos_sdk::move_types::identifier::Identifier;
+use aptos_sdk::move_types::language_storage::ModuleId;
+use aptos_sdk::types::account_address::AccountAddress;
+use aptos_sdk::types::transaction::{EntryFunction, TransactionPayload};
+use rusqlite::{params, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use tokio_rusqlite::Connection;
+
+use crate::aptos_utils::{self, EntryFunctionPayload, ExpectedTx, TxStatus};
+
+// Server-built payments: the server records what is being paid for and at which price, and hands
+// the wallet the exact payload to sign. A submitted tx settles an intent only if it carries that
+// payload, was sent by the intent's wallet, was committed on chain between the intent's creation
+// and its expiry, and settled nothing before. The tx may be posted back after the expiry.
+//
+// pending -> paid
+//         -> (no matching tx committed by expires_at)
+//
+// PAYMENT_RECEIVER="0x..."                      account receiving payments, payments are off while unset
+// PAYMENT_FUNCTION="0x1::aptos_account::transfer"   called with (receiver: address, amount: u64)
+// PRICE_GRADE_OCTAS="1000000"                   prices per action, 1 APT = 100000000 octas
+// PRICE_RETRIEVE_OCTAS="100000"
+// PRICE_LONG_CHAT_OCTAS="200000"
+// PAYMENT_INTENT_TTL_SECS="900"
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PaidAction {
+    Grade,
+    Retrieve,
+    LongChat,
+}
+
+impl PaidAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaidAction::Grade => "grade",
+            PaidAction::Retrieve => "retrieve",
+            PaidAction::LongChat => "long_chat",
+        }
+    }
+
+    pub fn price_octas(&self) -> u64 {
+        let (name, default) = match self {
+            PaidAction::Grade => ("PRICE_GRADE_OCTAS", 1_000_000),
+            PaidAction::Retrieve => ("PRICE_RETRIEVE_OCTAS", 100_000),
+            PaidAction::LongChat => ("PRICE_LONG_CHAT_OCTAS", 200_000),
+        };
+        std::env::var(name)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(default)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PaymentError {
+    #[error("Payments are not configured")]
+    NotConfigured,
+    #[error("Invalid payment configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Invalid wallet address")]
+    InvalidWallet,
+    #[error("Payment intent not found")]
+    IntentNotFound,
+    #[error("This payment intent belongs to another wallet or action")]
+    WrongIntent,
+    #[error("This payment intent has expired, request a new one")]
+    Expired,
+    #[error("This payment intent is already paid")]
+    AlreadyPaid,
+    #[error("This transaction already settled another payment")]
+    TxReused,
+    #[error("The transaction does not match the payment intent")]
+    TxMismatch,
+    #[error("{0}")]
+    Unconfirmed(TxStatus),
+}
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS payment_intents (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet TEXT NOT NULL,
+                action TEXT NOT NULL,
+                bottle_id TEXT,
+                price_octas INTEGER NOT NULL,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                tx_hash TEXT UNIQUE,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                expires_at INTEGER NOT NULL,
+                paid_at INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS idx_payment_intents_wallet ON payment_intents(wallet, status);"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn intent_ttl_secs() -> i64 {
+    std::env::var("PAYMENT_INTENT_TTL_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(900)
+}
+
+pub(crate) fn payment_function() -> String {
+    std::env::var("PAYMENT_FUNCTION").unwrap_or("0x1::aptos_account::transfer".to_string())
+}
+
+pub(crate) fn receiver() -> Result<AccountAddress, PaymentError> {
+    let receiver = std::env::var("PAYMENT_RECEIVER")
+        .ok()
+        .filter(|receiver| !receiver.is_empty())
+        .ok_or(PaymentError::NotConfigured)?;
+    AccountAddress::from_str(&receiver).map_err(|e| PaymentError::InvalidConfig(format!("PAYMENT_RECEIVER: {}", e)))
+}
+
+/// The call to sign, as BCS through `aptos_sdk` types and in its JSON form.
+fn build_payload(function: &str, receiver: AccountAddress, amount: u64) -> Result<(TransactionPayload, EntryFunctionPayload), PaymentError> {
+    let invalid = |e: String| PaymentError::InvalidConfig(format!("PAYMENT_FUNCTION {}: {}", function, e));
+    let parts: Vec<&str> = function.split("::").collect();
+    let [address, module, name] = parts[..] else {
+        return Err(invalid("expected <address>::<module>::<function>".to_string()));
+    };
+    let module = ModuleId::new(
+        AccountAddress::from_str(address).map_err(|e| invalid(e.to_string()))?,
+        Identifier::new(module).map_err(|e| invalid(e.to_string()))?,
+    );
+    let name = Identifier::new(name).map_err(|e| invalid(e.to_string()))?;
+
+    let entry_function = EntryFunction::new(
+        module,
+        name,
+        vec![],
+        vec![
+            bcs::to_bytes(&receiver).map_err(|e| invalid(e.to_string()))?,
+            bcs::to_bytes(&amount).map_err(|e| invalid(e.to_string()))?,
+        ],
+    );
+    let json = EntryFunctionPayload::new(function, vec![
+        serde_json::Value::String(receiver.to_hex_literal()),
+        serde_json::Value::String(amount.to_string()),
+    ]);
+    Ok((TransactionPayload::EntryFunction(entry_function), json))
+}
+
+#[derive(Serialize)]
+pub struct PaymentIntent {
+    pub id: i64,
+    pub action: PaidAction,
+    pub bottle_id: Option<String>,
+    pub price_octas: u64,
+    pub expires_at: i64,
+    pub payload: EntryFunctionPayload,
+    // hex of the BCS encoded `TransactionPayload`, for wallets and SDKs that take raw payloads
+    pub payload_bcs: String,
+}
+
+pub async fn create_intent(db_path: &str, wallet: &str, action: PaidAction, bottle_id: Option<&str>) -> Result<PaymentIntent, anyhow::Error> {
+    AccountAddress::from_str(wallet).map_err(|_| PaymentError::InvalidWallet)?;
+    let price_octas = action.price_octas();
+    let (payload, json) = build_payload(&payment_function(), receiver()?, price_octas)?;
+    let payload_bcs = format!("0x{}", hex_encode(&bcs::to_bytes(&payload)?));
+
+    let conn = Connection::open(db_path).await?;
+    let (wallet, bottle_id, stored) = (wallet.to_string(), bottle_id.map(|id| id.to_string()), serde_json::to_string(&json)?);
+    let bottle_id_owned = bottle_id.clone();
+    let ttl = intent_ttl_secs();
+
+    let (id, expires_at) = conn.call(move |conn| {
+        conn.execute(
+            "INSERT INTO payment_intents (wallet, action, bottle_id, price_octas, payload, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, unixepoch() + ?6)",
+            params![wallet, action.as_str(), bottle_id_owned, price_octas as i64, stored, ttl],
+        )?;
+        let id = conn.last_insert_rowid();
+        let expires_at: i64 = conn.query_row("SELECT expires_at FROM payment_intents WHERE id = ?1", [id], |row| row.get(0))?;
+        Ok((id, expires_at))
+    })
+    .await?;
+
+    Ok(PaymentIntent { id, action, bottle_id, price_octas, expires_at, payload: json, payload_bcs })
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+struct StoredIntent {
+    wallet: String,
+    action: String,
+    payload: String,
+    status: String,
+    created_at: i64,
+    expires_at: i64,
+    expired: bool,
+}
+
+/// Settle an intent with the wallet's submitted tx. Succeeds once per intent and per tx.
+pub async fn settle(db_path: &str, intent_id: i64, wallet: &str, action: PaidAction, tx_hash: &str) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let tx_hash = aptos_utils::normalize_tx_hash(tx_hash);
+
+    let lookup_tx = tx_hash.clone();
+    let (intent, reused) = conn.call(move |conn| {
+        let intent = conn.query_row(
+            "SELECT wallet, action, payload, status, created_at, expires_at, expires_at < unixepoch() FROM payment_intents WHERE id = ?1",
+            [intent_id],
+            |row| Ok(StoredIntent {
+                wallet: row.get(0)?,
+                action: row.get(1)?,
+                payload: row.get(2)?,
+                status: row.get(3)?,
+                created_at: row.get(4)?,
+                expires_at: row.get(5)?,
+                expired: row.get(6)?,
+            }),
+        )
+        .optional()?;
+        let reused = crate::credits::tx_spent(conn, &lookup_tx)?;
+        Ok((intent, reused))
+    })
+    .await?;
+
+    let intent = intent.ok_or(PaymentError::IntentNotFound)?;
+    if intent.wallet != wallet || intent.action != action.as_str() {
+        return Err(PaymentError::WrongIntent.into());
+    }
+    if intent.status == "paid" {
+        return Err(PaymentError::AlreadyPaid.into());
+    }
+    if reused {
+        return Err(PaymentError::TxReused.into());
+    }
+
+    let payload: EntryFunctionPayload = serde_json::from_str(&intent.payload)?;
+    let expected = ExpectedTx {
+        sender: wallet,
+        payload: &payload,
+        not_before_secs: Some(intent.created_at as u64),
+        // expiry is checked against the tx's commit time, a tx paid in time settles even if posted late
+        not_after_secs: Some(intent.expires_at as u64),
+    };
+    match aptos_utils::verify_tx(&tx_hash, &expected).await? {
+        TxStatus::Success => {},
+        status => return Err(rejected(status, intent.expired).into()),
+    }
+
+    conn.call(move |conn| Ok(mark_paid(conn, intent_id, &tx_hash)?)).await??;
+    Ok(())
+}
+
+/// Why a tx that did not verify cannot settle the intent.
+fn rejected(status: TxStatus, expired: bool) -> PaymentError {
+    match status {
+        // most likely committed after the intent expired, say so rather than a plain mismatch
+        TxStatus::Mismatch if expired => PaymentError::Expired,
+        TxStatus::Mismatch => PaymentError::TxMismatch,
+        status => PaymentError::Unconfirmed(status),
+    }
+}
+
+/// Record the verified tx on the intent. The checks made before asking the node are repeated under
+/// the write lock, a concurrent settlement or deposit may have used the intent or the tx meanwhile.
+fn mark_paid(conn: &mut rusqlite::Connection, intent_id: i64, tx_hash: &str) -> rusqlite::Result<Result<(), PaymentError>> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if crate::credits::tx_spent(&tx, tx_hash)? {
+        return Ok(Err(PaymentError::TxReused));
+    }
+    let updated = tx.execute(
+        "UPDATE payment_intents SET status = 'paid', tx_hash = ?2, paid_at = unixepoch() WHERE id = ?1 AND status = 'pending'",
+        params![intent_id, tx_hash],
+    )?;
+    if updated == 0 {
+        return Ok(Err(PaymentError::AlreadyPaid));
+    }
+    tx.commit()?;
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALLET: &str = "0xa11ce";
+
+    async fn test_db(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("lisa-payments-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_string_lossy().to_string();
+        let conn = Connection::open(&path).await.unwrap();
+        init_tables(&conn).await.unwrap();
+        crate::credits::init_tables(&conn).await.unwrap();
+        path
+    }
+
+    /// A pending intent created `age_secs` ago that expires `ttl_secs` after creation.
+    async fn pending_intent(db_path: &str, age_secs: i64, ttl_secs: i64) -> i64 {
+        let conn = Connection::open(db_path).await.unwrap();
+        conn.call(move |conn| {
+            conn.execute(
+                "INSERT INTO payment_intents (wallet, action, price_octas, payload, created_at, expires_at)
+                VALUES (?1, 'grade', 100, '{}', unixepoch() - ?2, unixepoch() - ?2 + ?3)",
+                params![WALLET, age_secs, ttl_secs],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn pay(db_path: &str, intent_id: i64, tx_hash: &str) -> Result<(), PaymentError> {
+        let conn = Connection::open(db_path).await.unwrap();
+        let tx_hash = tx_hash.to_string();
+        conn.call(move |conn| Ok(mark_paid(conn, intent_id, &tx_hash)?)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn an_intent_settles_once() {
+        let db_path = test_db("settle").await;
+        let intent_id = pending_intent(&db_path, 0, 900).await;
+
+        pay(&db_path, intent_id, "0x01").await.unwrap();
+        assert!(matches!(pay(&db_path, intent_id, "0x02").await, Err(PaymentError::AlreadyPaid)));
+    }
+
+    #[tokio::test]
+    async fn a_tx_settles_one_intent() {
+        let db_path = test_db("reuse").await;
+        let first = pending_intent(&db_path, 0, 900).await;
+        let second = pending_intent(&db_path, 0, 900).await;
+
+        pay(&db_path, first, "0x03").await.unwrap();
+        assert!(matches!(pay(&db_path, second, "0x03").await, Err(PaymentError::TxReused)));
+    }
+
+    #[tokio::test]
+    async fn a_deposited_tx_cannot_settle_an_intent() {
+        let db_path = test_db("deposited").await;
+        let intent_id = pending_intent(&db_path, 0, 900).await;
+        let conn = Connection::open(&db_path).await.unwrap();
+        conn.call(|conn| {
+            conn.execute(
+                "INSERT INTO credit_ledger (wallet, kind, amount_octas, tx_hash) VALUES (?1, 'deposit', 100, '0x04')",
+                [WALLET],
+            )?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(pay(&db_path, intent_id, "0x04").await, Err(PaymentError::TxReused)));
+    }
+
+    #[tokio::test]
+    async fn an_expired_intent_settles_with_a_tx_committed_in_time() {
+        let db_path = test_db("expired").await;
+        let intent_id = pending_intent(&db_path, 1000, 900).await;
+        let conn = Connection::open(&db_path).await.unwrap();
+        let (created_at, expires_at): (i64, i64) = conn.call(move |conn| {
+            Ok(conn.query_row("SELECT created_at, expires_at FROM payment_intents WHERE id = ?1", [intent_id], |row| Ok((row.get(0)?, row.get(1)?)))?)
+        })
+        .await
+        .unwrap();
+        let window = (Some(created_at as u64), Some(expires_at as u64));
+
+        // committed before the expiry, posted back after it
+        assert!(aptos_utils::within_window((expires_at as u64 - 10) * 1_000_000, window.0, window.1));
+        pay(&db_path, intent_id, "0x05").await.unwrap();
+
+        // committed after the expiry
+        assert!(!aptos_utils::within_window((expires_at as u64 + 10) * 1_000_000, window.0, window.1));
+        assert!(matches!(rejected(TxStatus::Mismatch, true), PaymentError::Expired));
+        assert!(matches!(rejected(TxStatus::Mismatch, false), PaymentError::TxMismatch));
+        assert!(matches!(rejected(TxStatus::Pending, true), PaymentError::Unconfirmed(TxStatus::Pending)));
+    }
+}
@@ -0,0 +1,179 @@
+use rusqlite::params;
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+
+use crate::credits;
+use crate::payments::{self, PaidAction};
+
+// Optional payment gate in front of `/api/retrive_drift`. Off by default; when on, each search is
+// paid in this order:
+//
+// 1. a payment intent settled by the tx sent along (`intent_id` + `tx_hash`, see `payments.rs`)
+// 2. the wallet's free searches of the day (UTC)
+// 3. the wallet's credits (see `credits.rs`)
+//
+// The intent's tx sender is verified on chain, free searches and credits belong to a wallet
+// anyone can name, so they are only used for a request signed in as it (see `wallet_auth.rs`).
+//
+// RETRIEVE_PAYWALL="off"          "on" to charge for searches
+// RETRIEVE_FREE_PER_DAY="0"       free searches per wallet and day before charging, 0 charges every search
+
+/// What paid for a search, returned with the results.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Charge {
+    // the paywall is off
+    Free,
+    FreeQuota { used: u32, limit: u32 },
+    Intent { intent_id: i64, price_octas: u64 },
+    Credits { debit_id: i64, price_octas: u64 },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PaywallError {
+    #[error("Sign in with this wallet to use its free searches and credits, or pay with a payment intent")]
+    SignInRequired,
+}
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS free_searches (
+                wallet TEXT NOT NULL,
+                day TEXT NOT NULL,
+                used INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (wallet, day)
+            );"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    std::env::var("RETRIEVE_PAYWALL").map(|value| value == "on").unwrap_or(false)
+}
+
+fn free_per_day() -> u32 {
+    std::env::var("RETRIEVE_FREE_PER_DAY")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Take one free search of today, None when they are used up.
+async fn use_free_search(db_path: &str, wallet: &str, limit: u32) -> Result<Option<u32>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let used = conn.call(move |conn| {
+        // the conditional upsert counts and checks in one statement, concurrent searches cannot overrun it
+        let taken = conn.execute(
+            "INSERT INTO free_searches (wallet, day, used) VALUES (?1, date('now'), 1)
+            ON CONFLICT (wallet, day) DO UPDATE SET used = used + 1 WHERE used < ?2",
+            params![wallet, limit],
+        )?;
+        if taken == 0 {
+            return Ok(None);
+        }
+        let used: u32 = conn.query_row(
+            "SELECT used FROM free_searches WHERE wallet = ?1 AND day = date('now')",
+            [wallet],
+            |row| row.get(0),
+        )?;
+        Ok(Some(used))
+    })
+    .await?;
+
+    Ok(used)
+}
+
+/// Pay for one search. `payment` is the intent id and tx hash the wallet sent, if any,
+/// `signed_in` whether the request is signed in as `wallet`.
+pub async fn charge(db_path: &str, wallet: &str, signed_in: bool, payment: Option<(i64, &str)>) -> Result<Charge, anyhow::Error> {
+    if !enabled() {
+        return Ok(Charge::Free);
+    }
+    charge_with_limit(db_path, wallet, signed_in, payment, free_per_day()).await
+}
+
+async fn charge_with_limit(db_path: &str, wallet: &str, signed_in: bool, payment: Option<(i64, &str)>, limit: u32) -> Result<Charge, anyhow::Error> {
+    if let Some((intent_id, tx_hash)) = payment {
+        payments::settle(db_path, intent_id, wallet, PaidAction::Retrieve, tx_hash).await?;
+        return Ok(Charge::Intent { intent_id, price_octas: PaidAction::Retrieve.price_octas() });
+    }
+    if !signed_in {
+        return Err(PaywallError::SignInRequired.into());
+    }
+
+    let used = if limit > 0 { use_free_search(db_path, wallet, limit).await? } else { None };
+    if let Some(used) = used {
+        return Ok(Charge::FreeQuota { used, limit });
+    }
+
+    let debit = credits::debit(db_path, wallet, PaidAction::Retrieve).await?;
+    Ok(Charge::Credits { debit_id: debit.id, price_octas: debit.amount_octas.unsigned_abs() })
+}
+
+/// Give back what a failed search took. A settled intent stays paid, the tx is on chain.
+pub async fn refund(db_path: &str, wallet: &str, charge: &Charge) -> Result<(), anyhow::Error> {
+    match charge {
+        Charge::FreeQuota { .. } => {
+            let conn = Connection::open(db_path).await?;
+            let wallet = wallet.to_string();
+            conn.call(move |conn| {
+                conn.execute(
+                    "UPDATE free_searches SET used = used - 1 WHERE wallet = ?1 AND day = date('now') AND used > 0",
+                    [wallet],
+                )?;
+                Ok(())
+            })
+            .await?;
+        },
+        Charge::Credits { debit_id, .. } => {
+            credits::refund(db_path, *debit_id).await?;
+        },
+        Charge::Free | Charge::Intent { .. } => {},
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WALLET: &str = "0xa11ce";
+
+    async fn test_db(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("lisa-paywall-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_string_lossy().to_string();
+        let conn = Connection::open(&path).await.unwrap();
+        payments::init_tables(&conn).await.unwrap();
+        credits::init_tables(&conn).await.unwrap();
+        init_tables(&conn).await.unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn an_unsigned_search_without_an_intent_is_refused() {
+        let db_path = test_db("unsigned").await;
+
+        let refused = charge_with_limit(&db_path, WALLET, false, None, 3).await.unwrap_err();
+        assert!(matches!(refused.downcast_ref::<PaywallError>(), Some(PaywallError::SignInRequired)));
+
+        // nothing was taken from the wallet's free searches
+        let charged = charge_with_limit(&db_path, WALLET, true, None, 3).await.unwrap();
+        assert!(matches!(charged, Charge::FreeQuota { used: 1, limit: 3 }));
+    }
+
+    #[tokio::test]
+    async fn a_signed_in_search_falls_back_to_credits() {
+        let db_path = test_db("credits").await;
+
+        charge_with_limit(&db_path, WALLET, true, None, 1).await.unwrap();
+        let refused = charge_with_limit(&db_path, WALLET, true, None, 1).await.unwrap_err();
+        assert!(matches!(refused.downcast_ref::<credits::CreditError>(), Some(credits::CreditError::InsufficientCredits { .. })));
+    }
+}
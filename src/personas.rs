@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent_impl::prompt_hub::{self, RenderedPrompt};
+use crate::agent_impl::RetrivalTool;
+use crate::membership::Tier;
+use crate::output_guard::{OutputGuard, OutputRules};
+
+// Characters the chat endpoint can play. `lisa` is built in, more personas (or an override
+// of `lisa`) are read from `PERSONAS_FILE` (default `personas.json`), a JSON array of `Persona`.
+// The file is read once on first use, a restart picks up changes; the prompt templates
+// a persona points at are still hot reloaded by `prompt_hub`.
+
+pub const LISA: &str = "lisa";
+
+/// Reply length depends on how much the patron wrote.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TokenPolicy {
+    pub short_max_tokens: u32,
+    pub long_max_tokens: u32,
+    // prompts longer than this many words get `long_max_tokens`
+    pub long_prompt_words: usize,
+    // long chats paid with credits get this regardless of the prompt
+    #[serde(default = "default_paid_max_tokens")]
+    pub paid_max_tokens: u32,
+    // premium wallets get at least this, see `membership.rs`
+    #[serde(default = "default_premium_max_tokens")]
+    pub premium_max_tokens: u32,
+}
+
+fn default_paid_max_tokens() -> u32 {
+    512
+}
+
+fn default_premium_max_tokens() -> u32 {
+    256
+}
+
+impl TokenPolicy {
+    pub fn max_tokens(&self, prompt: &str) -> u32 {
+        if crate::db_schemas::count_sequence_len(prompt) > self.long_prompt_words {
+            self.long_max_tokens
+        } else {
+            self.short_max_tokens
+        }
+    }
+
+    pub fn max_tokens_for(&self, prompt: &str, tier: Tier) -> u32 {
+        match tier {
+            Tier::Free => self.max_tokens(prompt),
+            Tier::Premium => self.max_tokens(prompt).max(self.premium_max_tokens),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RetrievalSettings {
+    pub top_k: usize,
+    pub min_score: f64,
+}
+
+impl Default for RetrievalSettings {
+    fn default() -> Self {
+        Self {
+            top_k: 2,
+            min_score: 0.7,
+        }
+    }
+}
+
+/// Long-term memories recalled into the chat, and whether new ones are extracted.
+/// Off unless the persona enables it, and even then only for patrons who opted in.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MemorySettings {
+    pub enabled: bool,
+    pub top_k: usize,
+    pub min_score: f64,
+}
+
+impl Default for MemorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            top_k: 5,
+            min_score: 0.3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Persona {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    // name of a `prompt_hub` template
+    pub prompt_template: String,
+    // `{{key}}` substitutions for the template, `word_limit` is filled in per request
+    #[serde(default)]
+    pub prompt_vars: HashMap<String, String>,
+    pub model: String,
+    pub temperature: f32,
+    pub token_policy: TokenPolicy,
+    // tool names, e.g. `search_related_story`
+    #[serde(default)]
+    pub tools: Vec<String>,
+    #[serde(default)]
+    pub retrieval: RetrievalSettings,
+    // context size of `model` in tokens, the chat history is trimmed to fit
+    #[serde(default = "default_context_window")]
+    pub context_window: usize,
+    // turns of a session sent verbatim, older ones only through the summary
+    #[serde(default = "default_history_turns")]
+    pub history_turns: usize,
+    #[serde(default)]
+    pub memory: MemorySettings,
+    // rules enforced on the streamed reply, all off unless configured
+    #[serde(default)]
+    pub output_rules: OutputRules,
+    // only premium wallets may chat with it
+    #[serde(default)]
+    pub premium: bool,
+}
+
+fn default_context_window() -> usize {
+    16384
+}
+
+fn default_history_turns() -> usize {
+    6
+}
+
+impl Persona {
+    pub fn allows_tool(&self, name: &str) -> bool {
+        self.tools.iter().any(|tool| tool == name)
+    }
+
+    pub fn render_prompt(&self, max_tokens: u32) -> RenderedPrompt {
+        let mut vars: Vec<(&str, String)> = self.prompt_vars.iter()
+            .map(|(key, value)| (key.as_str(), value.clone()))
+            .collect();
+        vars.push(("word_limit", prompt_hub::word_limit(max_tokens).to_string()));
+
+        prompt_hub::render(&self.prompt_template, &vars)
+    }
+
+    pub fn output_guard(&self, max_tokens: u32) -> OutputGuard {
+        OutputGuard::new(self.output_rules.clone(), prompt_hub::word_limit(max_tokens) as usize)
+    }
+
+    pub fn retrival_tool(&self, wallet: &str) -> RetrivalTool {
+        RetrivalTool::for_wallet(wallet).with_settings(self.retrieval.clone())
+    }
+}
+
+/// What the frontend gets from `GET /api/personas`, prompts stay on the server.
+#[derive(Serialize)]
+pub struct PersonaInfo {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub model: String,
+    pub tools: Vec<String>,
+    pub premium: bool,
+}
+
+impl From<&Persona> for PersonaInfo {
+    fn from(persona: &Persona) -> Self {
+        Self {
+            id: persona.id.clone(),
+            name: persona.name.clone(),
+            description: persona.description.clone(),
+            model: persona.model.clone(),
+            tools: persona.tools.clone(),
+            premium: persona.premium,
+        }
+    }
+}
+
+fn lisa() -> Persona {
+    let bar_name = std::env::var("BAR_NAME").unwrap_or(prompt_hub::DEFAULT_BAR_NAME.to_string());
+
+    Persona {
+        id: LISA.to_string(),
+        name: "Lisa".to_string(),
+        description: "Cyber-enhanced bartender who listens to your stories".to_string(),
+        prompt_template: prompt_hub::CHAT_AGENT.to_string(),
+        prompt_vars: HashMap::from([
+            ("bar_name".to_string(), bar_name),
+            ("persona_traits".to_string(), prompt_hub::DEFAULT_PERSONA_TRAITS.to_string()),
+        ]),
+        model: "deepseek-ai/DeepSeek-V3".to_string(),
+        temperature: 0.9,
+        token_policy: TokenPolicy {
+            short_max_tokens: 64,
+            long_max_tokens: 128,
+            long_prompt_words: 64,
+            paid_max_tokens: default_paid_max_tokens(),
+            premium_max_tokens: default_premium_max_tokens(),
+        },
+        tools: Vec::new(),
+        retrieval: RetrievalSettings::default(),
+        context_window: 65536,
+        history_turns: default_history_turns(),
+        memory: MemorySettings::default(),
+        output_rules: OutputRules::all(),
+        premium: false,
+    }
+}
+
+// built-ins first, then the file, in file order
+static PERSONAS: LazyLock<Vec<Persona>> = LazyLock::new(|| {
+    let mut personas = vec![lisa()];
+
+    let path = std::env::var("PERSONAS_FILE").unwrap_or("personas.json".to_string());
+    let loaded: Vec<Persona> = match std::fs::read_to_string(&path) {
+        Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+            println!("Failed to parse {}: {}", path, e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+
+    for persona in loaded {
+        match personas.iter_mut().find(|p| p.id == persona.id) {
+            Some(existing) => *existing = persona,
+            None => personas.push(persona),
+        }
+    }
+    personas
+});
+
+pub fn default_persona_id() -> String {
+    std::env::var("DEFAULT_PERSONA").unwrap_or(LISA.to_string())
+}
+
+/// Look up a persona, `None` picks `DEFAULT_PERSONA`.
+pub fn get(id: Option<&str>) -> Option<&'static Persona> {
+    let id = id.map(|id| id.to_string()).unwrap_or_else(default_persona_id);
+    PERSONAS.iter().find(|persona| persona.id == id)
+}
+
+pub fn list() -> Vec<PersonaInfo> {
+    PERSONAS.iter().map(PersonaInfo::from).collect()
+}
@@ -0,0 +1,173 @@
+use rusqlite::params;
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+
+// Picking up a random bottle: one public bottle the wallet has neither written nor seen,
+// drawn with a weight that favours recent and well graded bottles.
+//
+// PICKUP_POOL="200"            most recent eligible bottles to draw from
+// PICKUP_HALF_LIFE_DAYS="7"    a bottle this old weighs half as much as a new one
+
+// old bottles keep a chance of washing up
+const MIN_RECENCY_WEIGHT: f64 = 0.05;
+const SECS_PER_DAY: f64 = 86_400.0;
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS seen_bottles (
+                wallet TEXT NOT NULL,
+                bottle_id TEXT NOT NULL,
+                seen_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                PRIMARY KEY (wallet, bottle_id)
+            );"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn pool_size() -> usize {
+    std::env::var("PICKUP_POOL")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(200)
+}
+
+fn half_life_days() -> f64 {
+    std::env::var("PICKUP_HALF_LIFE_DAYS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|days: &f64| *days > 0.0)
+        .unwrap_or(7.0)
+}
+
+#[derive(Default)]
+pub struct PickupFilter {
+    pub emotion: Option<String>,
+    pub topic: Option<String>,
+}
+
+/// A whole bottle, all chunks joined. The author stays anonymous, replies go to `id`.
+#[derive(Serialize)]
+pub struct PickedBottle {
+    pub id: String,
+    pub title: String,
+    pub content: String,
+    pub emotion: Option<String>,
+    pub topic: Option<String>,
+    pub grade: Option<i64>,
+    pub created_at: Option<i64>,
+    // tx recording the bottle's content hash, see `anchoring.rs`
+    pub anchor_tx: Option<String>,
+}
+
+struct Eligible {
+    bottle: PickedBottle,
+    wallet: String,
+    // uniform in [0, 1), drawn by sqlite
+    draw: f64,
+}
+
+fn weight(grade: Option<i64>, created_at: Option<i64>, now: i64, half_life_days: f64) -> f64 {
+    let recency = match created_at {
+        Some(created_at) => {
+            let age_days = (now - created_at).max(0) as f64 / SECS_PER_DAY;
+            0.5f64.powf(age_days / half_life_days).max(MIN_RECENCY_WEIGHT)
+        },
+        None => MIN_RECENCY_WEIGHT,
+    };
+    // 0.5 for a 0 grade up to 1.5 for 100, ungraded bottles sit in the middle
+    let quality = grade.map(|grade| 0.5 + grade.clamp(0, 100) as f64 / 100.0).unwrap_or(1.0);
+    recency * quality
+}
+
+/// (id, content) of all chunks of a bottle in order, `title` without the chunk suffix.
+pub(crate) fn bottle_chunks(conn: &rusqlite::Connection, wallet: &str, title: &str) -> rusqlite::Result<Vec<(String, String)>> {
+    let prefix = format!("{}-", title);
+    let mut stmt = conn.prepare(
+        "SELECT id, title, content FROM drift_bottles WHERE wallet = ?1 AND substr(title, 1, ?2) = ?3"
+    )?;
+    let mut chunks = stmt.query_map(params![wallet, prefix.chars().count() as i64, prefix], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?
+    .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?
+    .into_iter()
+    .filter_map(|(id, chunk_title, content)| {
+        chunk_title[prefix.len()..].parse::<usize>().ok().map(|index| (index, id, content))
+    })
+    .collect::<Vec<_>>();
+    chunks.sort_by_key(|(index, _, _)| *index);
+    Ok(chunks.into_iter().map(|(_, id, content)| (id, content)).collect())
+}
+
+/// All chunks of a bottle joined in order.
+pub(crate) fn full_content(conn: &rusqlite::Connection, wallet: &str, title: &str) -> rusqlite::Result<String> {
+    let chunks = bottle_chunks(conn, wallet, title)?;
+    Ok(chunks.into_iter().map(|(_, content)| content).collect::<Vec<_>>().join(" "))
+}
+
+/// Draw a bottle for `wallet` and mark it seen. `None` when nothing is left to find.
+pub async fn pickup(db_path: &str, wallet: &str, filter: PickupFilter) -> Result<Option<PickedBottle>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+    let (pool, half_life) = (pool_size(), half_life_days());
+
+    let picked = conn.call(move |conn| {
+        let now: i64 = conn.query_row("SELECT unixepoch()", [], |row| row.get(0))?;
+
+        let tx = conn.transaction()?;
+        let eligible = {
+            let mut stmt = tx.prepare(
+                "SELECT b.id, b.wallet, b.title, m.emotion, m.topic, m.grade, m.created_at,
+                    (random() & 4294967295) / 4294967296.0, m.anchor_tx
+                FROM drift_bottles b LEFT JOIN bottle_meta m ON m.wallet = b.wallet AND b.title = m.title || '-0'
+                WHERE b.title LIKE '%-0' AND b.wallet != ?1 AND COALESCE(m.public, 1) = 1
+                    AND NOT EXISTS (SELECT 1 FROM hidden_bottles h WHERE h.bottle_id = b.id)
+                    AND NOT EXISTS (SELECT 1 FROM seen_bottles s WHERE s.wallet = ?1 AND s.bottle_id = b.id)
+                    AND (?2 IS NULL OR m.emotion = ?2)
+                    AND (?3 IS NULL OR m.topic = ?3)
+                ORDER BY b.rowid DESC LIMIT ?4"
+            )?;
+            stmt.query_map(params![wallet, filter.emotion, filter.topic, pool as i64], |row| {
+                let title: String = row.get(2)?;
+                Ok(Eligible {
+                    bottle: PickedBottle {
+                        id: row.get(0)?,
+                        title: title.strip_suffix("-0").unwrap_or(&title).to_string(),
+                        content: String::new(),
+                        emotion: row.get(3)?,
+                        topic: row.get(4)?,
+                        grade: row.get(5)?,
+                        created_at: row.get(6)?,
+                        anchor_tx: row.get(8)?,
+                    },
+                    wallet: row.get(1)?,
+                    draw: row.get(7)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?
+        };
+
+        // weighted sampling without a rand dependency: the largest draw^(1/weight) wins
+        let Some(mut picked) = eligible.into_iter().max_by(|a, b| {
+            let key = |e: &Eligible| e.draw.powf(1.0 / weight(e.bottle.grade, e.bottle.created_at, now, half_life));
+            key(a).total_cmp(&key(b))
+        }) else {
+            return Ok(None);
+        };
+
+        picked.bottle.content = full_content(&tx, &picked.wallet, &picked.bottle.title)?;
+
+        tx.execute(
+            "INSERT OR IGNORE INTO seen_bottles (wallet, bottle_id) VALUES (?1, ?2)",
+            params![wallet, picked.bottle.id],
+        )?;
+        tx.commit()?;
+        Ok(Some(picked.bottle))
+    })
+    .await?;
+
+    Ok(picked)
+}
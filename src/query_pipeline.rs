@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use crate::agent_impl::{prompt_hub, RetrivalAgent};
+use crate::agent_impl::metering::{self, CompletionMeta};
+use crate::db_schemas::{search_drift_vec, DriftBottle, VectorDBFromEnv};
+use crate::personas::RetrievalSettings;
+use crate::{moderation, reactions};
+use crate::rerank::{self, Candidate, ConfiguredReranker};
+
+// Retrieval shared by `RetrivalTool` and `/api/retrive_drift`:
+//
+// 1. understand: rewrite the message into search queries, optionally write a hypothetical story (HyDE)
+// 2. search: one vector search per query, fused with reciprocal rank fusion
+// 3. rerank: see `rerank.rs`, weight by reader reactions (`reactions::prior`), then keep the top k
+//    hidden bottles and stories the wallet marked not relevant for the same message are dropped
+//    right after the search, which is widened while they crowd out the rest
+//
+// QUERY_REWRITE="false"        rewrite the message, one extra model call per search (default off)
+// QUERY_REWRITE_COUNT="3"      queries to ask for, the original message is always searched too
+// QUERY_HYDE="false"           also embed a hypothetical story (default off)
+// QUERY_MODEL_NAME             model for both, default `Qwen/Qwen2.5-7B-Instruct`
+
+const REWRITE_MAX_TOKENS: u32 = 256;
+const HYDE_MAX_TOKENS: u32 = 192;
+// the usual RRF constant, damps the weight of the first ranks
+const RRF_K: f64 = 60.0;
+// how far the search is widened past what is kept, when dropped stories leave too few
+const MAX_FETCH_FACTOR: usize = 4;
+
+fn env_flag(name: &str, default: bool) -> bool {
+    std::env::var(name)
+        .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "on" | "yes"))
+        .unwrap_or(default)
+}
+
+pub struct QuerySettings {
+    pub rewrite: bool,
+    pub rewrite_count: usize,
+    pub hyde: bool,
+    pub model_name: String,
+}
+
+impl QuerySettings {
+    pub fn from_env() -> Self {
+        Self {
+            rewrite: env_flag("QUERY_REWRITE", false),
+            rewrite_count: std::env::var("QUERY_REWRITE_COUNT").ok().and_then(|s| s.parse().ok()).unwrap_or(3),
+            hyde: env_flag("QUERY_HYDE", false),
+            model_name: std::env::var("QUERY_MODEL_NAME").unwrap_or("Qwen/Qwen2.5-7B-Instruct".to_string()),
+        }
+    }
+}
+
+/// What gets searched for one message.
+pub struct QueryPlan {
+    // the original message first
+    pub queries: Vec<String>,
+    pub hypothetical: Option<String>,
+    // versions of the prompts that produced the plan, e.g. `rewrite-2026-10-19`
+    pub prompt_versions: Vec<String>,
+}
+
+impl QueryPlan {
+    fn texts(&self) -> Vec<String> {
+        self.queries.iter().cloned().chain(self.hypothetical.clone()).collect()
+    }
+}
+
+async fn complete(settings: &QuerySettings, wallet: Option<&str>, template: &str, vars: &[(&str, String)], max_tokens: u32, temperature: f32) -> Result<(String, String), anyhow::Error> {
+    let rendered = prompt_hub::render(template, vars);
+    let db_path = VectorDBFromEnv::new().await?.db_path;
+    let agent = RetrivalAgent::new_builder(
+        rendered.text.clone(),
+        Some(max_tokens),
+        Some(temperature),
+        Some(settings.model_name.clone())).await?
+        .build();
+    let meta = CompletionMeta::new(&db_path, wallet, &settings.model_name, &rendered.text)
+        .with_prompt_version(&rendered.version);
+    let response = metering::metered_prompt(&agent, &meta, "Go.").await?;
+    Ok((response, rendered.version))
+}
+
+fn parse_queries(response: &str) -> Vec<String> {
+    let parsed: Option<Vec<String>> = response.find('[')
+        .zip(response.rfind(']'))
+        .and_then(|(start, end)| serde_json::from_str(&response[start..=end]).ok());
+    parsed.unwrap_or_else(|| {
+        response.lines()
+            .map(|line| line.trim().trim_start_matches(|c: char| c == '-' || c.is_ascii_digit() || c == '.').trim().to_string())
+            .collect()
+    })
+    .into_iter()
+    .filter(|query| !query.is_empty())
+    .collect()
+}
+
+/// Build the queries for a message. A failing step only loses its queries.
+pub async fn plan(message: &str, settings: &QuerySettings, wallet: Option<&str>) -> QueryPlan {
+    let rewrite = async {
+        if !settings.rewrite || settings.rewrite_count == 0 {
+            return None;
+        }
+        let vars = [("message", message.to_string()), ("count", settings.rewrite_count.to_string())];
+        match complete(settings, wallet, prompt_hub::QUERY_REWRITE, &vars, REWRITE_MAX_TOKENS, 0.3).await {
+            Ok((response, version)) => Some((parse_queries(&response), version)),
+            Err(e) => {
+                println!("Query rewriting failed: {}", e);
+                None
+            }
+        }
+    };
+    let hyde = async {
+        if !settings.hyde {
+            return None;
+        }
+        let vars = [("message", message.to_string())];
+        match complete(settings, wallet, prompt_hub::HYDE_STORY, &vars, HYDE_MAX_TOKENS, 0.7).await {
+            Ok((response, version)) => Some((response.trim().to_string(), version)),
+            Err(e) => {
+                println!("HyDE failed: {}", e);
+                None
+            }
+        }
+    };
+    let (rewritten, hypothetical) = futures::join!(rewrite, hyde);
+
+    let mut plan = QueryPlan {
+        queries: vec![message.to_string()],
+        hypothetical: None,
+        prompt_versions: Vec::new(),
+    };
+    if let Some((queries, version)) = rewritten {
+        for query in queries.into_iter().take(settings.rewrite_count) {
+            if !plan.queries.contains(&query) {
+                plan.queries.push(query);
+            }
+        }
+        plan.prompt_versions.push(version);
+    }
+    if let Some((story, version)) = hypothetical {
+        if !story.is_empty() {
+            plan.hypothetical = Some(story);
+        }
+        plan.prompt_versions.push(version);
+    }
+    plan
+}
+
+/// Reciprocal rank fusion of the per-query results. Candidates below `min_score` on every query are dropped.
+fn fuse(result_lists: Vec<Vec<(f64, DriftBottle)>>, min_score: f64) -> Vec<Candidate> {
+    let single = result_lists.len() == 1;
+    let mut fused: HashMap<String, Candidate> = HashMap::new();
+
+    for results in result_lists {
+        for (rank, (vector_score, bottle)) in results.into_iter().enumerate() {
+            let rrf = 1.0 / (RRF_K + rank as f64 + 1.0);
+            let candidate = fused.entry(bottle.id.clone()).or_insert(Candidate {
+                vector_score,
+                fused_score: if single { None } else { Some(0.0) },
+                rerank_score: None,
+                prior: None,
+                reactions: Default::default(),
+                bottle,
+            });
+            candidate.vector_score = candidate.vector_score.max(vector_score);
+            if let Some(fused_score) = candidate.fused_score.as_mut() {
+                *fused_score += rrf;
+            }
+        }
+    }
+
+    let mut candidates: Vec<Candidate> = fused.into_values()
+        .filter(|candidate| candidate.vector_score > min_score)
+        .collect();
+    candidates.sort_by(|a, b| b.score().total_cmp(&a.score()));
+    candidates
+}
+
+pub struct Retrieval {
+    pub plan: QueryPlan,
+    // best first, at most `top_k`
+    pub candidates: Vec<Candidate>,
+}
+
+/// The full pipeline for one message.
+pub async fn retrieve(message: &str, settings: &RetrievalSettings, wallet: Option<&str>) -> Result<Retrieval, anyhow::Error> {
+    let plan = plan(message, &QuerySettings::from_env(), wallet).await;
+
+    let reranker = ConfiguredReranker::from_env(wallet);
+    let fetch = if reranker.is_enabled() {
+        rerank::candidate_count().max(settings.top_k)
+    } else {
+        settings.top_k
+    };
+
+    let db_path = VectorDBFromEnv::new().await?.db_path;
+    let dismissed = match wallet {
+        Some(wallet) => reactions::dismissed(&db_path, wallet, message).await?,
+        None => Default::default(),
+    };
+
+    // filtered before truncating, so hidden and dismissed stories do not take the places of visible ones
+    let mut k = fetch;
+    let mut candidates = loop {
+        let searches = plan.texts()
+            .into_iter()
+            .map(|text| async move { search_drift_vec(&text, k, wallet).await });
+        let result_lists = futures::future::join_all(searches)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        let exhausted = result_lists.iter().all(|results| results.len() < k);
+
+        let mut candidates = fuse(result_lists, settings.min_score);
+        let chunk_ids: Vec<String> = candidates.iter().map(|candidate| candidate.bottle.id.clone()).collect();
+        let hidden = moderation::hidden_chunks(&db_path, &chunk_ids).await?;
+        let found = candidates.len();
+        candidates.retain(|candidate| !hidden.contains(&candidate.bottle.id) && !dismissed.contains(&candidate.bottle.id));
+
+        let dropped = found - candidates.len();
+        if candidates.len() >= fetch || dropped == 0 || exhausted || k >= fetch * MAX_FETCH_FACTOR {
+            break candidates;
+        }
+        k *= 2;
+    };
+    candidates.truncate(fetch);
+
+    let mut candidates = if reranker.is_enabled() {
+        rerank::rerank(&reranker, message, candidates).await
+    } else {
+        candidates
+    };
+
+    let chunk_ids: Vec<String> = candidates.iter().map(|candidate| candidate.bottle.id.clone()).collect();
+    let mut counts = reactions::counts(&db_path, &chunk_ids).await?;
+    for candidate in candidates.iter_mut() {
+        if let Some(reaction_counts) = counts.remove(&candidate.bottle.id) {
+            candidate.prior = Some(reactions::prior(&reaction_counts));
+            candidate.reactions = reaction_counts;
+        }
+    }
+    candidates.sort_by(|a, b| b.score().total_cmp(&a.score()));
+    rerank::log_scores(message, &candidates);
+    candidates.truncate(settings.top_k);
+
+    Ok(Retrieval { plan, candidates })
+}
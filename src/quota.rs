@@ -0,0 +1,93 @@
+use rusqlite::params;
+use tokio_rusqlite::Connection;
+
+// Daily LLM-token quota per wallet. Days are UTC dates, as SQLite's `date('now')` returns them.
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS wallet_daily_tokens (
+                wallet TEXT NOT NULL,
+                day TEXT NOT NULL,
+                tokens INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (wallet, day)
+            );"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+/// Tokens a wallet may spend per day, 0 disables the quota.
+pub fn daily_token_quota() -> u64 {
+    std::env::var("DAILY_TOKEN_QUOTA")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
+
+pub async fn tokens_used_today(db_path: &str, wallet: &str) -> Result<u64, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    let used: i64 = conn.call(move |conn| {
+        let used = conn.query_row(
+            "SELECT COALESCE(SUM(tokens), 0) FROM wallet_daily_tokens WHERE wallet = ?1 AND day = date('now')",
+            [wallet],
+            |row| row.get(0),
+        )?;
+        Ok(used)
+    })
+    .await?;
+
+    Ok(used as u64)
+}
+
+pub async fn add_tokens(db_path: &str, wallet: &str, tokens: u64) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let wallet = wallet.to_string();
+
+    conn.call(move |conn| {
+        conn.execute(
+            "INSERT INTO wallet_daily_tokens (wallet, day, tokens) VALUES (?1, date('now'), ?2)
+            ON CONFLICT (wallet, day) DO UPDATE SET tokens = tokens + excluded.tokens",
+            params![wallet, tokens as i64],
+        )?;
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Seconds until the quota resets at the next UTC midnight.
+pub fn seconds_until_reset() -> u64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    86400 - now % 86400
+}
+
+/// Records the tokens of a streamed response once the stream is dropped,
+/// so a client hanging up halfway is still charged for what was generated.
+/// Completions that are awaited call `add_tokens` directly.
+pub struct TokenCharge {
+    pub db_path: String,
+    pub wallet: String,
+    pub tokens: u64,
+}
+
+impl Drop for TokenCharge {
+    fn drop(&mut self) {
+        let db_path = std::mem::take(&mut self.db_path);
+        let wallet = std::mem::take(&mut self.wallet);
+        let tokens = self.tokens;
+        tokio::spawn(async move {
+            if let Err(e) = add_tokens(&db_path, &wallet, tokens).await {
+                println!("Failed to record token usage for {}: {}", wallet, e);
+            }
+        });
+    }
+}
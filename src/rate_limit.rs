@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::http::Method;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::db_schemas::VectorDBFromEnv;
+use crate::quota;
+use crate::request_model::RateLimitedResponse;
+
+// Token buckets per (route, wallet) and per (route, client ip).
+// Each limit is configured as "<requests>/<seconds>", e.g. RATE_LIMIT_CHAT_WALLET="20/60":
+// a bucket holds 20 tokens and refills at 20 tokens per 60 seconds. "0" disables a limit.
+
+// (method, route, env name, default per wallet, default per ip, counts against the daily token quota)
+const ROUTES: [(Method, &str, &str, &str, &str, bool); 11] = [
+    (Method::POST, "/api/chat", "CHAT", "20/60", "60/60", true),
+    (Method::GET, "/api/retrive_drift", "RETRIVE_DRIFT", "10/60", "30/60", true),
+    (Method::POST, "/api/store_drift", "STORE_DRIFT", "5/60", "20/60", false),
+    // one limit across all bottles, keyed by the route pattern; reading replies is not limited
+    (Method::POST, "/api/bottles/{id}/replies", "BOTTLE_REPLIES", "10/60", "30/60", false),
+    (Method::POST, "/api/bottles/pickup", "BOTTLE_PICKUP", "10/60", "30/60", false),
+    (Method::POST, "/api/bottles/{id}/reactions", "BOTTLE_REACTIONS", "30/60", "60/60", false),
+    (Method::POST, "/api/bottles/{id}/report", "BOTTLE_REPORT", "5/60", "20/60", false),
+    (Method::POST, "/api/bottles/{id}/anchor", "BOTTLE_ANCHOR", "5/60", "20/60", false),
+    (Method::POST, "/api/payments/intent", "PAYMENT_INTENT", "10/60", "30/60", false),
+    (Method::POST, "/api/credits/deposit", "CREDIT_DEPOSIT", "5/60", "20/60", false),
+    (Method::POST, "/api/auth/challenge", "AUTH_CHALLENGE", "5/60", "20/60", false),
+];
+
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Clone, Copy)]
+struct Limit {
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+impl Limit {
+    fn parse(value: &str) -> Option<Self> {
+        let (requests, seconds) = value.split_once('/')?;
+        let requests: f64 = requests.trim().parse().ok()?;
+        let seconds: f64 = seconds.trim().parse().ok()?;
+        if requests <= 0.0 || seconds <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            capacity: requests,
+            refill_per_sec: requests / seconds,
+        })
+    }
+
+    fn from_env(name: &str, default: &str) -> Option<Self> {
+        match std::env::var(name) {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Self::parse(default),
+        }
+    }
+}
+
+struct RouteRule {
+    per_wallet: Option<Limit>,
+    per_ip: Option<Limit>,
+    token_quota: bool,
+}
+
+struct Bucket {
+    limit: Limit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.limit.refill_per_sec).min(self.limit.capacity)
+    }
+
+    // take one token, or return how long until one is available
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.tokens = self.refilled(now);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.limit.refill_per_sec))
+        }
+    }
+}
+
+pub struct RateLimiter {
+    rules: HashMap<(Method, &'static str), RouteRule>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+/// The client's address, from X-Forwarded-For when TRUST_PROXY_HEADERS is set.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let trust_proxy = std::env::var("TRUST_PROXY_HEADERS").map(|v| v == "1" || v == "true").unwrap_or(false);
+    if trust_proxy {
+        req.connection_info().realip_remote_addr().map(|s| s.to_string())
+    } else {
+        req.peer_addr().map(|addr| addr.ip().to_string())
+    }
+}
+
+impl RateLimiter {
+    pub fn from_env() -> Self {
+        let rules = ROUTES.iter()
+            .map(|(method, route, name, wallet_default, ip_default, token_quota)| {
+                ((method.clone(), *route), RouteRule {
+                    per_wallet: Limit::from_env(&format!("RATE_LIMIT_{}_WALLET", name), wallet_default),
+                    per_ip: Limit::from_env(&format!("RATE_LIMIT_{}_IP", name), ip_default),
+                    token_quota: *token_quota,
+                })
+            })
+            .collect();
+
+        Self {
+            rules,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn take(&self, key: String, limit: Limit) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > MAX_BUCKETS {
+            // a bucket that refilled completely carries no state
+            buckets.retain(|_, bucket| bucket.refilled(now) < bucket.limit.capacity);
+        }
+
+        buckets.entry(key)
+            .or_insert(Bucket { limit, tokens: limit.capacity, updated: now })
+            .take(now)
+    }
+}
+
+#[derive(Deserialize)]
+struct WalletField {
+    wallet: String,
+}
+
+fn too_many_requests(status: &str, retry_after: u64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(RateLimitedResponse {
+            status: status.to_string(),
+            retry_after,
+        })
+}
+
+pub async fn rate_limit<B: MessageBody + 'static>(
+    mut req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let Some(limiter) = req.app_data::<web::Data<RateLimiter>>().cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    let path = req.match_pattern().unwrap_or(req.path().to_string());
+    let Some(rule) = limiter.rules.get(&(req.method().clone(), path.as_str())) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    let route = format!("{} {}", req.method(), path);
+
+    // per client ip
+    if let Some(limit) = rule.per_ip {
+        if let Some(ip) = client_ip(req.request()) {
+            if let Err(wait) = limiter.take(format!("{} ip {}", route, ip), limit) {
+                let response = too_many_requests("Too many requests from this address", wait.as_secs() + 1);
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+        }
+    }
+
+    if rule.per_wallet.is_none() && !rule.token_quota {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    // the wallet lives in the json body, read it and put it back for the handler
+    let body = req.extract::<web::Bytes>().await?;
+    let wallet = serde_json::from_slice::<WalletField>(&body).ok().map(|field| field.wallet);
+    req.set_payload(Payload::from(body));
+
+    if let Some(wallet) = wallet {
+        if let Some(limit) = rule.per_wallet {
+            if let Err(wait) = limiter.take(format!("{} wallet {}", route, wallet), limit) {
+                let response = too_many_requests("Too many requests from this wallet", wait.as_secs() + 1);
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+        }
+
+        let token_quota = quota::daily_token_quota();
+        if rule.token_quota && token_quota > 0 {
+            let vcdb_from_env = VectorDBFromEnv::new().await.map_err(actix_web::error::ErrorInternalServerError)?;
+            let used = quota::tokens_used_today(&vcdb_from_env.db_path, &wallet)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            if used >= token_quota {
+                let response = too_many_requests("Daily token quota exceeded", quota::seconds_until_reset());
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tokio_rusqlite::Connection;
+
+use crate::replies::resolve_bottle;
+
+// Reader feedback on retrieved bottles. "felt this" and "helped me" count once per wallet and
+// bottle; "not relevant" is recorded per query, against the chunk that was retrieved, so search
+// quality can be evaluated from it. The counts feed a ranking prior in the query pipeline.
+//
+// Reacting needs a signed-in wallet, but key pairs cost nothing, so the counts and the prior go by
+// distinct client addresses: one address moves a bottle's prior as much as one reader.
+//
+// REACTION_PRIOR_WEIGHT="0.1"   0 turns the prior off
+
+// the prior moves a score by at most this factor either way
+const MAX_PRIOR: f64 = 0.5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Reaction {
+    FeltThis,
+    HelpedMe,
+    NotRelevant,
+}
+
+impl Reaction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reaction::FeltThis => "felt_this",
+            Reaction::HelpedMe => "helped_me",
+            Reaction::NotRelevant => "not_relevant",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReactionError {
+    #[error("Bottle not found")]
+    BottleNotFound,
+    #[error("You cannot react to your own bottle")]
+    OwnBottle,
+    #[error("A `not_relevant` reaction needs the query the bottle was retrieved for")]
+    MissingQuery,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ReactionCounts {
+    pub felt_this: u64,
+    pub helped_me: u64,
+    // distinct clients, over all queries
+    pub not_relevant: u64,
+}
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS bottle_reactions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                bottle_id TEXT NOT NULL,
+                chunk_id TEXT NOT NULL,
+                wallet TEXT NOT NULL,
+                reaction TEXT NOT NULL,
+                query TEXT NOT NULL DEFAULT '',
+                created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+                UNIQUE (bottle_id, wallet, reaction, query)
+            );
+            CREATE INDEX IF NOT EXISTS idx_bottle_reactions_wallet ON bottle_reactions(wallet, reaction, query);"
+        )?;
+
+        // added with per-address counting, older rows count by wallet
+        let has_ip: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('bottle_reactions') WHERE name = 'ip'",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_ip == 0 {
+            conn.execute_batch("ALTER TABLE bottle_reactions ADD COLUMN ip TEXT")?;
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn prior_weight() -> f64 {
+    std::env::var("REACTION_PRIOR_WEIGHT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0.1)
+}
+
+// queries are compared after trimming and lowercasing
+fn normalize_query(query: &str) -> String {
+    query.trim().to_lowercase()
+}
+
+/// Record a reaction on the bottle `chunk_id` belongs to, from a signed-in wallet at client address `ip`.
+/// Returns false if it was already recorded.
+pub async fn react(db_path: &str, chunk_id: &str, wallet: &str, ip: Option<&str>, reaction: Reaction, query: Option<&str>) -> Result<bool, anyhow::Error> {
+    let query = match reaction {
+        Reaction::NotRelevant => {
+            let query = query.map(normalize_query).filter(|query| !query.is_empty()).ok_or(ReactionError::MissingQuery)?;
+            crate::db_schemas::validate_text("query", &query)?;
+            query
+        },
+        _ => String::new(),
+    };
+
+    let conn = Connection::open(db_path).await?;
+    let (chunk_id, wallet, ip) = (chunk_id.to_string(), wallet.to_string(), ip.map(|ip| ip.to_string()));
+
+    let inserted = conn.call(move |conn| {
+        let Some((bottle_id, author_wallet)) = resolve_bottle(conn, &chunk_id)? else {
+            return Ok(Err(ReactionError::BottleNotFound));
+        };
+        // authors could only inflate their own prior
+        if author_wallet == wallet {
+            return Ok(Err(ReactionError::OwnBottle));
+        }
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO bottle_reactions (bottle_id, chunk_id, wallet, reaction, query, ip) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![bottle_id, chunk_id, wallet, reaction.as_str(), query, ip],
+        )?;
+        Ok(Ok(inserted > 0))
+    })
+    .await??;
+
+    Ok(inserted)
+}
+
+/// Counts for the bottles of the given chunks, keyed by chunk id.
+pub async fn counts(db_path: &str, chunk_ids: &[String]) -> Result<HashMap<String, ReactionCounts>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let chunk_ids = chunk_ids.to_vec();
+
+    let counts = conn.call(move |conn| {
+        let mut counts = HashMap::new();
+        for chunk_id in chunk_ids {
+            let Some((bottle_id, _)) = resolve_bottle(conn, &chunk_id)? else {
+                continue;
+            };
+            let mut stmt = conn.prepare_cached(
+                "SELECT reaction, COUNT(DISTINCT COALESCE(ip, wallet)) FROM bottle_reactions WHERE bottle_id = ?1 GROUP BY reaction"
+            )?;
+            let mut bottle_counts = ReactionCounts::default();
+            let rows = stmt.query_map([&bottle_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64)))?;
+            for row in rows {
+                let (reaction, count) = row?;
+                match reaction.as_str() {
+                    "felt_this" => bottle_counts.felt_this = count,
+                    "helped_me" => bottle_counts.helped_me = count,
+                    "not_relevant" => bottle_counts.not_relevant = count,
+                    _ => {},
+                }
+            }
+            counts.insert(chunk_id, bottle_counts);
+        }
+        Ok(counts)
+    })
+    .await?;
+
+    Ok(counts)
+}
+
+/// Chunks the wallet marked not relevant for this very query.
+pub async fn dismissed(db_path: &str, wallet: &str, query: &str) -> Result<HashSet<String>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (wallet, query) = (wallet.to_string(), normalize_query(query));
+
+    let dismissed = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT chunk_id FROM bottle_reactions WHERE wallet = ?1 AND reaction = 'not_relevant' AND query = ?2"
+        )?;
+        let dismissed = stmt.query_map(params![wallet, query], |row| row.get(0))?
+            .collect::<std::result::Result<HashSet<String>, rusqlite::Error>>()?;
+        Ok(dismissed)
+    })
+    .await?;
+
+    Ok(dismissed)
+}
+
+/// Multiplier for a candidate's score, 1.0 without reactions or with the prior turned off.
+pub fn prior(counts: &ReactionCounts) -> f64 {
+    let positive = (counts.felt_this + counts.helped_me) as f64;
+    let negative = counts.not_relevant as f64;
+    let evidence = (1.0 + positive).ln() - (1.0 + negative).ln();
+    (1.0 + prior_weight() * evidence).clamp(1.0 - MAX_PRIOR, 1.0 + MAX_PRIOR)
+}
+
+#[derive(Serialize)]
+pub struct SearchFeedback {
+    pub query: String,
+    pub chunk_id: String,
+    pub wallets: u64,
+    pub last_at: i64,
+}
+
+/// "not relevant" feedback grouped by query and chunk, most reported first.
+pub async fn search_feedback(db_path: &str, limit: usize) -> Result<Vec<SearchFeedback>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+
+    let feedback = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT query, chunk_id, COUNT(DISTINCT wallet), MAX(created_at) FROM bottle_reactions
+            WHERE reaction = 'not_relevant'
+            GROUP BY query, chunk_id ORDER BY COUNT(DISTINCT wallet) DESC, MAX(created_at) DESC LIMIT ?1"
+        )?;
+        let feedback = stmt.query_map([limit as i64], |row| {
+            Ok(SearchFeedback {
+                query: row.get(0)?,
+                chunk_id: row.get(1)?,
+                wallets: row.get::<_, i64>(2)? as u64,
+                last_at: row.get(3)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(feedback)
+    })
+    .await?;
+
+    Ok(feedback)
+}
@@ -0,0 +1,174 @@
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+
+use crate::db_schemas::validate_text;
+
+// Anonymous replies to drift bottles. A reply is attached to the first chunk of the bottle
+// (`title-0`), whichever chunk the replier found. Neither side ever sees the other's wallet,
+// `replier_wallet` is kept for rate limiting and moderation only.
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplyError {
+    #[error("Bottle not found")]
+    BottleNotFound,
+    #[error("You cannot reply to your own bottle")]
+    OwnBottle,
+    #[error("Only the author can read the replies")]
+    NotAuthor,
+}
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS bottle_replies (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                bottle_id TEXT NOT NULL,
+                author_wallet TEXT NOT NULL,
+                replier_wallet TEXT NOT NULL,
+                content TEXT NOT NULL,
+                read_at INTEGER,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE INDEX IF NOT EXISTS idx_bottle_replies_bottle ON bottle_replies(bottle_id, id);
+            CREATE INDEX IF NOT EXISTS idx_bottle_replies_author ON bottle_replies(author_wallet, read_at);"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct BottleReply {
+    pub id: i64,
+    pub bottle_id: String,
+    pub content: String,
+    pub read: bool,
+    pub created_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct UnreadReplies {
+    pub bottle_id: String,
+    pub title: String,
+    pub unread: u64,
+}
+
+/// The first chunk of the bottle a chunk belongs to, with its author.
+pub(crate) fn resolve_bottle(conn: &rusqlite::Connection, chunk_id: &str) -> rusqlite::Result<Option<(String, String)>> {
+    let chunk: Option<(String, String)> = conn.query_row(
+        "SELECT wallet, title FROM drift_bottles WHERE id = ?1",
+        [chunk_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()?;
+    let Some((wallet, title)) = chunk else {
+        return Ok(None);
+    };
+
+    let base_title = title.rsplit_once('-').map(|(base, _)| base).unwrap_or(&title);
+    let root_id: Option<String> = conn.query_row(
+        "SELECT id FROM drift_bottles WHERE wallet = ?1 AND title = ?2",
+        params![wallet, format!("{}-0", base_title)],
+        |row| row.get(0),
+    )
+    .optional()?;
+
+    Ok(Some((root_id.unwrap_or(chunk_id.to_string()), wallet)))
+}
+
+/// Store a reply, returns its id.
+pub async fn add_reply(db_path: &str, chunk_id: &str, replier_wallet: &str, content: &str) -> Result<i64, anyhow::Error> {
+    validate_text("content", content)?;
+
+    let conn = Connection::open(db_path).await?;
+    let (chunk_id, replier_wallet, content) = (chunk_id.to_string(), replier_wallet.to_string(), content.to_string());
+
+    let id = conn.call(move |conn| {
+        let Some((bottle_id, author_wallet)) = resolve_bottle(conn, &chunk_id)? else {
+            return Ok(Err(ReplyError::BottleNotFound));
+        };
+        if author_wallet == replier_wallet {
+            return Ok(Err(ReplyError::OwnBottle));
+        }
+        conn.execute(
+            "INSERT INTO bottle_replies (bottle_id, author_wallet, replier_wallet, content) VALUES (?1, ?2, ?3, ?4)",
+            params![bottle_id, author_wallet, replier_wallet, content],
+        )?;
+        Ok(Ok(conn.last_insert_rowid()))
+    })
+    .await??;
+
+    Ok(id)
+}
+
+/// Replies to one of the author's bottles, oldest first. Listing marks them read.
+pub async fn list_replies(db_path: &str, chunk_id: &str, author_wallet: &str) -> Result<Vec<BottleReply>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let (chunk_id, author_wallet) = (chunk_id.to_string(), author_wallet.to_string());
+
+    let replies = conn.call(move |conn| {
+        let Some((bottle_id, wallet)) = resolve_bottle(conn, &chunk_id)? else {
+            return Ok(Err(ReplyError::BottleNotFound));
+        };
+        if wallet != author_wallet {
+            return Ok(Err(ReplyError::NotAuthor));
+        }
+
+        let tx = conn.transaction()?;
+        let replies = {
+            let mut stmt = tx.prepare(
+                "SELECT id, bottle_id, content, read_at IS NOT NULL, created_at FROM bottle_replies WHERE bottle_id = ?1 ORDER BY id"
+            )?;
+            stmt.query_map([&bottle_id], |row| {
+                Ok(BottleReply {
+                    id: row.get(0)?,
+                    bottle_id: row.get(1)?,
+                    content: row.get(2)?,
+                    read: row.get(3)?,
+                    created_at: row.get(4)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?
+        };
+        tx.execute(
+            "UPDATE bottle_replies SET read_at = unixepoch() WHERE bottle_id = ?1 AND read_at IS NULL",
+            [&bottle_id],
+        )?;
+        tx.commit()?;
+        Ok(Ok(replies))
+    })
+    .await??;
+
+    Ok(replies)
+}
+
+/// Unread replies per bottle of the author.
+pub async fn unread_counts(db_path: &str, author_wallet: &str) -> Result<Vec<UnreadReplies>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let author_wallet = author_wallet.to_string();
+
+    let counts = conn.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT r.bottle_id, COALESCE(b.title, ''), COUNT(*)
+            FROM bottle_replies r LEFT JOIN drift_bottles b ON b.id = r.bottle_id
+            WHERE r.author_wallet = ?1 AND r.read_at IS NULL
+            GROUP BY r.bottle_id ORDER BY MAX(r.id) DESC"
+        )?;
+        let counts = stmt.query_map([author_wallet], |row| {
+            let title: String = row.get(1)?;
+            Ok(UnreadReplies {
+                bottle_id: row.get(0)?,
+                // show the bottle's title, not the chunk's
+                title: title.strip_suffix("-0").unwrap_or(&title).to_string(),
+                unread: row.get::<_, i64>(2)? as u64,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(counts)
+    })
+    .await?;
+
+    Ok(counts)
+}
@@ -1,11 +1,32 @@
 use serde::{Deserialize, Serialize};
 use crate::db_schemas::DocInfo;
+use crate::jobs::IngestJob;
+use crate::patron_memory::PatronMemory;
+use crate::personas::PersonaInfo;
+use crate::anchoring::Anchor;
+use crate::aptos_utils::EntryFunctionPayload;
+use crate::delivery::InboxItem;
+use crate::pickup::PickedBottle;
+use crate::moderation::{ReportOutcome, ReportReason};
+use crate::credits::LedgerEntry;
+use crate::membership::Tier;
+use crate::paywall::Charge;
+use crate::payments::{PaidAction, PaymentIntent};
+use crate::reactions::Reaction;
+use crate::replies::{BottleReply, UnreadReplies};
+use crate::wallet_auth::{Challenge, Session};
 
 // chat api
 #[derive(Deserialize)]
 pub struct ChatRequest {
     pub wallet: String,
     pub content: String,
+    // persona id from `/api/personas`, the default persona when omitted
+    pub persona: Option<String>,
+    // from the `X-Session-Id` header of an earlier reply, a new session is started when omitted
+    pub session_id: Option<i64>,
+    // a longer reply paid with credits, refunded if the model fails
+    pub long: Option<bool>,
 }
 
 #[derive(Serialize)]
@@ -14,12 +35,218 @@ pub struct ChatResponse {
     pub agent_response: String
 }
 
+// persona list api
+#[derive(Serialize)]
+pub struct PersonaListResponse {
+    pub status: String,
+    pub default_persona: String,
+    pub personas: Vec<PersonaInfo>
+}
+
+// wallet sign-in api
+#[derive(Deserialize)]
+pub struct AuthChallengeRequest {
+    pub wallet: String
+}
+
+#[derive(Serialize)]
+pub struct AuthChallengeResponse {
+    pub status: String,
+    pub challenge: Option<Challenge>
+}
+
+#[derive(Deserialize)]
+pub struct AuthSessionRequest {
+    pub wallet: String,
+    pub nonce: String,
+    // hex, as the wallet's `signMessage` returns them
+    pub public_key: String,
+    pub signature: String,
+    pub full_message: String
+}
+
+#[derive(Serialize)]
+pub struct AuthSessionResponse {
+    pub status: String,
+    pub session: Option<Session>
+}
+
+// patron memory api
+#[derive(Deserialize)]
+pub struct MemoryOptInRequest {
+    pub wallet: String,
+    pub enabled: bool
+}
+
+#[derive(Serialize)]
+pub struct MemoryListResponse {
+    pub status: String,
+    pub memories: Vec<PatronMemory>
+}
+
+#[derive(Serialize)]
+pub struct MemoryDeleteResponse {
+    pub status: String,
+    pub deleted: usize
+}
+
+// bottle reply api
+#[derive(Deserialize)]
+pub struct ReplyRequest {
+    pub wallet: String,
+    pub content: String
+}
+
+#[derive(Serialize)]
+pub struct ReplyResponse {
+    pub status: String,
+    pub reply_id: Option<i64>
+}
+
+#[derive(Serialize)]
+pub struct ReplyListResponse {
+    pub status: String,
+    pub replies: Vec<BottleReply>
+}
+
+#[derive(Serialize)]
+pub struct UnreadRepliesResponse {
+    pub status: String,
+    pub total: u64,
+    pub bottles: Vec<UnreadReplies>
+}
+
+// bottle reaction api
+#[derive(Deserialize)]
+pub struct ReactionRequest {
+    pub wallet: String,
+    pub reaction: Reaction,
+    // the message the bottle was retrieved for, required for `not_relevant`
+    pub query: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct ReactionResponse {
+    pub status: String,
+    // false when the same reaction was already recorded
+    pub recorded: bool
+}
+
+// report a bottle api
+#[derive(Deserialize)]
+pub struct ReportRequest {
+    pub wallet: String,
+    pub reason: ReportReason,
+    pub note: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct ReportResponse {
+    pub status: String,
+    pub outcome: Option<ReportOutcome>
+}
+
+// on-chain anchoring api
+#[derive(Serialize)]
+pub struct AnchorPayloadResponse {
+    pub status: String,
+    pub anchor: Option<Anchor>,
+    // sign and submit this, then post the tx hash back
+    pub payload: Option<EntryFunctionPayload>
+}
+
+#[derive(Deserialize)]
+pub struct AnchorRequest {
+    pub wallet: String,
+    pub tx_hash: String
+}
+
+#[derive(Serialize)]
+pub struct AnchorResponse {
+    pub status: String,
+    pub anchor: Option<Anchor>
+}
+
+// payment api
+#[derive(Deserialize)]
+pub struct PaymentIntentRequest {
+    pub wallet: String,
+    pub action: PaidAction,
+    pub bottle_id: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct PaymentIntentResponse {
+    pub status: String,
+    // sign and submit `intent.payload`, then send `intent.id` with the tx hash
+    pub intent: Option<PaymentIntent>
+}
+
+// membership api
+#[derive(Serialize)]
+pub struct MembershipResponse {
+    pub status: String,
+    pub tier: Tier
+}
+
+// credits api
+#[derive(Serialize)]
+pub struct CreditsResponse {
+    pub status: String,
+    pub balance_octas: i64
+}
+
+#[derive(Serialize)]
+pub struct LedgerResponse {
+    pub status: String,
+    pub entries: Vec<LedgerEntry>
+}
+
+#[derive(Deserialize)]
+pub struct DepositRequest {
+    pub wallet: String,
+    pub tx_hash: String
+}
+
+#[derive(Serialize)]
+pub struct DepositResponse {
+    pub status: String,
+    pub deposit: Option<LedgerEntry>,
+    pub balance_octas: Option<i64>
+}
+
+// pick up a random bottle api
+#[derive(Deserialize)]
+pub struct PickupRequest {
+    pub wallet: String,
+    pub emotion: Option<String>,
+    pub topic: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct PickupResponse {
+    pub status: String,
+    pub bottle: Option<PickedBottle>
+}
+
+// inbox api, bottles delivered by the scheduler
+#[derive(Serialize)]
+pub struct InboxResponse {
+    pub status: String,
+    pub items: Vec<InboxItem>
+}
+
 // store drift bottle api
 #[derive(Deserialize)]
 pub struct StoreDriftBottleRequest {
     pub wallet: String,
     pub title: String,
-    pub content: String
+    pub content: String,
+    // optional labels, used by `/api/bottles/pickup` filters
+    pub emotion: Option<String>,
+    pub topic: Option<String>,
+    // defaults to true, private bottles are never picked up
+    pub public: Option<bool>
 }
 
 #[derive(Serialize)]
@@ -27,17 +254,40 @@ pub struct GeneralReponse {
     pub status: String
 }
 
+// returned with 202, poll `/api/jobs/{job_id}` for the result
+#[derive(Serialize)]
+pub struct StoreDriftResponse {
+    pub status: String,
+    pub job_id: Option<i64>
+}
+
+// job status api
+#[derive(Serialize)]
+pub struct JobStatusResponse {
+    pub status: String,
+    pub job: Option<IngestJob>
+}
+
 // retrive drift bottle api
 #[derive(Deserialize)]
 pub struct RetriveRequest {
     pub wallet: String,
     pub content: String,
+    // retrieval settings come from this persona, the default persona when omitted
+    pub persona: Option<String>,
+    // pay this search with a payment intent for action "retrieve", otherwise the free
+    // searches of the day or credits are used
+    pub intent_id: Option<i64>,
+    pub tx_hash: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct RetriveResponse {
     pub status: String,
-    pub retrive_results: Vec<DocInfo>
+    pub retrive_results: Vec<DocInfo>,
+    pub prompt_version: String,
+    // what paid for this search, absent when it was not run
+    pub charge: Option<Charge>
 }
 
 // grade drift bottle score api
@@ -46,11 +296,20 @@ pub struct GradeBottleRequest {
     pub wallet: String,
     pub title: String,
     pub content: String,
-    pub tx_hash: String
+    // from `POST /api/payments/intent` with action "grade", settled by `tx_hash`, both required
+    pub intent_id: Option<i64>,
+    pub tx_hash: Option<String>
 }
 
 #[derive(Serialize)]
 pub struct GradeBottleResponse {
     pub status: String,
     pub score: i16   // 0-100
+}
+
+// returned with status 429, `retry_after` repeats the Retry-After header in seconds
+#[derive(Serialize)]
+pub struct RateLimitedResponse {
+    pub status: String,
+    pub retry_after: u64
 }
\ No newline at end of file
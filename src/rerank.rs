@@ -0,0 +1,225 @@
+use std::future::Future;
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent_impl::{prompt_hub, RetrivalAgent};
+use crate::agent_impl::metering::{self, CompletionMeta};
+use crate::db_schemas::{DriftBottle, VectorDBFromEnv};
+use crate::reactions::ReactionCounts;
+
+// Second retrieval stage: the vector search fetches `RERANK_CANDIDATES` stories,
+// a reranker scores them, `rerank` reorders them and the caller keeps the top k.
+//
+// RERANKER=none            keep the vector order (default)
+// RERANKER=llm             an LLM rates every candidate, `RERANK_MODEL_NAME`
+// RERANKER=cross_encoder   POST to `RERANK_URL`, a `/rerank` endpoint in the Cohere / SiliconFlow format
+
+const JUDGE_MAX_TOKENS: u32 = 128;
+// stories are cut to this many chars for the judge
+const JUDGE_STORY_CHARS: usize = 600;
+
+pub struct Candidate {
+    // best cosine score over all the queries that found it
+    pub vector_score: f64,
+    // reciprocal rank fusion over the queries, `None` for a single query
+    pub fused_score: Option<f64>,
+    // `None` until a reranker has scored it
+    pub rerank_score: Option<f64>,
+    // multiplier from reader reactions, see `reactions::prior`
+    pub prior: Option<f64>,
+    pub reactions: ReactionCounts,
+    pub bottle: DriftBottle,
+}
+
+impl Candidate {
+    pub fn score(&self) -> f64 {
+        self.rerank_score.or(self.fused_score).unwrap_or(self.vector_score) * self.prior.unwrap_or(1.0)
+    }
+}
+
+pub trait Reranker {
+    /// One relevance score per candidate, in the given order, higher is better.
+    fn score(&self, query: &str, candidates: &[Candidate]) -> impl Future<Output = Result<Vec<f64>, anyhow::Error>> + Send;
+}
+
+pub fn candidate_count() -> usize {
+    std::env::var("RERANK_CANDIDATES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(10)
+}
+
+/// Reorder `candidates` best first. A failing reranker only costs the reordering.
+pub async fn rerank<R: Reranker>(reranker: &R, query: &str, mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+    if !candidates.is_empty() {
+        match reranker.score(query, &candidates).await {
+            Ok(scores) => {
+                for (candidate, score) in candidates.iter_mut().zip(scores) {
+                    candidate.rerank_score = Some(score);
+                }
+            },
+            Err(e) => println!("Reranking failed, keeping the retrieval order: {}", e),
+        }
+    }
+    candidates.sort_by(|a, b| b.score().total_cmp(&a.score()));
+    candidates
+}
+
+/// One line per candidate, so thresholds can be tuned from the logs.
+pub fn log_scores(query: &str, candidates: &[Candidate]) {
+    println!("Rerank for {:?}:", query);
+    for candidate in candidates {
+        let mut line = format!("  {} vector {:.3}", candidate.bottle.id, candidate.vector_score);
+        if let Some(fused_score) = candidate.fused_score {
+            line.push_str(&format!(" fused {:.4}", fused_score));
+        }
+        if let Some(rerank_score) = candidate.rerank_score {
+            line.push_str(&format!(" rerank {:.3}", rerank_score));
+        }
+        if let Some(prior) = candidate.prior {
+            line.push_str(&format!(" prior {:.3}", prior));
+        }
+        println!("{}", line);
+    }
+}
+
+/// Rates candidates with a chat model through `RetrivalAgent::new_builder`, scores are 0..=1.
+pub struct LlmJudgeReranker {
+    pub model_name: String,
+    // whose request the judge call is metered against
+    pub wallet: Option<String>,
+}
+
+impl LlmJudgeReranker {
+    fn parse_scores(response: &str, expected: usize) -> Result<Vec<f64>, anyhow::Error> {
+        let start = response.find('[').ok_or_else(|| anyhow::anyhow!("No score list in judge response: {}", response))?;
+        let end = response.rfind(']').ok_or_else(|| anyhow::anyhow!("No score list in judge response: {}", response))?;
+        let scores: Vec<f64> = serde_json::from_str(&response[start..=end])?;
+        if scores.len() != expected {
+            anyhow::bail!("Judge rated {} of {} stories", scores.len(), expected);
+        }
+        Ok(scores.into_iter().map(|score| (score / 10.0).clamp(0.0, 1.0)).collect())
+    }
+}
+
+impl Reranker for LlmJudgeReranker {
+    async fn score(&self, query: &str, candidates: &[Candidate]) -> Result<Vec<f64>, anyhow::Error> {
+        let listed = candidates.iter()
+            .enumerate()
+            .map(|(i, c)| format!("{}. {}: {}", i + 1, c.bottle.title, c.bottle.content.chars().take(JUDGE_STORY_CHARS).collect::<String>()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let rendered = prompt_hub::render(prompt_hub::RERANK_JUDGE, &[
+            ("query", query.to_string()),
+            ("candidates", listed),
+        ]);
+
+        let db_path = VectorDBFromEnv::new().await?.db_path;
+        let agent = RetrivalAgent::new_builder(
+            rendered.text.clone(),
+            Some(JUDGE_MAX_TOKENS),
+            Some(0.0),
+            Some(self.model_name.clone())).await?
+            .build();
+        let meta = CompletionMeta::new(&db_path, self.wallet.as_deref(), &self.model_name, &rendered.text)
+            .with_prompt_version(&rendered.version);
+        let response = metering::metered_prompt(&agent, &meta, "Rate the stories.").await?;
+
+        Self::parse_scores(&response, candidates.len())
+    }
+}
+
+#[derive(Serialize)]
+struct CrossEncoderRequest<'a> {
+    model: &'a str,
+    query: &'a str,
+    documents: Vec<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct CrossEncoderResult {
+    index: usize,
+    relevance_score: f64,
+}
+
+#[derive(Deserialize)]
+struct CrossEncoderResponse {
+    results: Vec<CrossEncoderResult>,
+}
+
+/// Scores (query, story) pairs with a cross-encoder served over HTTP.
+pub struct CrossEncoderReranker {
+    pub url: String,
+    pub model_name: String,
+    pub api_key: Option<String>,
+}
+
+impl Reranker for CrossEncoderReranker {
+    async fn score(&self, query: &str, candidates: &[Candidate]) -> Result<Vec<f64>, anyhow::Error> {
+        let body = CrossEncoderRequest {
+            model: &self.model_name,
+            query,
+            documents: candidates.iter().map(|c| c.bottle.content.as_str()).collect(),
+        };
+        let mut request = reqwest::Client::new().post(&self.url).json(&body);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response: CrossEncoderResponse = request.send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // results may come back sorted by score, put them back in candidate order
+        let mut scores = vec![f64::MIN; candidates.len()];
+        for result in response.results {
+            if let Some(score) = scores.get_mut(result.index) {
+                *score = result.relevance_score;
+            }
+        }
+        Ok(scores)
+    }
+}
+
+/// The reranker picked by `RERANKER`.
+pub enum ConfiguredReranker {
+    Disabled,
+    Llm(LlmJudgeReranker),
+    CrossEncoder(CrossEncoderReranker),
+}
+
+impl ConfiguredReranker {
+    pub fn from_env(wallet: Option<&str>) -> Self {
+        let kind = std::env::var("RERANKER").unwrap_or_default();
+        match kind.as_str() {
+            "llm" => ConfiguredReranker::Llm(LlmJudgeReranker {
+                model_name: std::env::var("RERANK_MODEL_NAME").unwrap_or("Qwen/Qwen2.5-7B-Instruct".to_string()),
+                wallet: wallet.map(|w| w.to_string()),
+            }),
+            "cross_encoder" => {
+                let base_url = std::env::var("BASE_URL").unwrap_or_default();
+                ConfiguredReranker::CrossEncoder(CrossEncoderReranker {
+                    url: std::env::var("RERANK_URL").unwrap_or(format!("{}/rerank", base_url.trim_end_matches('/'))),
+                    model_name: std::env::var("RERANK_MODEL_NAME").unwrap_or("BAAI/bge-reranker-v2-m3".to_string()),
+                    api_key: std::env::var("RERANK_API_KEY").or(std::env::var("OPENAI_API_KEY")).ok(),
+                })
+            },
+            _ => ConfiguredReranker::Disabled,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, ConfiguredReranker::Disabled)
+    }
+}
+
+impl Reranker for ConfiguredReranker {
+    async fn score(&self, query: &str, candidates: &[Candidate]) -> Result<Vec<f64>, anyhow::Error> {
+        match self {
+            ConfiguredReranker::Disabled => Ok(candidates.iter().map(|c| c.score()).collect()),
+            ConfiguredReranker::Llm(reranker) => reranker.score(query, candidates).await,
+            ConfiguredReranker::CrossEncoder(reranker) => reranker.score(query, candidates).await,
+        }
+    }
+}
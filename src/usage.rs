@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tokio_rusqlite::Connection;
+
+// Ledger of every LLM and embedding call.
+// rig's prompt and streaming APIs do not hand back the provider's usage numbers, so token counts
+// come from `metering::count_tokens`, a BPE tokenizer. Exact for OpenAI models and within a few
+// percent for other providers. The daily quota is charged by `metering`, not here.
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS usage_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet TEXT,
+                kind TEXT NOT NULL,
+                model TEXT NOT NULL,
+                input_tokens INTEGER NOT NULL,
+                output_tokens INTEGER NOT NULL,
+                latency_ms INTEGER NOT NULL,
+                created_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );
+            CREATE INDEX IF NOT EXISTS idx_usage_events_wallet ON usage_events(wallet);
+            CREATE INDEX IF NOT EXISTS idx_usage_events_created_at ON usage_events(created_at);"
+        )?;
+
+        // added with prompt templates
+        let has_prompt_version: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('usage_events') WHERE name = 'prompt_version'",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_prompt_version == 0 {
+            conn.execute_batch("ALTER TABLE usage_events ADD COLUMN prompt_version TEXT")?;
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UsageKind {
+    Completion,
+    Embedding,
+}
+
+impl UsageKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UsageKind::Completion => "completion",
+            UsageKind::Embedding => "embedding",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UsageEvent {
+    pub wallet: Option<String>,
+    pub kind: UsageKind,
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub latency_ms: u64,
+    pub prompt_version: Option<String>,
+}
+
+/// Store one event.
+pub async fn record_event(db_path: &str, event: UsageEvent) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+
+    conn.call(move |conn| {
+        conn.execute(
+            "INSERT INTO usage_events (wallet, kind, model, input_tokens, output_tokens, latency_ms, prompt_version)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                event.wallet,
+                event.kind.as_str(),
+                event.model,
+                event.input_tokens as i64,
+                event.output_tokens as i64,
+                event.latency_ms as i64,
+                event.prompt_version
+            ],
+        )?;
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+// Prices in USD per million tokens, from MODEL_PRICES, e.g.
+// MODEL_PRICES='{"deepseek-ai/DeepSeek-V3": {"input": 0.27, "output": 1.10}}'
+#[derive(Deserialize, Clone, Copy, Default)]
+pub struct ModelPrice {
+    #[serde(default)]
+    pub input: f64,
+    #[serde(default)]
+    pub output: f64,
+}
+
+pub fn model_prices() -> HashMap<String, ModelPrice> {
+    std::env::var("MODEL_PRICES")
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Wallet,
+    Model,
+    Day,
+}
+
+#[derive(Serialize)]
+pub struct UsageSummary {
+    pub key: String,
+    pub calls: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub avg_latency_ms: u64,
+    pub cost_usd: f64,
+}
+
+/// Aggregate usage between two unix timestamps. Rows are grouped by (key, model) in SQL
+/// so every model is priced separately, then folded into one summary per key.
+pub async fn aggregate(
+    db_path: &str,
+    group_by: GroupBy,
+    wallet: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>
+) -> Result<Vec<UsageSummary>, anyhow::Error> {
+    let conn = Connection::open(db_path).await?;
+    let key_expr = match group_by {
+        GroupBy::Wallet => "COALESCE(wallet, '-')",
+        GroupBy::Model => "model",
+        GroupBy::Day => "date(created_at, 'unixepoch')",
+    };
+
+    let rows = conn.call(move |conn| {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {key_expr}, model, COUNT(*), SUM(input_tokens), SUM(output_tokens), SUM(latency_ms)
+            FROM usage_events
+            WHERE (?1 IS NULL OR wallet = ?1) AND (?2 IS NULL OR created_at >= ?2) AND (?3 IS NULL OR created_at < ?3)
+            GROUP BY 1, 2 ORDER BY 1"
+        ))?;
+        let rows = stmt.query_map(params![wallet, since, until], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)? as u64,
+                row.get::<_, i64>(3)? as u64,
+                row.get::<_, i64>(4)? as u64,
+                row.get::<_, i64>(5)? as u64,
+            ))
+        })?
+        .collect::<std::result::Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(rows)
+    })
+    .await?;
+
+    let prices = model_prices();
+    let mut summaries: Vec<UsageSummary> = Vec::new();
+    let mut total_latency: Vec<u64> = Vec::new();
+
+    for (key, model, calls, input_tokens, output_tokens, latency_ms) in rows {
+        let price = prices.get(&model).copied().unwrap_or_default();
+        let cost_usd = (input_tokens as f64 * price.input + output_tokens as f64 * price.output) / 1_000_000.0;
+
+        // rows arrive ordered by key
+        if summaries.last().map(|s| s.key != key).unwrap_or(true) {
+            summaries.push(UsageSummary {
+                key,
+                calls: 0,
+                input_tokens: 0,
+                output_tokens: 0,
+                avg_latency_ms: 0,
+                cost_usd: 0.0,
+            });
+            total_latency.push(0);
+        }
+        let summary = summaries.last_mut().unwrap();
+        summary.calls += calls;
+        summary.input_tokens += input_tokens;
+        summary.output_tokens += output_tokens;
+        summary.cost_usd += cost_usd;
+        *total_latency.last_mut().unwrap() += latency_ms;
+    }
+
+    for (summary, latency) in summaries.iter_mut().zip(total_latency) {
+        summary.avg_latency_ms = latency / summary.calls.max(1);
+    }
+
+    Ok(summaries)
+}
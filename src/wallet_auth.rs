@@ -0,0 +1,253 @@
+use std::str::FromStr;
+
+use actix_web::{HttpRequest, HttpResponse};
+use aptos_sdk::crypto::ed25519::{Ed25519PublicKey, Ed25519Signature};
+use aptos_sdk::crypto::Signature;
+use aptos_sdk::rest_client::Client as aptos_client;
+use aptos_sdk::types::account_address::AccountAddress;
+use aptos_sdk::types::transaction::authenticator::AuthenticationKey;
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use tokio_rusqlite::Connection;
+use url::Url;
+
+use crate::db_schemas::VectorDBFromEnv;
+use crate::request_model::GeneralReponse;
+
+// Proof that a caller holds a wallet's key, for endpoints that read or act on a wallet's private
+// state. A `?wallet=` or `"wallet"` field alone is only a claim.
+//
+// 1. POST /api/auth/challenge {wallet}       -> a message and a one-time nonce
+// 2. the wallet signs them, e.g. Petra's `signMessage({message, nonce})`
+// 3. POST /api/auth/session {wallet, nonce, public_key, signature, full_message}
+//    -> a session token, sent as `Authorization: Bearer <token>`
+//
+// The signature is checked over `full_message`, which has to contain the message and the nonce,
+// and the public key has to be the wallet's current authentication key (or derive the wallet's
+// address, for accounts not created on chain yet).
+//
+// AUTH_CHALLENGE_SECS="300"
+// AUTH_SESSION_SECS="86400"
+// AUTH_NODE_URL="https://fullnode.testnet.aptoslabs.com"
+
+const DEFAULT_NODE_URL: &str = "https://fullnode.testnet.aptoslabs.com";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Invalid wallet address")]
+    InvalidWallet,
+    #[error("Unknown, used or expired challenge, request a new one")]
+    InvalidChallenge,
+    #[error("The signed message does not contain the challenge")]
+    WrongMessage,
+    #[error("Invalid public key or signature")]
+    InvalidSignature,
+    #[error("The public key does not belong to this wallet")]
+    KeyMismatch,
+}
+
+pub async fn init_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.call(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS wallet_challenges (
+                nonce TEXT PRIMARY KEY,
+                wallet TEXT NOT NULL,
+                expires_at INTEGER NOT NULL,
+                used INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE TABLE IF NOT EXISTS wallet_sessions (
+                token TEXT PRIMARY KEY,
+                wallet TEXT NOT NULL,
+                expires_at INTEGER NOT NULL
+            );"
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+fn env_secs(name: &str, default: i64) -> i64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default)
+}
+
+fn node_client() -> aptos_client {
+    let node_url = std::env::var("AUTH_NODE_URL").unwrap_or(DEFAULT_NODE_URL.to_string());
+    aptos_client::new(Url::parse(&node_url).expect("Invalid URL"))
+}
+
+fn challenge_message(wallet: &str) -> String {
+    format!("Sign in to lisa as {}", wallet)
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim().trim_start_matches("0x");
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct Challenge {
+    pub message: String,
+    pub nonce: String,
+    pub expires_at: i64,
+}
+
+pub async fn challenge(db_path: &str, wallet: &str) -> Result<Challenge, anyhow::Error> {
+    let wallet = AccountAddress::from_str(wallet).map_err(|_| AuthError::InvalidWallet)?.to_hex_literal();
+    let conn = Connection::open(db_path).await?;
+    let ttl = env_secs("AUTH_CHALLENGE_SECS", 300);
+    let message = challenge_message(&wallet);
+
+    let (nonce, expires_at) = conn.call(move |conn| {
+        conn.execute("DELETE FROM wallet_challenges WHERE expires_at < unixepoch()", [])?;
+        let challenge = conn.query_row(
+            "INSERT INTO wallet_challenges (nonce, wallet, expires_at) VALUES (lower(hex(randomblob(16))), ?1, unixepoch() + ?2)
+            RETURNING nonce, expires_at",
+            params![wallet, ttl],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(challenge)
+    })
+    .await?;
+
+    Ok(Challenge { message, nonce, expires_at })
+}
+
+/// Whether `public_key` may sign for `wallet` right now.
+async fn key_controls(wallet: AccountAddress, public_key: &Ed25519PublicKey) -> Result<bool, anyhow::Error> {
+    let auth_key = AuthenticationKey::ed25519(public_key);
+    let account = node_client().get_account_resource(wallet, "0x1::account::Account").await?.into_inner();
+    let Some(account) = account else {
+        return Ok(auth_key.account_address() == wallet);
+    };
+    let current = account.data["authentication_key"].as_str()
+        .and_then(hex_decode)
+        .ok_or_else(|| anyhow::anyhow!("Unexpected 0x1::account::Account for {}: {}", wallet, account.data))?;
+    Ok(current == auth_key.to_vec())
+}
+
+#[derive(Serialize)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: i64,
+}
+
+/// Trade a signed challenge for a session token. Each challenge signs in once.
+pub async fn sign_in(db_path: &str, wallet: &str, nonce: &str, public_key: &str, signature: &str, full_message: &str) -> Result<Session, anyhow::Error> {
+    let address = AccountAddress::from_str(wallet).map_err(|_| AuthError::InvalidWallet)?;
+    let wallet = address.to_hex_literal();
+
+    let conn = Connection::open(db_path).await?;
+    let (lookup_nonce, lookup_wallet) = (nonce.to_string(), wallet.clone());
+    let pending = conn.call(move |conn| {
+        let pending = conn.query_row(
+            "SELECT 1 FROM wallet_challenges WHERE nonce = ?1 AND wallet = ?2 AND used = 0 AND expires_at >= unixepoch()",
+            params![lookup_nonce, lookup_wallet],
+            |_| Ok(()),
+        )
+        .optional()?;
+        Ok(pending.is_some())
+    })
+    .await?;
+    if !pending {
+        return Err(AuthError::InvalidChallenge.into());
+    }
+    if !full_message.contains(&challenge_message(&wallet)) || !full_message.contains(nonce) {
+        return Err(AuthError::WrongMessage.into());
+    }
+
+    let public_key = hex_decode(public_key)
+        .and_then(|bytes| Ed25519PublicKey::try_from(bytes.as_slice()).ok())
+        .ok_or(AuthError::InvalidSignature)?;
+    let signature = hex_decode(signature)
+        .and_then(|bytes| Ed25519Signature::try_from(bytes.as_slice()).ok())
+        .ok_or(AuthError::InvalidSignature)?;
+    signature.verify_arbitrary_msg(full_message.as_bytes(), &public_key)
+        .map_err(|_| AuthError::InvalidSignature)?;
+    if !key_controls(address, &public_key).await? {
+        return Err(AuthError::KeyMismatch.into());
+    }
+
+    let nonce = nonce.to_string();
+    let ttl = env_secs("AUTH_SESSION_SECS", 86400);
+    let session = conn.call(move |conn| {
+        let tx = conn.transaction()?;
+        // a challenge signed twice only signs in once
+        let used = tx.execute("UPDATE wallet_challenges SET used = 1 WHERE nonce = ?1 AND used = 0", [&nonce])?;
+        if used == 0 {
+            return Ok(Err(AuthError::InvalidChallenge));
+        }
+        tx.execute("DELETE FROM wallet_sessions WHERE expires_at < unixepoch()", [])?;
+        let session = tx.query_row(
+            "INSERT INTO wallet_sessions (token, wallet, expires_at) VALUES (lower(hex(randomblob(32))), ?1, unixepoch() + ?2)
+            RETURNING token, expires_at",
+            params![wallet, ttl],
+            |row| Ok(Session { token: row.get(0)?, expires_at: row.get(1)? }),
+        )?;
+        tx.commit()?;
+        Ok(Ok(session))
+    })
+    .await??;
+
+    Ok(session)
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+/// The wallet the request's session token was issued to, if any.
+pub async fn session_wallet(req: &HttpRequest) -> Result<Option<String>, anyhow::Error> {
+    let Some(token) = bearer_token(req) else {
+        return Ok(None);
+    };
+    let db_path = VectorDBFromEnv::new().await?.db_path;
+    let conn = Connection::open(db_path).await?;
+
+    let wallet = conn.call(move |conn| {
+        let wallet = conn.query_row(
+            "SELECT wallet FROM wallet_sessions WHERE token = ?1 AND expires_at >= unixepoch()",
+            [token],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+        Ok(wallet)
+    })
+    .await?;
+
+    Ok(wallet)
+}
+
+/// Whether the request carries a session for `wallet`. A failed lookup counts as no session.
+pub async fn is_owner(req: &HttpRequest, wallet: &str) -> bool {
+    let Ok(claimed) = AccountAddress::from_str(wallet) else {
+        return false;
+    };
+    match session_wallet(req).await {
+        Ok(Some(signed_in)) => AccountAddress::from_str(&signed_in).map(|a| a == claimed).unwrap_or(false),
+        Ok(None) => false,
+        Err(e) => {
+            println!("Wallet session lookup failed: {}", e);
+            false
+        },
+    }
+}
+
+pub fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized().json(GeneralReponse {
+        status: "Sign in with this wallet first, see /api/auth/challenge".to_string()
+    })
+}